@@ -0,0 +1,69 @@
+use socks5_frontend;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+
+/// Starts a server which accepts every request without connecting anywhere.
+fn start_server(auth_methods: Vec<socks5_frontend::AuthMethod>) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        auth_methods,
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    thread::spawn(move || {
+        for conn in server.flatten() {
+            let _ = conn.report_success();
+        }
+    });
+    return addr;
+}
+
+/// Performs a full handshake, sending each message only after the previous one was answered.
+fn handshake(addr: net::SocketAddr, auth: Option<&[u8]>) {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut buf = [0; 10];
+    match auth {
+        Some(auth) => {
+            stream.write_all(&[5, 1, 2]).unwrap();
+            stream.read_exact(&mut buf[..2]).unwrap();
+            stream.write_all(auth).unwrap();
+            stream.read_exact(&mut buf[..2]).unwrap();
+            assert_eq!(&buf[..2], &[1, 0]);
+        }
+        None => {
+            stream.write_all(&[5, 1, 0]).unwrap();
+            stream.read_exact(&mut buf[..2]).unwrap();
+        }
+    }
+    stream.write_all(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]).unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[1], 0x00);
+}
+
+fn bench_handshakes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handshake");
+    group.throughput(Throughput::Elements(1));
+
+    let addr = start_server(vec![socks5_frontend::AuthMethod::NoAuth]);
+    group.bench_function("no_auth", |b| b.iter(|| handshake(addr, None)));
+
+    let addr = start_server(vec![socks5_frontend::AuthMethod::UsernamePassword]);
+    let mut auth = vec![1, 7];
+    auth.extend_from_slice(b"randall");
+    auth.push(25);
+    auth.extend_from_slice(b"CorrectHorseBatteryStaple");
+    group.bench_function("username_password", |b| b.iter(|| handshake(addr, Some(&auth))));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_handshakes);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+use socks5_frontend;
+use socks5_frontend::obfs::{ObfsConfig, ObfsListener, ObfsTransform};
+use std::env;
+use std::process;
+use std::thread;
+
+fn usage() -> ! {
+    eprintln!("usage: obfs_transport client <listen address>");
+    eprintln!("       obfs_transport server <listen address> <forward address> <hex secret>");
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        ["client", listen] => run_client(listen),
+        ["server", listen, forward, secret] => run_server(listen, forward, secret),
+        _ => usage(),
+    }
+}
+
+// Accepts SOCKS5 clients which pass `secret=<hex secret>` as their credentials, and relays them obfuscated to the requested bridge
+fn run_client(listen: &str) {
+    let mut server = socks5_frontend::Server::init(
+        listen.parse().unwrap_or_else(|_| usage()),
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        None,
+        None,
+    )
+    .unwrap();
+    server.accept_pt_arguments();
+    for connection in server {
+        let conn = match connection {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Failed to handle client connection: {}", e);
+                continue;
+            }
+        };
+        let config = match conn.get_pt_arguments().map(ObfsConfig::from_pt_arguments) {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                eprintln!("Client passed invalid arguments: {}", e);
+                let _ = conn.report_connection_not_allowed();
+                continue;
+            }
+            None => continue,
+        };
+        thread::spawn(move || match conn.connect_keeping_early_data() {
+            Ok((conn, upstream)) => {
+                if let Err(e) = conn.relay_with(upstream, vec![Box::new(ObfsTransform::new(config))]) {
+                    eprintln!("Relaying failed: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to reach the bridge: {}", e),
+        });
+    }
+}
+
+// Accepts obfuscated connections and forwards them to `forward`
+fn run_server(listen: &str, forward: &str, secret: &str) {
+    let args = format!("secret={}", secret).parse().unwrap_or_else(|_| usage());
+    let config = ObfsConfig::from_pt_arguments(&args).unwrap_or_else(|e| {
+        eprintln!("Invalid secret: {}", e);
+        process::exit(2);
+    });
+    let listener = ObfsListener::bind(listen.parse().unwrap_or_else(|_| usage()), config).unwrap();
+    listener.forward(forward.parse().unwrap_or_else(|_| usage())).unwrap();
+    println!("Forwarding obfuscated connections on {} to {}", listen, forward);
+    loop {
+        thread::park();
+    }
+}
@@ -1,5 +1,5 @@
 use socks5_frontend;
-use std::io;
+use std::io::{self, Write};
 use std::net;
 use std::thread;
 use std::time;
@@ -58,17 +58,23 @@ fn main() {
                             .set_write_timeout(Some(time::Duration::from_secs(1)))
                             .unwrap();
                         // If successful, tell the client to expect data to start being relayed
-                        let ready_conn = conn.report_success().unwrap();
+                        let mut ready_conn = conn.report_success().unwrap();
 
                         // Start relaying data between the two streams
                         println!("Starting to proxy data!");
                         // Spawn 2 threads to continuously copy on both directions
                         // This is not very efficient, but good enough for a demo.
                         // We need to clone the stream here because both the reading and writing threads need a mutable handle
+                        let early_data = ready_conn.take_early_data();
                         let mut client_stream_1 = ready_conn.get_stream();
                         let mut client_stream_2 = client_stream_1.try_clone().unwrap();
                         let mut server_stream_1 = remote_stream;
                         let mut server_stream_2 = server_stream_1.try_clone().unwrap();
+                        // Data the client sent before our reply was already read along with its request,
+                        // so pass it on before copying the rest of the stream
+                        if server_stream_1.write_all(&early_data).is_err() {
+                            return;
+                        }
                         // Client => Server
                         let _ = thread::Builder::new()
                             .stack_size(10 * 1024)
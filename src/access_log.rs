@@ -0,0 +1,296 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::address::Address;
+use crate::observer::{ServerObserver, SessionRecord};
+
+const REDACTED: &str = "[redacted]";
+
+/// How each session is written to the access log.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LogFormat {
+    /// Space separated fields, in the order timestamp, client, user, command, destination, reply, duration, bytes in and bytes out.
+    /// Missing fields are written as `-`, fields containing spaces are quoted.
+    Plain,
+    /// One JSON object per line, missing fields are `null`.
+    Json,
+    /// `key=value` pairs, missing fields are left out.
+    Logfmt,
+}
+
+/// Writes a line per session to a log file, e.g. to keep track of who connected where.
+///
+/// Set it as the observer of a `Server` to have it log every session.
+/// Write errors are ignored, as they mustn't affect the sessions being logged.
+pub struct AccessLog {
+    format: LogFormat,
+    redact_destinations: bool,
+    output: Mutex<Output>,
+}
+
+enum Output {
+    File(LogFile),
+    Writer(Box<dyn Write + Send>),
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    // The size at which the file is rotated, and how many rotated files to keep
+    rotation: Option<(u64, usize)>,
+}
+
+impl AccessLog {
+    /// Opens the log file at `path`, which is created if it doesn't exist yet and appended to otherwise.
+    pub fn open<P: AsRef<Path>>(path: P, format: LogFormat) -> Result<AccessLog, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        return Ok(AccessLog::with_output(
+            Output::File(LogFile {
+                path: path,
+                file: file,
+                size: size,
+                rotation: None,
+            }),
+            format,
+        ));
+    }
+
+    /// Writes the log to `writer` instead of a file, e.g. to standard output.
+    pub fn from_writer<W: Write + Send + 'static>(writer: W, format: LogFormat) -> AccessLog {
+        return AccessLog::with_output(Output::Writer(Box::new(writer)), format);
+    }
+
+    fn with_output(output: Output, format: LogFormat) -> AccessLog {
+        return AccessLog {
+            format: format,
+            redact_destinations: false,
+            output: Mutex::new(output),
+        };
+    }
+
+    /// Rotates the log file once writing a line would grow it beyond `max_size` bytes.
+    ///
+    /// The current file is renamed by appending `.1`, whose predecessor becomes `.2` and so on.
+    /// At most `keep` rotated files are kept, the oldest one is removed.
+    /// This has no effect on logs written with `from_writer`.
+    pub fn set_rotation(&mut self, max_size: u64, keep: usize) {
+        if let Output::File(file) = self.output.get_mut().unwrap() {
+            file.rotation = Some((max_size, keep));
+        }
+    }
+
+    /// Logs `[redacted]` instead of the destination of every session,
+    /// so the log tells who used the server but not what they used it for.
+    pub fn set_redact_destinations(&mut self, redact: bool) {
+        self.redact_destinations = redact;
+    }
+
+    /// Formats `session` as a line of the log, including the trailing newline.
+    pub fn format(&self, session: &SessionRecord) -> String {
+        let timestamp = format_timestamp(session.started);
+        let client = session.client.to_string();
+        let destination = match &session.destination {
+            Some(_) if self.redact_destinations => Some(REDACTED.to_string()),
+            Some((addr, port)) => Some(format_destination(addr, *port)),
+            None => None,
+        };
+        let user = session.user.as_deref();
+        let command = session.command.map(|command| command.label());
+        let reply = session.reply.map(|reply| reply.label());
+
+        let mut line = String::new();
+        match self.format {
+            LogFormat::Plain => {
+                write!(
+                    line,
+                    "{} {} {} {} {} {} {:.3}s {} {}",
+                    timestamp,
+                    quoted(&client),
+                    quoted(user.unwrap_or("-")),
+                    command.unwrap_or("-"),
+                    quoted(destination.as_deref().unwrap_or("-")),
+                    reply.unwrap_or("-"),
+                    session.duration.as_secs_f64(),
+                    session.bytes_in,
+                    session.bytes_out,
+                )
+                .ignore();
+            }
+            LogFormat::Json => {
+                write!(
+                    line,
+                    "{{\"timestamp\":{},\"client\":{},\"user\":{},\"command\":{},\"destination\":{},\"reply\":{},\"duration_ms\":{},\"bytes_in\":{},\"bytes_out\":{}}}",
+                    json_string(Some(&timestamp)),
+                    json_string(Some(&client)),
+                    json_string(user),
+                    json_string(command),
+                    json_string(destination.as_deref()),
+                    json_string(reply),
+                    session.duration.as_millis(),
+                    session.bytes_in,
+                    session.bytes_out,
+                )
+                .ignore();
+            }
+            LogFormat::Logfmt => {
+                let fields = [
+                    ("ts", Some(timestamp.as_str())),
+                    ("client", Some(client.as_str())),
+                    ("user", user),
+                    ("command", command),
+                    ("destination", destination.as_deref()),
+                    ("reply", reply),
+                ];
+                for (key, value) in fields {
+                    if let Some(value) = value {
+                        write!(line, "{}={} ", key, quoted(value)).ignore();
+                    }
+                }
+                write!(
+                    line,
+                    "duration={:.3}s bytes_in={} bytes_out={}",
+                    session.duration.as_secs_f64(),
+                    session.bytes_in,
+                    session.bytes_out,
+                )
+                .ignore();
+            }
+        }
+        line.push('\n');
+        return line;
+    }
+
+    fn write_line(&self, line: &str) -> Result<(), io::Error> {
+        match &mut *self.output.lock().unwrap() {
+            Output::File(file) => return file.write_line(line),
+            Output::Writer(writer) => {
+                writer.write_all(line.as_bytes())?;
+                return writer.flush();
+            }
+        }
+    }
+}
+
+impl ServerObserver for AccessLog {
+    fn on_session_finished(&self, session: &SessionRecord) {
+        self.write_line(&self.format(session)).ignore();
+    }
+}
+
+impl LogFile {
+    fn write_line(&mut self, line: &str) -> Result<(), io::Error> {
+        if let Some((max_size, keep)) = self.rotation {
+            if self.size > 0 && self.size + line.len() as u64 > max_size {
+                self.rotate(keep)?;
+            }
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        return Ok(());
+    }
+
+    fn rotate(&mut self, keep: usize) -> Result<(), io::Error> {
+        if keep > 0 {
+            match fs::remove_file(self.rotated_path(keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for index in (1..keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = fs::OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        return Ok(());
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        return PathBuf::from(path);
+    }
+}
+
+pub(crate) fn format_destination(addr: &Address, port: u16) -> String {
+    match addr {
+        Address::V6(ip) => return format!("[{}]:{}", ip, port),
+        _ => return format!("{}:{}", addr, port),
+    }
+}
+
+// Formats `time` as an RFC 3339 timestamp in UTC, with millisecond precision
+pub(crate) fn format_timestamp(time: time::SystemTime) -> String {
+    let since_epoch = time.duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    );
+}
+
+// Converts days since the epoch to a (year, month, day) civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+pub(crate) fn json_string(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "null".to_string(),
+    };
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).ignore(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+// Quotes values which would otherwise be ambiguous, or could even forge log lines
+fn quoted(value: &str) -> String {
+    let needs_quotes = value.is_empty() || value.chars().any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if needs_quotes {
+        return json_string(Some(value));
+    }
+    return value.to_string();
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+use std::net;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::address::{Address, ClientAddress};
+use crate::network::IpNetwork;
+use crate::reply::ReplyType;
+
+/// What happens to a request matched by a `Rule`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Action {
+    Allow,
+    /// Refuse the request, replying `ConnectionNotAllowed`.
+    Deny,
+    /// Refuse the request with a specific failure reply code, e.g. to not reveal that a destination is filtered.
+    /// `Succeeded` is replaced with `GeneralSocksServerFailure`.
+    Reject(ReplyType),
+}
+
+/// Matches the destination a client requested.
+#[derive(PartialEq, Debug, Clone)]
+pub enum DestinationPattern {
+    /// IP destinations within a network. Domain names are not resolved, so they only match if they are IP addresses.
+    Network(IpNetwork),
+    /// Exactly this domain name, compared case-insensitively.
+    Domain(String),
+    /// Any subdomain of this domain name, but not the domain itself. Written as `*.example.com`.
+    Subdomains(String),
+}
+
+impl DestinationPattern {
+    pub fn matches(&self, addr: &Address) -> bool {
+        match (self, addr) {
+            (DestinationPattern::Network(net), Address::V4(ip)) => return net.contains((*ip).into()),
+            (DestinationPattern::Network(net), Address::V6(ip)) => return net.contains((*ip).into()),
+            // The dialer connects to IP addresses sent as domain names directly
+            (DestinationPattern::Network(net), Address::DomainName(name)) => match name.parse::<net::IpAddr>() {
+                Ok(ip) => return net.contains(ip),
+                Err(_) => return false,
+            },
+            (DestinationPattern::Domain(domain), Address::DomainName(name)) => {
+                return normalize_domain(name) == *domain;
+            }
+            (DestinationPattern::Subdomains(domain), Address::DomainName(name)) => {
+                let name = normalize_domain(name);
+                return name.len() > domain.len()
+                    && name.ends_with(domain.as_str())
+                    && name.as_bytes()[name.len() - domain.len() - 1] == b'.';
+            }
+            _ => return false,
+        }
+    }
+}
+
+impl FromStr for DestinationPattern {
+    type Err = String;
+
+    /// Parses a network in CIDR notation, a bare IP address, `*.domain` or a domain name.
+    fn from_str(s: &str) -> Result<DestinationPattern, String> {
+        if let Ok(net) = s.parse::<IpNetwork>() {
+            return Ok(DestinationPattern::Network(net));
+        }
+        if s.contains('/') {
+            return Err(format!("'{}' is not a valid IP network", s));
+        }
+        let (pattern, domain): (fn(String) -> DestinationPattern, &str) = match s.strip_prefix("*.") {
+            Some(domain) => (DestinationPattern::Subdomains, domain),
+            None => (DestinationPattern::Domain, s),
+        };
+        let domain = normalize_domain(domain);
+        if domain.is_empty() || domain.contains('*') {
+            return Err(format!("'{}' is not a valid domain pattern", s));
+        }
+        return Ok(pattern(domain));
+    }
+}
+
+impl fmt::Display for DestinationPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DestinationPattern::Network(net) => write!(f, "{}", net),
+            DestinationPattern::Domain(domain) => write!(f, "{}", domain),
+            DestinationPattern::Subdomains(domain) => write!(f, "*.{}", domain),
+        }
+    }
+}
+
+fn normalize_domain(name: &str) -> String {
+    return name.trim_end_matches('.').to_ascii_lowercase();
+}
+
+/// A rule matches a request if every one of its conditions does.
+/// Conditions which are empty match anything.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Rule {
+    pub action: Action,
+    /// The requested destination must match one of these.
+    pub destinations: Vec<DestinationPattern>,
+    /// The requested port must lie within one of these ranges.
+    pub ports: Vec<RangeInclusive<u16>>,
+    /// The client must have authenticated as one of these users.
+    pub users: Vec<String>,
+    /// The client's IP address must lie within one of these networks.
+    /// Clients connected through Unix domain sockets never match a rule with source networks.
+    pub sources: Vec<IpNetwork>,
+}
+
+impl Rule {
+    /// A rule allowing every request, to be narrowed down with struct update syntax.
+    pub fn allow() -> Rule {
+        return Rule::new(Action::Allow);
+    }
+
+    /// A rule denying every request, to be narrowed down with struct update syntax.
+    pub fn deny() -> Rule {
+        return Rule::new(Action::Deny);
+    }
+
+    fn new(action: Action) -> Rule {
+        return Rule {
+            action: action,
+            destinations: Vec::new(),
+            ports: Vec::new(),
+            users: Vec::new(),
+            sources: Vec::new(),
+        };
+    }
+
+    pub fn matches(&self, req: &AccessRequest) -> bool {
+        if !self.destinations.is_empty() && !self.destinations.iter().any(|d| d.matches(req.destination)) {
+            return false;
+        }
+        if !self.ports.is_empty() && !self.ports.iter().any(|range| range.contains(&req.port)) {
+            return false;
+        }
+        if !self.users.is_empty() {
+            match req.user {
+                Some(user) if self.users.iter().any(|u| u == user) => (),
+                _ => return false,
+            }
+        }
+        if !self.sources.is_empty() {
+            match req.client {
+                ClientAddress::Tcp(addr) if self.sources.iter().any(|net| net.contains(addr.ip())) => (),
+                _ => return false,
+            }
+        }
+        return true;
+    }
+}
+
+/// The properties of a request that rules are evaluated against.
+pub struct AccessRequest<'a> {
+    pub destination: &'a Address,
+    pub port: u16,
+    pub user: Option<&'a str>,
+    pub client: &'a ClientAddress,
+}
+
+/// An ordered list of rules. The first rule matching a request decides what happens to it,
+/// and requests no rule matches get the default action.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AccessControlList {
+    pub rules: Vec<Rule>,
+    pub default_action: Action,
+}
+
+impl AccessControlList {
+    pub fn new(rules: Vec<Rule>, default_action: Action) -> AccessControlList {
+        return AccessControlList {
+            rules: rules,
+            default_action: default_action,
+        };
+    }
+
+    pub fn evaluate(&self, req: &AccessRequest) -> Decision {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.matches(req) {
+                return Decision {
+                    action: rule.action,
+                    rule: Some(index),
+                };
+            }
+        }
+        return Decision {
+            action: self.default_action,
+            rule: None,
+        };
+    }
+}
+
+/// The outcome of evaluating an `AccessControlList`.
+/// Pass it to `apply_decision` on the connection to reply to refused requests automatically.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Decision {
+    pub action: Action,
+    /// The index of the rule which matched, or `None` if the default action applied.
+    pub rule: Option<usize>,
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        return self.action == Action::Allow;
+    }
+
+    /// The reply code to send to the client, if the request is refused.
+    pub fn reply(&self) -> Option<ReplyType> {
+        match self.action {
+            Action::Allow => return None,
+            Action::Deny => return Some(ReplyType::ConnectionNotAllowed),
+            Action::Reject(ReplyType::Succeeded) => return Some(ReplyType::GeneralSocksServerFailure),
+            Action::Reject(reply) => return Some(reply),
+        }
+    }
+}
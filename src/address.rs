@@ -1,13 +1,87 @@
+use std::io;
+use std::io::Read;
 use std::net;
 use std::fmt;
 
-#[derive(PartialEq, Clone)]
+const ATYP_V4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_V6: u8 = 0x04;
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Address {
     V4(net::Ipv4Addr),
     DomainName(String),
     V6(net::Ipv6Addr),
 }
 
+impl Address {
+    /// Appends the address type, address and port in the format used by requests, replies and UDP datagrams.
+    pub(crate) fn encode(&self, port: u16, buf: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Address::V4(ip) => {
+                buf.push(ATYP_V4);
+                buf.extend_from_slice(&ip.octets());
+            }
+            Address::V6(ip) => {
+                buf.push(ATYP_V6);
+                buf.extend_from_slice(&ip.octets());
+            }
+            Address::DomainName(name) => {
+                if name.is_empty() || name.len() > 255 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("domain name '{}' can't be sent over SOCKS5", name),
+                    ));
+                }
+                buf.push(ATYP_DOMAIN);
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+            }
+        }
+        buf.extend_from_slice(&port.to_be_bytes());
+        return Ok(());
+    }
+
+    /// Reads an address and port encoded like `encode` does.
+    /// Returns the unknown address type as the error if there is one.
+    pub(crate) fn decode<R: Read>(r: &mut R) -> io::Result<Result<(Address, u16), u8>> {
+        let mut atyp_buf = [0; 1];
+        r.read_exact(&mut atyp_buf)?;
+        let addr = match atyp_buf[0] {
+            ATYP_V4 => {
+                let mut buf = [0; 4];
+                r.read_exact(&mut buf)?;
+                Address::V4(buf.into())
+            }
+            ATYP_V6 => {
+                let mut buf = [0; 16];
+                r.read_exact(&mut buf)?;
+                Address::V6(buf.into())
+            }
+            ATYP_DOMAIN => {
+                let mut len_buf = [0; 1];
+                r.read_exact(&mut len_buf)?;
+                let mut buf = vec![0; len_buf[0].into()];
+                r.read_exact(&mut buf)?;
+                Address::DomainName(String::from_utf8_lossy(&buf).to_string())
+            }
+            atyp => return Ok(Err(atyp)),
+        };
+        let mut port_buf = [0; 2];
+        r.read_exact(&mut port_buf)?;
+        return Ok(Ok((addr, u16::from_be_bytes(port_buf))));
+    }
+}
+
+impl From<net::IpAddr> for Address {
+    fn from(ip: net::IpAddr) -> Address {
+        match ip {
+            net::IpAddr::V4(ip) => return Address::V4(ip),
+            net::IpAddr::V6(ip) => return Address::V6(ip),
+        }
+    }
+}
+
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -17,3 +91,32 @@ impl fmt::Display for Address {
         }
     }
 }
+
+/// The credentials of the process on the other end of a Unix domain socket, as reported by the kernel.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Not every platform reports the PID of the peer.
+    pub pid: Option<i32>,
+}
+
+/// Identifies the client on the other end of a connection.
+/// TCP clients are identified by their socket address, Unix domain socket clients by their credentials.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ClientAddress {
+    Tcp(net::SocketAddr),
+    Unix(PeerCredentials),
+}
+
+impl fmt::Display for ClientAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientAddress::Tcp(addr) => write!(f, "{}", addr),
+            ClientAddress::Unix(creds) => match creds.pid {
+                Some(pid) => write!(f, "uid={} gid={} pid={}", creds.uid, creds.gid, pid),
+                None => write!(f, "uid={} gid={}", creds.uid, creds.gid),
+            },
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::access_log::{format_destination, format_timestamp, json_string};
+use crate::http;
+use crate::listener::{AcceptBackoff, Listener};
+use crate::percent;
+use crate::registry::{Registry, SessionInfo};
+use crate::stream::ClientStream;
+
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+// Web pages can't send this to another origin without a CORS preflight, so it proves a request wasn't forged by one
+const ADMIN_HEADER: &str = "X-Socks5-Admin";
+
+/// Serves an HTTP interface to list and close the live connections of a `Server`, and to pause accepting clients.
+///
+/// Anyone who can reach it can disconnect every client, so it should only be served on a loopback address,
+/// or on a Unix domain socket whose permissions restrict who may connect:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use socks5_frontend::admin::Admin;
+/// # let server = socks5_frontend::Server::init("127.0.0.1:1080".parse().unwrap(), None, vec![socks5_frontend::AuthMethod::NoAuth], None, None).unwrap();
+/// let admin = Arc::new(Admin::new(server.registry()));
+/// admin.serve(socks5_frontend::Listener::bind_tcp("127.0.0.1:9151".parse().unwrap()).unwrap()).unwrap();
+/// ```
+///
+/// Even on a loopback address it's reachable from any web page the operator visits,
+/// so requests other than `GET` must carry an `X-Socks5-Admin` header (with any value).
+/// Browsers don't let pages send that header to other origins without the interface's consent, which it never gives.
+///
+/// All endpoints respond with JSON:
+///
+/// - `GET /sessions` lists the live connections, with the fields of `SessionInfo`
+/// - `DELETE /sessions/<id>` closes a connection
+/// - `DELETE /users/<user>/sessions` closes all connections of a user, whose name is percent-encoded
+/// - `GET /status` tells whether accepting clients is paused, and how many connections are live
+/// - `POST /pause` and `POST /resume` pause and resume accepting clients
+pub struct Admin {
+    registry: Registry,
+}
+
+impl Admin {
+    pub fn new(registry: Registry) -> Admin {
+        return Admin { registry: registry };
+    }
+
+    /// Serves the interface on `listener` from a background thread.
+    /// Requests are answered one at a time.
+    pub fn serve(self: Arc<Self>, listener: Listener) -> Result<(), io::Error> {
+        let mut backoff = AcceptBackoff::new();
+        thread::Builder::new().name("socks5-admin".to_string()).spawn(move || loop {
+            // A failed request is of no concern to the server
+            if let Some(stream) = backoff.check(listener.socket.accept()) {
+                self.answer(stream).ignore();
+            }
+        })?;
+        return Ok(());
+    }
+
+    fn answer(&self, mut stream: ClientStream) -> Result<(), io::Error> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let request = http::read_request_head(&mut stream)?;
+        let mut parts = request.request_line().split(' ');
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some(method), Some(_)) if method != "GET" && request.header(ADMIN_HEADER).is_none() => {
+                ("403 Forbidden", error("requests other than GET need an X-Socks5-Admin header"))
+            }
+            (Some(method), Some(path)) => self.route(method, path),
+            _ => ("400 Bad Request", error("malformed request")),
+        };
+        return http::write_response(&mut stream, status, "application/json", &body);
+    }
+
+    fn route(&self, method: &str, path: &str) -> (&'static str, String) {
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["sessions"]) => return ("200 OK", self.sessions()),
+            ("DELETE", ["sessions", id]) => {
+                let killed = match id.parse() {
+                    Ok(id) => self.registry.kill(id),
+                    Err(_) => false,
+                };
+                if killed {
+                    return ("200 OK", "{\"killed\":1}".to_string());
+                }
+                return ("404 Not Found", error("no such session"));
+            }
+            ("DELETE", ["users", user, "sessions"]) => match percent::decode(user) {
+                Some(user) => return ("200 OK", format!("{{\"killed\":{}}}", self.registry.kill_user(&user))),
+                None => return ("400 Bad Request", error("malformed user name")),
+            },
+            ("GET", ["status"]) => return ("200 OK", self.status()),
+            ("POST", ["pause"]) => {
+                self.registry.shutdown_handle().pause();
+                return ("200 OK", self.status());
+            }
+            ("POST", ["resume"]) => {
+                self.registry.shutdown_handle().resume();
+                return ("200 OK", self.status());
+            }
+            (_, ["sessions"]) | (_, ["sessions", _]) | (_, ["users", _, "sessions"]) | (_, ["status"]) | (_, ["pause"]) | (_, ["resume"]) => {
+                return ("405 Method Not Allowed", error("method not allowed"));
+            }
+            _ => return ("404 Not Found", error("not found")),
+        }
+    }
+
+    fn sessions(&self) -> String {
+        let sessions: Vec<String> = self.registry.sessions().iter().map(session_json).collect();
+        return format!("[{}]", sessions.join(","));
+    }
+
+    fn status(&self) -> String {
+        return format!(
+            "{{\"paused\":{},\"sessions\":{}}}",
+            self.registry.shutdown_handle().is_paused(),
+            self.registry.shutdown_handle().active_connections(),
+        );
+    }
+}
+
+fn session_json(session: &SessionInfo) -> String {
+    let destination = session.destination.as_ref().map(|(addr, port)| format_destination(addr, *port));
+    let mut out = String::new();
+    write!(
+        out,
+        "{{\"id\":{},\"client\":{},\"user\":{},\"destination\":{},\"started\":{},\"bytes_in\":{},\"bytes_out\":{}}}",
+        session.id,
+        json_string(Some(&session.client.to_string())),
+        json_string(session.user.as_deref()),
+        json_string(destination.as_deref()),
+        json_string(Some(&format_timestamp(session.started))),
+        session.bytes_in,
+        session.bytes_out,
+    )
+    .ignore();
+    return out;
+}
+
+fn error(message: &str) -> String {
+    return format!("{{\"error\":{}}}", json_string(Some(message)));
+}
@@ -24,67 +24,92 @@ impl AuthMethod {
 }
 
 pub(crate) mod user_pass_auth {
+    use crate::address::ClientAddress;
+    use crate::observer::ServerObserver;
+    use crate::pt::PtArguments;
     use crate::socks_error::SOCKSError;
+    use crate::stream::ClientStream;
+    use crate::trace;
     use std::io::{Read, Write};
     use std::net;
 
-    pub(crate) fn negotiate_stream(
-        correct_username: String,
-        correct_password: String,
-        stream: &mut net::TcpStream,
-    ) -> Option<SOCKSError> {
-        // We expect the client to use version 1 of the subnegotiation protocol
-        let mut ver_buf: [u8; 1] = [0];
-        stream.read_exact(&mut ver_buf).unwrap();
-        if ver_buf[0] != 1 {
-            return Some(SOCKSError::UnknownAuthMethodSubnegotiationVersionError(
-                stream.peer_addr().unwrap(),
-                ver_buf[0],
+    /// What the client sent in place of credentials.
+    pub(crate) enum Authenticated {
+        User(String),
+        PtArguments(PtArguments),
+    }
+
+    /// Reads the client's credentials from `reader` and tells the client on `stream` whether they're correct.
+    /// If `correct` is `None`, the credentials are parsed as pluggable transport arguments instead, which are never logged.
+    pub(crate) fn negotiate_stream<R: Read>(
+        correct: Option<(&str, &str)>,
+        reader: &mut R,
+        stream: &mut ClientStream,
+        client_addr: &ClientAddress,
+        observer: &dyn ServerObserver,
+    ) -> Result<Authenticated, SOCKSError> {
+        let span = trace::span!("auth");
+        let _entered = span.enter();
+        // We expect the client to use version 1 of the subnegotiation protocol, which is followed by the length of the username
+        let mut header_buf: [u8; 2] = [0; 2];
+        reader.read_exact(&mut header_buf)?;
+        if header_buf[0] != 1 {
+            return Err(SOCKSError::UnknownAuthMethodSubnegotiationVersionError(
+                client_addr.clone(),
+                header_buf[0],
                 1,
             ));
         }
 
-        // Read the length of the username that follows
-        let mut username_len_buf: [u8; 1] = [0];
-        stream.read_exact(&mut username_len_buf).unwrap();
-
-        // Read the username
-        let username: String;
-        if username_len_buf[0] > 0 {
-            let mut username_buf = vec![0; username_len_buf[0].into()];
-            stream.read_exact(&mut username_buf).unwrap();
-            username = String::from_utf8_lossy(&username_buf).to_string();
-        } else {
-            username = "".to_string();
-        }
-
-        // Read the length of the password that follows
-        let mut password_len_buf: [u8; 1] = [0];
-        stream.read_exact(&mut password_len_buf).unwrap();
+        // Read the username and the length of the password that follows it
+        let mut username_buf = vec![0; usize::from(header_buf[1]) + 1];
+        reader.read_exact(&mut username_buf)?;
+        let password_len = username_buf.pop().unwrap();
+        let username = String::from_utf8_lossy(&username_buf).to_string();
 
         // Read the password
-        let password: String;
-        if password_len_buf[0] > 0 {
-            let mut password_buf = vec![0; password_len_buf[0].into()];
-            stream.read_exact(&mut password_buf).unwrap();
-            password = String::from_utf8_lossy(&password_buf).to_string();
-        } else {
-            password = "".to_string();
-        }
+        let mut password_buf = vec![0; password_len.into()];
+        reader.read_exact(&mut password_buf)?;
+        let password = String::from_utf8_lossy(&password_buf).to_string();
 
         // Check for correctness
-        if username == correct_username && password == correct_password {
-            let creds_correct_buf: [u8; 2] = [1, 0];
-            stream.write(&creds_correct_buf).unwrap();
-            return None;
-        } else {
-            let creds_incorrect_buf: [u8; 2] = [1, 1];
-            stream.write(&creds_incorrect_buf).unwrap();
-            // Close the connection, as mandated by the spec
-            stream.shutdown(net::Shutdown::Both).unwrap();
-            return Some(SOCKSError::WrongCredentialsError(
-                stream.peer_addr().unwrap(),
-            ));
+        let authenticated = match correct {
+            Some((correct_username, correct_password)) => {
+                if username == correct_username && password == correct_password {
+                    trace::debug!(user = %username, "client authenticated");
+                    observer.on_auth_succeeded(client_addr, &username);
+                    Some(Authenticated::User(username))
+                } else {
+                    // Users sometimes type their password into the username field, so neither is recorded
+                    trace::debug!(username_len = username.len(), "client supplied invalid credentials");
+                    None
+                }
+            }
+            None => match PtArguments::from_credentials(&username, &password) {
+                Ok(args) => {
+                    trace::debug!("client supplied transport arguments");
+                    Some(Authenticated::PtArguments(args))
+                }
+                Err(_) => {
+                    trace::debug!("client supplied malformed transport arguments");
+                    None
+                }
+            },
+        };
+        match authenticated {
+            Some(authenticated) => {
+                let creds_correct_buf: [u8; 2] = [1, 0];
+                stream.write_all(&creds_correct_buf)?;
+                return Ok(authenticated);
+            }
+            None => {
+                observer.on_auth_failed(client_addr);
+                let creds_incorrect_buf: [u8; 2] = [1, 1];
+                stream.write_all(&creds_incorrect_buf)?;
+                // Close the connection, as mandated by the spec
+                stream.shutdown(net::Shutdown::Both)?;
+                return Err(SOCKSError::WrongCredentialsError(client_addr.clone()));
+            }
         }
     }
 }
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::address::Address;
+use crate::auth::AuthMethod;
+use crate::command::Command;
+use crate::reply::ReplyType;
+
+/// A username and password to authenticate to a proxy with.
+#[derive(PartialEq, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Credentials {
+        return Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+    }
+}
+
+impl fmt::Debug for Credentials {
+    // Don't leak the password into logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+/// Returned when talking to a SOCKS5 server fails.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server spoke a protocol version other than 5.
+    ProtocolVersionError(u8),
+    /// The server accepted none of the offered authentication methods.
+    NoAcceptableAuthMethodsError,
+    /// The server chose an authentication method that wasn't offered.
+    UnexpectedAuthMethodError(AuthMethod),
+    WrongCredentialsError,
+    /// The server refused the request with this reply code.
+    RequestFailedError(ReplyType),
+    UnknownReplyError(u8),
+    UnknownAddressTypeError(u8),
+    StreamIOError(io::Error),
+}
+
+impl ClientError {
+    /// The reply code to pass on to a client whose request was relayed to the failing server.
+    pub fn reply(&self) -> ReplyType {
+        match self {
+            ClientError::RequestFailedError(ReplyType::Succeeded) => return ReplyType::GeneralSocksServerFailure,
+            ClientError::RequestFailedError(rep) => return *rep,
+            ClientError::StreamIOError(e) if e.kind() == io::ErrorKind::TimedOut => return ReplyType::TTLExpired,
+            ClientError::StreamIOError(e) if e.kind() == io::ErrorKind::WouldBlock => return ReplyType::TTLExpired,
+            _ => return ReplyType::GeneralSocksServerFailure,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::ProtocolVersionError(ver) => write!(f, "Proxy speaks unsupported SOCKS version {}", ver),
+            ClientError::NoAcceptableAuthMethodsError => write!(f, "Proxy accepts none of the offered authentication methods"),
+            ClientError::UnexpectedAuthMethodError(method) => {
+                write!(f, "Proxy chose authentication method {:?}, which wasn't offered", method)
+            }
+            ClientError::WrongCredentialsError => write!(f, "Proxy rejected the credentials"),
+            ClientError::RequestFailedError(rep) => write!(f, "Proxy refused the request with reply {:?}", rep),
+            ClientError::UnknownReplyError(rep) => write!(f, "Proxy sent unknown reply code {}", rep),
+            ClientError::UnknownAddressTypeError(atyp) => write!(f, "Proxy sent unknown address type {}", atyp),
+            ClientError::StreamIOError(e) => write!(f, "I/O error while talking to proxy: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        return ClientError::StreamIOError(e);
+    }
+}
+
+/// A SOCKS5 client, connected and authenticated to a server and ready to send a request.
+///
+/// A `Dialer` uses it to relay connections through a chain of `UpstreamProxy`s,
+/// but it works over any stream on its own as well.
+pub struct Client<S: Read + Write> {
+    stream: S,
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Negotiates authentication over `stream`.
+    /// No authentication is offered, as well as username/password authentication if `credentials` are given.
+    pub fn handshake(mut stream: S, credentials: Option<&Credentials>) -> Result<Client<S>, ClientError> {
+        let mut methods = vec![AuthMethod::NoAuth];
+        if credentials.is_some() {
+            methods.push(AuthMethod::UsernamePassword);
+        }
+        let mut msg = vec![5, methods.len() as u8];
+        msg.extend(methods.iter().map(|method| method.to_byte()));
+        stream.write_all(&msg)?;
+
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf)?;
+        if buf[0] != 5 {
+            return Err(ClientError::ProtocolVersionError(buf[0]));
+        }
+        if buf[1] == 0xFF {
+            return Err(ClientError::NoAcceptableAuthMethodsError);
+        }
+        match (AuthMethod::from_byte(buf[1]), credentials) {
+            (AuthMethod::NoAuth, _) => (),
+            (AuthMethod::UsernamePassword, Some(creds)) => authenticate(&mut stream, creds)?,
+            (method, _) => return Err(ClientError::UnexpectedAuthMethodError(method)),
+        }
+        return Ok(Client { stream: stream });
+    }
+
+    /// Asks the server to connect to `addr`:`port`.
+    /// Returns the stream, which now relays data to the destination, and the address the server connected from.
+    pub fn connect(mut self, addr: &Address, port: u16) -> Result<(S, (Address, u16)), ClientError> {
+        let bound = self.request(Command::Connect, addr, port)?;
+        return Ok((self.stream, bound));
+    }
+
+    /// Asks the server to listen for a connection from `addr`:`port`.
+    /// The server replies with the address it listens on, which has to be passed on to the peer somehow.
+    pub fn bind(mut self, addr: &Address, port: u16) -> Result<PendingBind<S>, ClientError> {
+        let bound = self.request(Command::Bind, addr, port)?;
+        return Ok(PendingBind {
+            stream: self.stream,
+            bound: bound,
+        });
+    }
+
+    /// Asks the server to relay UDP datagrams, which will be sent from `addr`:`port` (which may be all zeros if unknown).
+    /// The association lasts as long as the returned control stream is open.
+    pub fn udp_associate(mut self, addr: &Address, port: u16) -> Result<UdpAssociation<S>, ClientError> {
+        let relay = self.request(Command::UDPAssociate, addr, port)?;
+        return Ok(UdpAssociation {
+            control: self.stream,
+            relay: relay,
+        });
+    }
+
+    fn request(&mut self, cmd: Command, addr: &Address, port: u16) -> Result<(Address, u16), ClientError> {
+        let mut msg = vec![5, cmd.to_byte(), 0];
+        addr.encode(port, &mut msg)?;
+        self.stream.write_all(&msg)?;
+        return read_reply(&mut self.stream);
+    }
+}
+
+fn authenticate<S: Read + Write>(stream: &mut S, creds: &Credentials) -> Result<(), ClientError> {
+    if creds.username.len() > 255 || creds.password.len() > 255 {
+        return Err(ClientError::StreamIOError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "username and password may be at most 255 bytes long",
+        )));
+    }
+    let mut msg = vec![1, creds.username.len() as u8];
+    msg.extend_from_slice(creds.username.as_bytes());
+    msg.push(creds.password.len() as u8);
+    msg.extend_from_slice(creds.password.as_bytes());
+    stream.write_all(&msg)?;
+
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf)?;
+    if buf[1] != 0 {
+        return Err(ClientError::WrongCredentialsError);
+    }
+    return Ok(());
+}
+
+fn read_reply<S: Read>(stream: &mut S) -> Result<(Address, u16), ClientError> {
+    let mut buf = [0; 3];
+    stream.read_exact(&mut buf)?;
+    if buf[0] != 5 {
+        return Err(ClientError::ProtocolVersionError(buf[0]));
+    }
+    match ReplyType::from_byte(buf[1]) {
+        Some(ReplyType::Succeeded) => (),
+        Some(rep) => return Err(ClientError::RequestFailedError(rep)),
+        None => return Err(ClientError::UnknownReplyError(buf[1])),
+    }
+    return Address::decode(stream)?.map_err(ClientError::UnknownAddressTypeError);
+}
+
+/// A BIND request the server accepted, waiting for the peer to connect.
+pub struct PendingBind<S: Read + Write> {
+    stream: S,
+    bound: (Address, u16),
+}
+
+impl<S: Read + Write> PendingBind<S> {
+    /// The address the server listens on for the peer.
+    pub fn bound_address(&self) -> &(Address, u16) {
+        return &self.bound;
+    }
+
+    /// Waits for the peer to connect to the server.
+    /// Returns the stream, which now relays data to the peer, and the peer's address.
+    pub fn accept(mut self) -> Result<(S, (Address, u16)), ClientError> {
+        let peer = read_reply(&mut self.stream)?;
+        return Ok((self.stream, peer));
+    }
+}
+
+/// An established UDP ASSOCIATE.
+pub struct UdpAssociation<S: Read + Write> {
+    control: S,
+    relay: (Address, u16),
+}
+
+impl<S: Read + Write> UdpAssociation<S> {
+    /// The address to send datagrams to, wrapped with `encode_udp_datagram`.
+    pub fn relay_address(&self) -> &(Address, u16) {
+        return &self.relay;
+    }
+
+    /// The control stream, which has to be kept open for the association to last.
+    pub fn control_stream(&mut self) -> &mut S {
+        return &mut self.control;
+    }
+}
+
+/// Wraps `payload` in the header a UDP relay expects, addressing it to `addr`:`port`.
+pub fn encode_udp_datagram(addr: &Address, port: u16, payload: &[u8]) -> io::Result<Vec<u8>> {
+    // Reserved bytes and fragment number, fragmentation is not supported
+    let mut datagram = vec![0, 0, 0];
+    addr.encode(port, &mut datagram)?;
+    datagram.extend_from_slice(payload);
+    return Ok(datagram);
+}
+
+/// Splits a datagram received from a UDP relay into the address it came from and the payload.
+/// Fragmented datagrams are rejected.
+pub fn decode_udp_datagram(datagram: &[u8]) -> io::Result<(Address, u16, &[u8])> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if datagram.len() < 3 {
+        return Err(invalid("datagram is too short"));
+    }
+    if datagram[2] != 0 {
+        return Err(invalid("fragmented datagrams are not supported"));
+    }
+    let mut cursor = io::Cursor::new(&datagram[3..]);
+    let (addr, port) = Address::decode(&mut cursor)?.map_err(|_| invalid("unknown address type"))?;
+    let header_len = 3 + cursor.position() as usize;
+    return Ok((addr, port, &datagram[header_len..]));
+}
@@ -1,5 +1,6 @@
-#[derive(PartialEq)]
-pub(crate) enum Command {
+/// The kind of relay a client requested.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Command {
     Unknown, // Placeholder value
     Connect, // 0x01
     Bind, // 0x02
@@ -15,4 +16,23 @@ impl Command {
             _ => return Command::Unknown,
         }
     }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Command::Connect => return 0x01,
+            Command::Bind => return 0x02,
+            Command::UDPAssociate => return 0x03,
+            Command::Unknown => return 0x00,
+        }
+    }
+
+    /// A short name for the command, for logs.
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Command::Connect => return "connect",
+            Command::Bind => return "bind",
+            Command::UDPAssociate => return "udp_associate",
+            Command::Unknown => return "unknown",
+        }
+    }
 }
\ No newline at end of file
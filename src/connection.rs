@@ -1,14 +1,31 @@
-use crate::reply::SOCKSReply;
+use crate::reply::{ReplyType, SOCKSReply};
 use crate::request::SOCKSRequest;
 use crate::socks_error::SOCKSError;
 use crate::auth::AuthMethod;
-use crate::auth::user_pass_auth;
-use crate::address::Address;
+use crate::auth::user_pass_auth::{self, Authenticated};
+use crate::command::Command;
+use crate::dialer::{DialContext, Dialer};
+use crate::handshake::HandshakeReader;
+use crate::acl::{AccessControlList, AccessRequest, Decision};
+use crate::address::{Address, ClientAddress};
+use crate::listener::ListenerInfo;
+use crate::observer::{RelayStats, ServerObserver, SessionRecord};
+use crate::pt::PtArguments;
+use crate::quota::QuotaMeter;
+use crate::stream::ClientStream;
+use crate::throttle::{Direction, Limiter, Throttle};
+use crate::tracker::Upstream;
+use crate::transform::StreamTransform;
+use crate::trace;
 
 use std::io::Read;
 use std::io::Write;
 use std::io;
 use std::net;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
 
 use ignore_result::Ignore;
 
@@ -24,84 +41,167 @@ If the connection
 */
 
 pub struct SOCKSConnection {
-    stream: net::TcpStream,
+    stream: ClientStream,
+    client_addr: ClientAddress,
+    username: Option<String>,
+    // What the client sent in place of credentials, if the server accepts pluggable transport arguments
+    pt_args: Option<PtArguments>,
+    cmd: Command,
     dst_addr: Address,
     dst_port: u16,
+    early_data: Vec<u8>,
+    // How much of the early data `connect` wrote to the destination already
+    early_data_forwarded: u64,
+    observer: Arc<dyn ServerObserver>,
+    throttle: Arc<Throttle>,
+    // Charges the relayed traffic to the user's quota, if the server enforces quotas
+    quota: Option<QuotaMeter>,
+    session: Session,
+    // Events concerning this connection are recorded within this span
+    span: trace::Span,
+}
+
+// Collects the record of a session, which is handed to the observer once the connection is dropped
+struct Session {
+    observer: Arc<dyn ServerObserver>,
+    // When negotiating with the client started
+    started: time::Instant,
+    record: SessionRecord,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.record.duration = self.started.elapsed();
+        self.observer.on_session_finished(&self.record);
+    }
 }
 
 impl SOCKSConnection {
-    /// Negotiates a connection with the client and returns the TcpStream the client is connected to.
-    pub(crate) fn init(stream: net::TcpStream, supported_auth_methods: Vec<AuthMethod>, username: Option<String>, pass: Option<String>) -> Result<SOCKSConnection, SOCKSError> {
+    /// Negotiates a connection with the client and returns the stream the client is connected to.
+    pub(crate) fn init(stream: ClientStream, observer: Arc<dyn ServerObserver>, throttle: Arc<Throttle>, supported_auth_methods: Vec<AuthMethod>, username: Option<String>, pass: Option<String>, pt_arguments: bool) -> Result<SOCKSConnection, SOCKSError> {
+        let client_addr = stream.client_address()?;
+        let span = trace::span!("connection", id = tracing::field::Empty, client = %client_addr);
+        if let Some(id) = stream.connection_id() {
+            span.record("id", id);
+        }
+        let _entered = span.enter();
         let mut conn = SOCKSConnection {
             stream: stream,
+            client_addr: client_addr.clone(),
+            username: None,
+            pt_args: None,
+            cmd: Command::Unknown,
             dst_addr: Address::DomainName("".to_string()),
             dst_port: 0,
+            early_data: Vec::new(),
+            early_data_forwarded: 0,
+            session: Session {
+                observer: observer.clone(),
+                started: time::Instant::now(),
+                record: SessionRecord {
+                    started: time::SystemTime::now(),
+                    duration: time::Duration::ZERO,
+                    client: client_addr.clone(),
+                    user: None,
+                    command: None,
+                    destination: None,
+                    reply: None,
+                    bytes_in: 0,
+                    bytes_out: 0,
+                },
+            },
+            observer: observer,
+            throttle: throttle,
+            quota: None,
+            span: span.clone(),
         };
+        match conn.negotiate(supported_auth_methods, username, pass, pt_arguments) {
+            Ok(()) => {
+                trace::debug!("handshake complete");
+                return Ok(conn);
+            }
+            Err(err) => {
+                trace::info!(error = %err, "handshake failed");
+                return Err(err);
+            }
+        }
+    }
+
+    fn negotiate(&mut self, supported_auth_methods: Vec<AuthMethod>, username: Option<String>, pass: Option<String>, pt_arguments: bool) -> Result<(), SOCKSError> {
+        // Everything the client sends during the handshake is read through this, the stream itself is only written to
+        let mut reader = HandshakeReader::new(self.stream.try_clone()?);
 
         // FIXME: Handle r/w timeouts everywhere by returning appropriate SOCKSError
 
         // Ensure that the client speaks SOCKS5
         let mut version_buf: [u8; 1] = [0];
-        conn.stream.read_exact(&mut version_buf)?;
+        reader.read_exact(&mut version_buf)?;
         if version_buf[0] != 5 {
-            let mut reply = SOCKSReply::new(conn.stream.local_addr().unwrap());
-            reply.report_general_server_error(&mut conn.stream).ignore();
+            self.reply_failure(ReplyType::GeneralSocksServerFailure).ignore();
             return Err(SOCKSError::ProtoolVersionError(
-                conn.stream.peer_addr().unwrap(),
+                self.client_addr.clone(),
                 version_buf[0],
             ));
         }
 
         // Read how many authentication methods the client supports
         let mut nmethods_buf: [u8; 1] = [0];
-        conn.stream.read_exact(&mut nmethods_buf)?;
+        reader.read_exact(&mut nmethods_buf)?;
         // Ensure client actually supplied > 0 auth methods
         if nmethods_buf[0] < 1 {
-            let mut reply = SOCKSReply::new(conn.stream.local_addr().unwrap());
-            reply.report_general_server_error(&mut conn.stream).ignore();
+            self.reply_failure(ReplyType::GeneralSocksServerFailure).ignore();
             return Err(SOCKSError::NoAuthMethodsError(
-                conn.stream.peer_addr().unwrap(),
+                self.client_addr.clone(),
             ));
             // TODO: Close connections after error (not just here)
         }
 
         // Read the authentication methods supported by the client and check for overlap with ours
         let mut methods_buf = vec![0; nmethods_buf[0].into()];
-        conn.stream.read_exact(&mut methods_buf)?;
+        reader.read_exact(&mut methods_buf)?;
         let mut client_methods: Vec<AuthMethod> = Vec::new();
         for byte in methods_buf {
             client_methods.push(AuthMethod::from_byte(byte));
         }
+        trace::debug!(methods = ?client_methods, "client offered authentication methods");
 
-        let proto_ver_buf: [u8; 1] = [5];
-        conn.stream.write(&proto_ver_buf)?;
+        // The method selection message is the protocol version followed by the chosen method
         match SOCKSConnection::get_auth_method_overlap(client_methods.clone(), supported_auth_methods.clone()) {
             Some(overlap) => {
                 // If we support username/pass authentication, tell the client to use it
                 if overlap.contains(&AuthMethod::UsernamePassword) {
-                    let method_username_pw_buf: [u8; 1] = [AuthMethod::to_byte(&AuthMethod::UsernamePassword)];
-                    conn.stream.write(&method_username_pw_buf)?;
+                    let method_username_pw_buf: [u8; 2] = [5, AuthMethod::to_byte(&AuthMethod::UsernamePassword)];
+                    self.stream.write_all(&method_username_pw_buf)?;
+                    self.observer.on_method_negotiated(&self.client_addr, Some(&AuthMethod::UsernamePassword));
+                    trace::debug!(method = ?AuthMethod::UsernamePassword, "selected authentication method");
                     // User/Pass auth has a separate negotiation, perform that
-                    match user_pass_auth::negotiate_stream(username.unwrap(), pass.unwrap(), &mut conn.stream) {
-                        Some(err) => return Err(err),
-                        None => (),
+                    let correct = if pt_arguments { None } else { Some((username.as_deref().unwrap(), pass.as_deref().unwrap())) };
+                    match user_pass_auth::negotiate_stream(correct, &mut reader, &mut self.stream, &self.client_addr, &*self.observer)? {
+                        Authenticated::User(username) => {
+                            self.session.record.user = Some(username.clone());
+                            self.username = Some(username);
+                        }
+                        Authenticated::PtArguments(args) => self.pt_args = Some(args),
                     }
                 // Otherwise, fall back to no auth
                 } else if overlap.contains(&AuthMethod::NoAuth) {
-                    let method_no_auth_buf: [u8; 1] = [AuthMethod::to_byte(&AuthMethod::NoAuth)];
-                    conn.stream.write(&method_no_auth_buf)?;
+                    let method_no_auth_buf: [u8; 2] = [5, AuthMethod::to_byte(&AuthMethod::NoAuth)];
+                    self.stream.write_all(&method_no_auth_buf)?;
+                    self.observer.on_method_negotiated(&self.client_addr, Some(&AuthMethod::NoAuth));
+                    trace::debug!(method = ?AuthMethod::NoAuth, "selected authentication method");
                 } else {
                     panic!("Unimplemented auth method was registered as usable! This is a bug.");
                 }
             },
             None => {
                 // Tell the client there's no overlap in auth methods
-                let no_compat_methods_buf: [u8; 1] = [NO_SUPPORTED_AUTH_METHODS];
-                conn.stream.write(&no_compat_methods_buf)?;
+                let no_compat_methods_buf: [u8; 2] = [5, NO_SUPPORTED_AUTH_METHODS];
+                self.stream.write_all(&no_compat_methods_buf)?;
+                self.observer.on_method_negotiated(&self.client_addr, None);
                 // Close the connection, as mandated by the spec
-                conn.stream.shutdown(net::Shutdown::Both)?;
+                self.stream.shutdown(net::Shutdown::Both)?;
                 return Err(SOCKSError::NoOverlappingAuthMethodsError(
-                    conn.stream.peer_addr().unwrap(),
+                    self.client_addr.clone(),
                     supported_auth_methods,
                     client_methods
                 ));
@@ -109,18 +209,171 @@ impl SOCKSConnection {
         }
 
         // Read the client's request, which contains information such as the destination server.
-        let mut cloned_stream = conn.stream.try_clone()?;
-        let req = SOCKSRequest::from_stream(&mut cloned_stream)?;
-        conn.dst_addr = req.get_dst_addr();
-        conn.dst_port = req.get_dst_port();
+        let req = match SOCKSRequest::from_stream(&mut reader, &self.client_addr, &*self.observer) {
+            Ok(req) => req,
+            Err(err) => {
+                if let Some(rep) = SOCKSRequest::failure_reply(&err) {
+                    self.reply_failure(rep).ignore();
+                }
+                return Err(err);
+            }
+        };
+        self.cmd = req.get_cmd();
+        self.dst_addr = req.get_dst_addr();
+        self.dst_port = req.get_dst_port();
+        self.session.record.command = Some(self.cmd);
+        self.session.record.destination = Some((self.dst_addr.clone(), self.dst_port));
+        // Clients may send data right after the request, without waiting for the reply
+        self.early_data = reader.into_remaining();
+
+        return Ok(());
+    }
 
-        return Ok(conn);
+    /// Returns the id the server assigned to this connection, which is also recorded in its tracing span.
+    pub fn connection_id(&self) -> Option<u64> {
+        return self.stream.connection_id();
     }
 
-    pub fn get_stream(self) -> net::TcpStream {
+    /// Returns the stream to the client.
+    ///
+    /// Data the client sent before the reply was already read, so it isn't readable from the returned stream.
+    /// Take it with `take_early_data` first and send it on before relaying the stream.
+    pub fn get_stream(self) -> ClientStream {
         return self.stream;
     }
 
+    /// Returns the data the client sent after its request which hasn't been forwarded or taken yet,
+    /// see `UnrequitedSOCKSConnection::take_early_data`.
+    ///
+    /// Since it was already read from the client, it can't be read from the stream returned by `get_stream`.
+    pub fn take_early_data(&mut self) -> Vec<u8> {
+        return std::mem::take(&mut self.early_data);
+    }
+
+    /// Relays data between the client and `upstream` until both have closed their side of the connection,
+    /// and returns how much was transferred. Data the client sent before the reply is forwarded first, unless it was taken.
+    ///
+    /// If either connection fails, both are closed and the error is returned.
+    /// The server's observer is told what was transferred either way, and the server's `Registry` reports the progress meanwhile.
+    /// Closing the connection through the registry closes both connections.
+    /// The server's bandwidth limits are enforced on both directions.
+    /// If the server enforces quotas, both connections are closed as soon as the client's user exceeds theirs.
+    /// Note that the server's `timeout` still applies to the client stream, so clients idle for longer are disconnected.
+    pub fn relay(self, upstream: net::TcpStream) -> Result<RelayStats, io::Error> {
+        return self.relay_with(upstream, Vec::new());
+    }
+
+    /// Relays like `relay`, but passes the data exchanged with `upstream` through `transforms`, e.g. to obfuscate it.
+    ///
+    /// The first transform wraps `upstream` itself and each following one wraps the previous one,
+    /// so data from the client passes through the last transform first.
+    /// The transferred amounts, bandwidth limits and quotas refer to the data before it's transformed.
+    ///
+    /// Data the client sent before the reply passes through the transforms as well, unless `UnrequitedSOCKSConnection::connect`
+    /// already wrote it to the destination as is. Use `connect_keeping_early_data` to avoid that.
+    pub fn relay_with(self, upstream: net::TcpStream, mut transforms: Vec<Box<dyn StreamTransform>>) -> Result<RelayStats, io::Error> {
+        let mut upstream_read: Box<dyn Read + Send> = Box::new(upstream.try_clone()?);
+        let mut upstream_write: Box<dyn Write + Send> = Box::new(upstream.try_clone()?);
+        for transform in transforms.iter_mut() {
+            upstream_read = transform.wrap_reader(upstream_read);
+            upstream_write = transform.wrap_writer(upstream_write);
+        }
+        return self.relay_halves(upstream_read, upstream_write, Arc::new(upstream));
+    }
+
+    /// Relays like `relay`, between the client and the halves of a stream which `upstream` closes.
+    /// The halves may wrap `upstream`, so it's closed directly rather than through them.
+    pub(crate) fn relay_halves(mut self, mut upstream_read: Box<dyn Read + Send>, mut upstream_write: Box<dyn Write + Send>, upstream: Arc<dyn Upstream>) -> Result<RelayStats, io::Error> {
+        let started = time::Instant::now();
+        let early_data = self.take_early_data();
+        let counters = self.stream.byte_counters();
+        counters.client_to_upstream.fetch_add(self.early_data_forwarded, Ordering::Relaxed);
+        self.stream.set_upstream(upstream.clone());
+        let mut client_read = self.stream.try_clone()?;
+        let mut client_write = self.stream.try_clone()?;
+        let sending_upstream = upstream.clone();
+        let mut upload = self.throttle.limiter(Direction::Upload, self.username.as_deref());
+        let mut download = self.throttle.limiter(Direction::Download, self.username.as_deref());
+        let quota = self.quota.clone();
+        let sending_quota = self.quota.clone();
+        if let Some(quota) = &quota {
+            quota.consume(self.early_data_forwarded as usize)?;
+        }
+
+        // Client => Upstream
+        let sending_counters = counters.clone();
+        let sending = thread::Builder::new().name("socks5-relay".to_string()).spawn(move || {
+            let count = &sending_counters.client_to_upstream;
+            let mut result = match &sending_quota {
+                Some(quota) => quota.consume(early_data.len()),
+                None => Ok(()),
+            };
+            if result.is_ok() {
+                result = upstream_write.write_all(&early_data);
+            }
+            if result.is_ok() {
+                count.fetch_add(early_data.len() as u64, Ordering::Relaxed);
+                result = pump(&mut client_read, &mut upstream_write, count, &mut upload, sending_quota.as_ref());
+            }
+            if result.is_ok() {
+                result = upstream_write.flush();
+            }
+            match result {
+                Ok(()) => sending_upstream.shutdown(net::Shutdown::Write).ignore(),
+                // Wake up the other direction, which would otherwise wait for data that's never going to be relayed
+                Err(_) => {
+                    sending_upstream.shutdown(net::Shutdown::Both).ignore();
+                    client_read.shutdown(net::Shutdown::Both).ignore();
+                }
+            }
+            return result;
+        })?;
+
+        // Upstream => Client
+        let receive_result = pump(&mut upstream_read, &mut client_write, &counters.upstream_to_client, &mut download, quota.as_ref());
+        match receive_result {
+            Ok(()) => client_write.shutdown(net::Shutdown::Write).ignore(),
+            Err(_) => {
+                client_write.shutdown(net::Shutdown::Both).ignore();
+                upstream.shutdown(net::Shutdown::Both).ignore();
+            }
+        }
+        let send_result = sending.join().unwrap();
+
+        let stats = RelayStats {
+            client_to_upstream: counters.client_to_upstream.load(Ordering::Relaxed),
+            upstream_to_client: counters.upstream_to_client.load(Ordering::Relaxed),
+            duration: started.elapsed(),
+        };
+        self.observer.on_relay_finished(&self.client_addr, &stats);
+        self.session.record.bytes_in = stats.client_to_upstream;
+        self.session.record.bytes_out = stats.upstream_to_client;
+        if let Some(quota) = &quota {
+            quota.save().ignore();
+        }
+        send_result?;
+        receive_result?;
+        return Ok(stats);
+    }
+
+    // Sends the failure `rep` to the client and closes the connection
+    fn reply_failure(&mut self, rep: ReplyType) -> Result<(), io::Error> {
+        let mut reply = SOCKSReply::new(self.stream.reply_address());
+        let result = reply.report_failure(rep, &mut self.stream);
+        self.span.in_scope(|| trace::debug!(reply = rep.label(), "sent reply"));
+        self.observer.on_reply(&self.client_addr, rep, self.session.started.elapsed());
+        self.session.record.reply = Some(rep);
+        return result;
+    }
+
+    fn reply_success(&mut self, bnd_addr: net::SocketAddr) -> Result<(), io::Error> {
+        let mut reply = SOCKSReply::new(bnd_addr);
+        reply.report_success(&mut self.stream)?;
+        self.span.in_scope(|| trace::debug!(reply = ReplyType::Succeeded.label(), bound = %bnd_addr, "sent reply"));
+        self.observer.on_reply(&self.client_addr, ReplyType::Succeeded, self.session.started.elapsed());
+        self.session.record.reply = Some(ReplyType::Succeeded);
+        return Ok(());
+    }
 
     fn get_auth_method_overlap(one: Vec<AuthMethod>, two: Vec<AuthMethod>) -> Option<Vec<AuthMethod>> {
         let mut intersection: Vec<AuthMethod> = Vec::new();
@@ -130,7 +383,7 @@ impl SOCKSConnection {
             }
         }
 
-        if intersection.len() > 0 {
+        if !intersection.is_empty() {
             return Some(intersection);
         } else {
             return None;
@@ -146,51 +399,180 @@ impl SOCKSConnection {
 /// can do this is returned.
 pub struct UnrequitedSOCKSConnection {
     underlying_connection: SOCKSConnection,
+    listener: Arc<ListenerInfo>,
+    dialer: Arc<Dialer>,
 }
 
 impl UnrequitedSOCKSConnection {
-    pub(crate) fn init(stream: net::TcpStream, auth_methods: Vec<AuthMethod>, username: Option<String>, pass: Option<String>) -> Result<UnrequitedSOCKSConnection, SOCKSError> {
-        let socks_conn = SOCKSConnection::init(stream, auth_methods, username, pass)?;
-        return Ok(UnrequitedSOCKSConnection {
+    /// Wraps a connection which has been negotiated with `SOCKSConnection::init`, but not replied to yet.
+    pub(crate) fn new(socks_conn: SOCKSConnection, listener: Arc<ListenerInfo>, dialer: Arc<Dialer>) -> UnrequitedSOCKSConnection {
+        return UnrequitedSOCKSConnection {
             underlying_connection: socks_conn,
-        });
+            listener: listener,
+            dialer: dialer,
+        };
+    }
+
+    /// Connects to the requested destination using the server's `Dialer`, and reports the outcome to the client.
+    ///
+    /// The dialer is told which user the client authenticated as, to pick a source address for example.
+    /// On success, the client is told the local address of the outgoing connection,
+    /// and both connections are returned ready to relay data.
+    /// Data the client sent after its request without waiting for the reply is written to the destination
+    /// before the client is told the outcome, unless it was taken with `take_early_data` already.
+    /// On failure, the client receives the reply code matching the error and the connection is closed.
+    /// Only the CONNECT command can be served this way, clients requesting anything else are told it's not supported.
+    pub fn connect(self) -> Result<(SOCKSConnection, net::TcpStream), SOCKSError> {
+        return self.connect_forwarding(true);
+    }
+
+    /// Connects like `connect`, but leaves the data the client sent after its request to be relayed,
+    /// so that `SOCKSConnection::relay_with` passes it through its transforms.
+    pub fn connect_keeping_early_data(self) -> Result<(SOCKSConnection, net::TcpStream), SOCKSError> {
+        return self.connect_forwarding(false);
+    }
+
+    fn connect_forwarding(mut self, early_data: bool) -> Result<(SOCKSConnection, net::TcpStream), SOCKSError> {
+        match self.dial(early_data) {
+            Ok(upstream) => return Ok((self.underlying_connection, upstream)),
+            Err(err) => {
+                self.underlying_connection.observer.on_handshake_error(&err);
+                return Err(err);
+            }
+        }
+    }
+
+    // Writes the early data to the destination before replying if `early_data` is set
+    fn dial(&mut self, early_data: bool) -> Result<net::TcpStream, SOCKSError> {
+        let conn = &mut self.underlying_connection;
+        if conn.cmd != Command::Connect {
+            conn.reply_failure(ReplyType::CommandNotSupported).ignore();
+            return Err(SOCKSError::UnknownRequestCommandError(conn.client_addr.clone(), conn.cmd.to_byte()));
+        }
+        let ctx = DialContext {
+            destination: &conn.dst_addr,
+            port: conn.dst_port,
+            user: conn.username.as_deref(),
+        };
+        match self.dialer.dial_for(&ctx) {
+            Ok(mut upstream) => {
+                if early_data && !conn.early_data.is_empty() {
+                    if let Err(err) = upstream.write_all(&conn.early_data) {
+                        conn.reply_failure(ReplyType::GeneralSocksServerFailure).ignore();
+                        return Err(SOCKSError::StreamIOError(err));
+                    }
+                    conn.early_data_forwarded = conn.early_data.len() as u64;
+                    conn.early_data.clear();
+                }
+                conn.reply_success(upstream.local_addr()?)?;
+                return Ok(upstream);
+            }
+            Err(err) => {
+                conn.reply_failure(err.reply()).ignore();
+                return Err(SOCKSError::DestinationError(conn.client_addr.clone(), err));
+            }
+        }
+    }
+
+    /// Replies to the client with `rep`, and tells the observer about `err` before handing it back.
+    #[cfg(feature = "mux")]
+    pub(crate) fn refuse(mut self, rep: ReplyType, err: SOCKSError) -> SOCKSError {
+        self.underlying_connection.reply_failure(rep).ignore();
+        self.underlying_connection.observer.on_handshake_error(&err);
+        return err;
     }
 
     pub fn report_success(mut self) -> Result<SOCKSConnection, io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_success(&mut self.underlying_connection.stream)?;
+        let bnd_addr = self.underlying_connection.stream.reply_address();
+        self.underlying_connection.reply_success(bnd_addr)?;
         return Ok(self.underlying_connection);
     }
     pub fn report_connection_not_allowed(mut self) -> Result<(), io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_connection_not_allowed(&mut self.underlying_connection.stream)?;
-        return Ok(());
+        return self.underlying_connection.reply_failure(ReplyType::ConnectionNotAllowed);
     }
 
     pub fn report_destination_unreachable(mut self) ->  Result<(), io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_destination_unreachable(&mut self.underlying_connection.stream)?;
-        return Ok(());
+        return self.underlying_connection.reply_failure(ReplyType::DestinationUnreachable);
     }
     pub fn report_network_unreachable(mut self) ->  Result<(), io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_network_unreachable(&mut self.underlying_connection.stream)?;
-        return Ok(());
+        return self.underlying_connection.reply_failure(ReplyType::NetworkUnreachable);
+    }
+
+    pub fn report_general_server_failure(mut self) -> Result<(), io::Error> {
+        return self.underlying_connection.reply_failure(ReplyType::GeneralSocksServerFailure);
     }
 
     pub fn report_connection_refused(mut self) ->  Result<(), io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_connection_refused(&mut self.underlying_connection.stream)?;
-        return Ok(());
+        return self.underlying_connection.reply_failure(ReplyType::ConnectionRefused);
     }
     pub fn report_ttl_expired(mut self) ->  Result<(), io::Error> {
-        let mut reply = SOCKSReply::new(self.underlying_connection.stream.local_addr().unwrap());
-        reply.report_ttl_expired(&mut self.underlying_connection.stream)?;
-        return Ok(());
+        return self.underlying_connection.reply_failure(ReplyType::TTLExpired);
     }
 
-    pub fn get_client_address(&self) -> net::SocketAddr {
-        return self.underlying_connection.stream.peer_addr().unwrap();
+    /// Returns the socket address of TCP clients, or the credentials of the peer process for Unix domain socket clients.
+    pub fn get_client_address(&self) -> ClientAddress {
+        return self.underlying_connection.client_addr.clone();
+    }
+
+    /// Evaluates `acl` against the request of this connection.
+    pub fn check_access(&self, acl: &AccessControlList) -> Decision {
+        let conn = &self.underlying_connection;
+        return acl.evaluate(&AccessRequest {
+            destination: &conn.dst_addr,
+            port: conn.dst_port,
+            user: conn.username.as_deref(),
+            client: &conn.client_addr,
+        });
+    }
+
+    /// If `decision` refuses the request, replies to the client with the matching reply code and returns `None`.
+    /// Otherwise the connection is handed back, so the consumer can go on serving it.
+    pub fn apply_decision(mut self, decision: &Decision) -> Result<Option<UnrequitedSOCKSConnection>, io::Error> {
+        match decision.reply() {
+            Some(rep) => {
+                self.underlying_connection.reply_failure(rep)?;
+                return Ok(None);
+            }
+            None => return Ok(Some(self)),
+        }
+    }
+
+    /// Returns the username the client authenticated with, or `None` if it didn't use username/password authentication.
+    pub fn get_username(&self) -> Option<String> {
+        return self.underlying_connection.username.clone();
+    }
+
+    /// Returns the id the server assigned to this connection, which is also recorded in its tracing span.
+    pub fn connection_id(&self) -> Option<u64> {
+        return self.underlying_connection.connection_id();
+    }
+
+    /// Returns the pluggable transport arguments the client sent in place of credentials,
+    /// or `None` unless the server accepts them, see `Server::accept_pt_arguments`.
+    pub fn get_pt_arguments(&self) -> Option<&PtArguments> {
+        return self.underlying_connection.pt_args.as_ref();
+    }
+
+    pub(crate) fn set_quota(&mut self, quota: QuotaMeter) {
+        self.underlying_connection.quota = Some(quota);
+    }
+
+    pub fn get_command(&self) -> Command {
+        return self.underlying_connection.cmd;
+    }
+
+    /// Returns the data the client sent after its request, without waiting for the reply (optimistic data).
+    ///
+    /// Some clients, like Tor, start sending application data right away, which ends up being read along with the request.
+    /// It's the consumer's responsibility to forward it to the destination, before or after reporting success.
+    /// The data is only returned once, subsequent calls return an empty buffer.
+    pub fn take_early_data(&mut self) -> Vec<u8> {
+        return self.underlying_connection.take_early_data();
+    }
+
+    /// Returns which of the server's listeners the client connected to.
+    pub fn get_listener(&self) -> &ListenerInfo {
+        return &self.listener;
     }
 
     pub fn get_destination_address(&self) -> (Address, u16) {
@@ -213,4 +595,33 @@ impl UnrequitedSOCKSConnection {
             }
         }
     }
+}
+
+// Copies everything from `src` to `dst` until `src` is closed, counting the bytes written and keeping to the limits of `limiter`.
+// Fails without writing the data which would exceed `quota`.
+fn pump<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    count: &AtomicU64,
+    limiter: &mut Limiter,
+    quota: Option<&QuotaMeter>,
+) -> Result<(), io::Error> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let chunk_len = limiter.chunk_len(buf.len());
+        let len = match src.read(&mut buf[..chunk_len]) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(quota) = quota {
+            quota.consume(len)?;
+        }
+        limiter.wait(len);
+        dst.write_all(&buf[..len])?;
+        // Transforms may buffer what's written to them
+        dst.flush()?;
+        count.fetch_add(len as u64, Ordering::Relaxed);
+    }
 }
\ No newline at end of file
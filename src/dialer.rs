@@ -0,0 +1,502 @@
+use std::fmt;
+use std::io;
+use std::net;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use crate::address::Address;
+use crate::client::{Client, ClientError, Credentials};
+use crate::http_connect;
+use crate::http_connect::HttpConnectError;
+use crate::network::IpNetwork;
+use crate::reply::ReplyType;
+use crate::resolver::{Resolver, SystemResolver};
+use crate::socket_options::SocketOptions;
+
+/// Establishes outgoing connections to the destinations clients request.
+///
+/// Domain names are resolved by the dialer itself, and the resulting addresses are connected to directly.
+/// This means a `DestinationGuard` sees every address a connection is attempted to,
+/// and a name can't resolve to something else between checking and connecting (DNS rebinding).
+#[derive(Clone)]
+pub struct Dialer {
+    connect_timeout: Option<time::Duration>,
+    attempt_delay: Option<time::Duration>,
+    guard: Option<DestinationGuard>,
+    resolver: Arc<dyn Resolver>,
+    upstream: Vec<UpstreamProxy>,
+    source_policy: Option<SourcePolicy>,
+    socket_options: SocketOptions,
+}
+
+type SourcePolicy = Arc<dyn Fn(&DialContext) -> OutboundSource + Send + Sync>;
+
+impl Dialer {
+    /// Creates a dialer which connects to any destination, resolving names with the `SystemResolver`.
+    pub fn new() -> Dialer {
+        return Dialer {
+            connect_timeout: None,
+            attempt_delay: Some(time::Duration::from_millis(250)),
+            guard: None,
+            resolver: Arc::new(SystemResolver),
+            upstream: Vec::new(),
+            source_policy: None,
+            socket_options: SocketOptions::default(),
+        };
+    }
+
+    /// Sets how long connecting to a single address may take. If `None`, the operating system's timeout applies.
+    pub fn set_connect_timeout(&mut self, timeout: Option<time::Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Sets how long to wait for a connection attempt before racing it against one to the next address,
+    /// as in Happy Eyeballs (RFC 8305). Defaults to 250 milliseconds.
+    /// If `None`, the addresses are tried one after the other.
+    pub fn set_attempt_delay(&mut self, delay: Option<time::Duration>) {
+        self.attempt_delay = delay;
+    }
+
+    /// Refuses to connect to destinations blocked by `guard`. If `None`, every destination is allowed.
+    pub fn set_destination_guard(&mut self, guard: Option<DestinationGuard>) {
+        self.guard = guard;
+    }
+
+    /// Sets the resolver used for domain name destinations.
+    pub fn set_resolver(&mut self, resolver: Arc<dyn Resolver>) {
+        self.resolver = resolver;
+    }
+
+    /// Relays connections through `proxies`, in order, instead of connecting to destinations directly.
+    /// The last proxy in the chain connects to the destination.
+    ///
+    /// Domain name destinations are passed on to the proxy unresolved, so no DNS queries are made locally.
+    /// This also means a `DestinationGuard` can only check destinations which are IP addresses.
+    pub fn set_upstream_proxies(&mut self, proxies: Vec<UpstreamProxy>) {
+        self.upstream = proxies;
+    }
+
+    /// Makes every outgoing connection from `source`.
+    pub fn set_source(&mut self, source: OutboundSource) {
+        self.source_policy = Some(Arc::new(move |_| source.clone()));
+    }
+
+    /// Chooses where each outgoing connection is made from with `policy`, e.g. depending on the user.
+    /// This replaces any source set with `set_source`.
+    pub fn set_source_policy<F>(&mut self, policy: F)
+    where
+        F: Fn(&DialContext) -> OutboundSource + Send + Sync + 'static,
+    {
+        self.source_policy = Some(Arc::new(policy));
+    }
+
+    /// Sets socket options on outgoing connections before connecting.
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    /// Connects to `addr`:`port`, on behalf of no particular user.
+    pub fn dial(&self, addr: &Address, port: u16) -> Result<net::TcpStream, DialError> {
+        return self.dial_for(&DialContext {
+            destination: addr,
+            port: port,
+            user: None,
+        });
+    }
+
+    /// Connects to the destination of `ctx`.
+    ///
+    /// If a domain name resolves to several addresses, they are tried alternating between IPv6 and IPv4,
+    /// starting with the family of the first address the resolver returned.
+    /// Attempts are started `attempt_delay` apart without cancelling the previous ones, and the first to succeed wins,
+    /// so a broken path in one family doesn't stall the connection for a full TCP timeout.
+    /// The address that won is the stream's `peer_addr`, and its `local_addr` is what should be reported to the client.
+    ///
+    /// If a `DestinationGuard` is set and any of the addresses is blocked, no connection is attempted at all.
+    ///
+    /// The connection is made from the `OutboundSource` the source policy picks for `ctx`.
+    /// With upstream proxies, that's the connection to the first proxy.
+    pub fn dial_for(&self, ctx: &DialContext) -> Result<net::TcpStream, DialError> {
+        let (addr, port) = (ctx.destination, ctx.port);
+        let source = match &self.source_policy {
+            Some(policy) => policy(ctx),
+            None => OutboundSource::default(),
+        };
+        if !self.upstream.is_empty() {
+            let ip: Option<net::IpAddr> = match addr {
+                Address::V4(ip) => Some((*ip).into()),
+                Address::V6(ip) => Some((*ip).into()),
+                Address::DomainName(_) => None,
+            };
+            if let (Some(guard), Some(ip)) = (&self.guard, ip) {
+                if guard.is_blocked(ip) {
+                    return Err(DialError::NotAllowed(ip));
+                }
+            }
+            return self.dial_through_upstream(addr, port, &source);
+        }
+
+        let addrs = self.resolve(addr, port)?;
+        if let Some(guard) = &self.guard {
+            for addr in addrs.iter() {
+                if guard.is_blocked(addr.ip()) {
+                    return Err(DialError::NotAllowed(addr.ip()));
+                }
+            }
+        }
+        return self.connect_any(addrs, &source);
+    }
+
+    fn connect_any(&self, addrs: Vec<net::SocketAddr>, source: &OutboundSource) -> Result<net::TcpStream, DialError> {
+        let addrs = interleave_families(addrs);
+        match self.attempt_delay {
+            Some(delay) if addrs.len() > 1 => return self.race(addrs, delay, source),
+            _ => return self.connect_sequentially(addrs, source),
+        }
+    }
+
+    fn dial_through_upstream(&self, addr: &Address, port: u16, source: &OutboundSource) -> Result<net::TcpStream, DialError> {
+        let first = &self.upstream[0];
+        let mut stream = self.connect_any(self.resolve(first.addr(), first.port())?, source)?;
+        // Don't let a stuck proxy hang the handshake forever
+        stream.set_read_timeout(self.connect_timeout).map_err(DialError::Connect)?;
+        stream.set_write_timeout(self.connect_timeout).map_err(DialError::Connect)?;
+        for (i, proxy) in self.upstream.iter().enumerate() {
+            let (next_addr, next_port) = match self.upstream.get(i + 1) {
+                Some(next) => (next.addr(), next.port()),
+                None => (addr, port),
+            };
+            stream = proxy.connect_through(stream, next_addr, next_port)?;
+        }
+        stream.set_read_timeout(None).map_err(DialError::Connect)?;
+        stream.set_write_timeout(None).map_err(DialError::Connect)?;
+        return Ok(stream);
+    }
+
+    fn connect_sequentially(&self, addrs: Vec<net::SocketAddr>, source: &OutboundSource) -> Result<net::TcpStream, DialError> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "destination did not resolve to any address");
+        for addr in addrs {
+            match connect(addr, self.connect_timeout, source, &self.socket_options) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = e,
+            }
+        }
+        return Err(DialError::Connect(last_err));
+    }
+
+    fn race(&self, addrs: Vec<net::SocketAddr>, delay: time::Duration, source: &OutboundSource) -> Result<net::TcpStream, DialError> {
+        let (tx, rx) = mpsc::channel();
+        let mut pending = 0;
+        let mut last_err = None;
+        let mut addrs = addrs.into_iter();
+        loop {
+            // Start the next attempt, unless all have been started already
+            let started = match addrs.next() {
+                Some(addr) => {
+                    let tx = tx.clone();
+                    let timeout = self.connect_timeout;
+                    let source = source.clone();
+                    let options = self.socket_options.clone();
+                    // Attempts which finish after another won are dropped along with their result
+                    thread::Builder::new()
+                        .name("socks5-dial".to_string())
+                        .spawn(move || {
+                            let _ = tx.send(connect(addr, timeout, &source, &options));
+                        })
+                        .map_err(DialError::Connect)?;
+                    pending += 1;
+                    true
+                }
+                None => false,
+            };
+            if pending == 0 {
+                return Err(DialError::Connect(last_err.unwrap()));
+            }
+
+            // Wait for an attempt to finish. A failure starts the next attempt right away.
+            let result = if started {
+                match rx.recv_timeout(delay) {
+                    Ok(result) => result,
+                    Err(_) => continue,
+                }
+            } else {
+                rx.recv().unwrap()
+            };
+            pending -= 1;
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+    }
+
+    fn resolve(&self, addr: &Address, port: u16) -> Result<Vec<net::SocketAddr>, DialError> {
+        match addr {
+            Address::V4(ip) => return Ok(vec![net::SocketAddr::from((*ip, port))]),
+            Address::V6(ip) => return Ok(vec![net::SocketAddr::from((*ip, port))]),
+            Address::DomainName(name) => {
+                // Some clients send IP addresses as domain names
+                if let Ok(ip) = name.parse::<net::IpAddr>() {
+                    return Ok(vec![net::SocketAddr::new(ip, port)]);
+                }
+                let lookup = self.resolver.lookup(name).map_err(DialError::Resolve)?;
+                if lookup.addrs.is_empty() {
+                    return Err(DialError::Resolve(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("'{}' did not resolve to any address", name),
+                    )));
+                }
+                return Ok(lookup.addrs.into_iter().map(|ip| net::SocketAddr::new(ip, port)).collect());
+            }
+        }
+    }
+}
+
+fn connect(
+    addr: net::SocketAddr,
+    timeout: Option<time::Duration>,
+    source: &OutboundSource,
+    options: &SocketOptions,
+) -> io::Result<net::TcpStream> {
+    let source_ip: Option<net::IpAddr> = match addr {
+        net::SocketAddr::V4(_) => source.v4.map(net::IpAddr::V4),
+        net::SocketAddr::V6(_) => source.v6.map(net::IpAddr::V6),
+    };
+    if source_ip.is_none() && source.device.is_none() && options.is_empty() {
+        match timeout {
+            Some(timeout) => return net::TcpStream::connect_timeout(&addr, timeout),
+            None => return net::TcpStream::connect(addr),
+        }
+    }
+
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, None)?;
+    options.apply(socket2::SockRef::from(&socket))?;
+    if let Some(device) = &source.device {
+        bind_device(&socket, device)?;
+    }
+    if let Some(ip) = source_ip {
+        socket.bind(&net::SocketAddr::new(ip, 0).into())?;
+    }
+    match timeout {
+        Some(timeout) => socket.connect_timeout(&addr.into(), timeout)?,
+        None => socket.connect(&addr.into())?,
+    }
+    return Ok(socket.into());
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &socket2::Socket, device: &str) -> io::Result<()> {
+    return socket.bind_device(Some(device.as_bytes()));
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &socket2::Socket, _device: &str) -> io::Result<()> {
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to a network interface is not supported on this platform",
+    ));
+}
+
+/// Orders addresses alternating between families, keeping their order within each family.
+fn interleave_families(addrs: Vec<net::SocketAddr>) -> Vec<net::SocketAddr> {
+    let first_is_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == first_is_v6);
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+impl Default for Dialer {
+    fn default() -> Dialer {
+        return Dialer::new();
+    }
+}
+
+/// What a `Dialer` knows about the connection it's asked to make.
+pub struct DialContext<'a> {
+    pub destination: &'a Address,
+    pub port: u16,
+    /// The user the client authenticated as, if any.
+    pub user: Option<&'a str>,
+}
+
+/// Where a `Dialer` makes an outgoing connection from.
+/// Addresses of a family without a source address are connected to from whatever address the OS picks.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct OutboundSource {
+    pub v4: Option<net::Ipv4Addr>,
+    pub v6: Option<net::Ipv6Addr>,
+    /// The network interface to connect through (`SO_BINDTODEVICE`).
+    /// Only supported on Linux, and usually requires `CAP_NET_RAW`.
+    pub device: Option<String>,
+}
+
+/// A proxy a `Dialer` relays connections through.
+#[derive(PartialEq, Debug, Clone)]
+pub enum UpstreamProxy {
+    Socks5 {
+        addr: Address,
+        port: u16,
+        credentials: Option<Credentials>,
+    },
+    /// An HTTP proxy supporting the `CONNECT` method. Credentials are sent using Basic authentication.
+    HttpConnect {
+        addr: Address,
+        port: u16,
+        credentials: Option<Credentials>,
+    },
+}
+
+impl UpstreamProxy {
+    fn addr(&self) -> &Address {
+        match self {
+            UpstreamProxy::Socks5 { addr, .. } => return addr,
+            UpstreamProxy::HttpConnect { addr, .. } => return addr,
+        }
+    }
+
+    fn port(&self) -> u16 {
+        match self {
+            UpstreamProxy::Socks5 { port, .. } => return *port,
+            UpstreamProxy::HttpConnect { port, .. } => return *port,
+        }
+    }
+
+    /// Asks the proxy on the other end of `stream` to connect to `addr`:`port`.
+    fn connect_through(&self, mut stream: net::TcpStream, addr: &Address, port: u16) -> Result<net::TcpStream, DialError> {
+        match self {
+            UpstreamProxy::Socks5 { credentials, .. } => {
+                let (stream, _) = Client::handshake(stream, credentials.as_ref())
+                    .and_then(|client| client.connect(addr, port))
+                    .map_err(DialError::Upstream)?;
+                return Ok(stream);
+            }
+            UpstreamProxy::HttpConnect { credentials, .. } => {
+                http_connect::connect(&mut stream, addr, port, credentials.as_ref()).map_err(DialError::HttpUpstream)?;
+                return Ok(stream);
+            }
+        }
+    }
+}
+
+/// Returned when a `Dialer` fails to connect to a destination.
+#[derive(Debug)]
+pub enum DialError {
+    /// The destination is or resolved to an address blocked by the `DestinationGuard`.
+    NotAllowed(net::IpAddr),
+    Resolve(io::Error),
+    Connect(io::Error),
+    /// An upstream SOCKS5 proxy refused or failed to relay the connection.
+    Upstream(ClientError),
+    /// An upstream HTTP proxy refused or failed to relay the connection.
+    HttpUpstream(HttpConnectError),
+}
+
+impl DialError {
+    /// The reply code to send to the client on whose behalf the connection was attempted.
+    pub fn reply(&self) -> ReplyType {
+        match self {
+            DialError::NotAllowed(_) => return ReplyType::ConnectionNotAllowed,
+            DialError::Resolve(_) => return ReplyType::DestinationUnreachable,
+            DialError::Connect(e) => match e.kind() {
+                io::ErrorKind::ConnectionRefused => return ReplyType::ConnectionRefused,
+                io::ErrorKind::NetworkUnreachable => return ReplyType::NetworkUnreachable,
+                io::ErrorKind::HostUnreachable => return ReplyType::DestinationUnreachable,
+                io::ErrorKind::TimedOut => return ReplyType::TTLExpired,
+                _ => return ReplyType::GeneralSocksServerFailure,
+            },
+            DialError::Upstream(e) => return e.reply(),
+            DialError::HttpUpstream(e) => return e.reply(),
+        }
+    }
+}
+
+impl fmt::Display for DialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialError::NotAllowed(ip) => write!(f, "connecting to {} is not allowed", ip),
+            DialError::Resolve(e) => write!(f, "failed to resolve destination: {}", e),
+            DialError::Connect(e) => write!(f, "failed to connect to destination: {}", e),
+            DialError::Upstream(e) => write!(f, "upstream proxy failed: {}", e),
+            DialError::HttpUpstream(e) => write!(f, "upstream proxy failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DialError {}
+
+/// Blocks connections to IP addresses which shouldn't be reachable through a public-facing proxy,
+/// such as loopback, private and link-local networks.
+///
+/// IPv4-mapped IPv6 addresses are checked as the IPv4 address they map to.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DestinationGuard {
+    pub blocked: Vec<IpNetwork>,
+    /// Exceptions from `blocked`, e.g. for an internal service clients are supposed to reach.
+    pub allowed: Vec<IpNetwork>,
+}
+
+impl DestinationGuard {
+    pub fn is_blocked(&self, ip: net::IpAddr) -> bool {
+        if self.allowed.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        return self.blocked.iter().any(|net| net.contains(ip));
+    }
+}
+
+impl Default for DestinationGuard {
+    /// Blocks every special-purpose network which doesn't lead to the public internet.
+    fn default() -> DestinationGuard {
+        let blocked = [
+            // "This network", loopback, RFC 1918, shared address space (CGNAT)
+            "0.0.0.0/8",
+            "127.0.0.0/8",
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "100.64.0.0/10",
+            // Link-local, which includes cloud metadata services at 169.254.169.254
+            "169.254.0.0/16",
+            // IETF protocol assignments, benchmarking, multicast, reserved and broadcast
+            "192.0.0.0/24",
+            "198.18.0.0/15",
+            "224.0.0.0/4",
+            "240.0.0.0/4",
+            // Documentation
+            "192.0.2.0/24",
+            "198.51.100.0/24",
+            "203.0.113.0/24",
+            // Unspecified, loopback and the deprecated IPv4-compatible addresses
+            "::/96",
+            // NAT64, which may lead to any of the IPv4 networks above
+            "64:ff9b::/96",
+            "64:ff9b:1::/48",
+            // Teredo and 6to4, which may wrap any IPv4 address as well
+            "2001::/32",
+            "2002::/16",
+            // Documentation
+            "2001:db8::/32",
+            // Unique local, link-local, deprecated site-local and multicast
+            "fc00::/7",
+            "fe80::/10",
+            "fec0::/10",
+            "ff00::/8",
+        ];
+        return DestinationGuard {
+            blocked: blocked.iter().map(|net| net.parse().unwrap()).collect(),
+            allowed: Vec::new(),
+        };
+    }
+}
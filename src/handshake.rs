@@ -0,0 +1,55 @@
+use std::io;
+use std::io::Read;
+
+use crate::stream::ClientStream;
+
+// Large enough for any handshake message but the longest username/password pairs
+const READ_CHUNK_LEN: usize = 512;
+
+/// Reads the handshake from a client in chunks, rather than issuing a read for every field.
+///
+/// Clients may send data right after their request without waiting for the reply (optimistic data),
+/// so whatever was read past the end of the request is kept and can be taken with `into_remaining`.
+pub(crate) struct HandshakeReader {
+    stream: ClientStream,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl HandshakeReader {
+    pub(crate) fn new(stream: ClientStream) -> HandshakeReader {
+        return HandshakeReader {
+            stream: stream,
+            buf: Vec::with_capacity(READ_CHUNK_LEN),
+            pos: 0,
+        };
+    }
+
+    /// Returns the bytes which were read from the stream, but not consumed.
+    pub(crate) fn into_remaining(mut self) -> Vec<u8> {
+        self.buf.drain(..self.pos);
+        return self.buf;
+    }
+}
+
+impl Read for HandshakeReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(READ_CHUNK_LEN, 0);
+            let len = match self.stream.read(&mut self.buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    self.buf.clear();
+                    self.pos = 0;
+                    return Err(e);
+                }
+            };
+            self.buf.truncate(len);
+            self.pos = 0;
+        }
+        let len = out.len().min(self.buf.len() - self.pos);
+        out[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        return Ok(len);
+    }
+}
@@ -0,0 +1,57 @@
+use std::io;
+use std::io::{Read, Write};
+
+// Requests with a larger head than this are rejected
+const MAX_REQUEST_LEN: usize = 8192;
+
+pub(crate) struct RequestHead {
+    // Everything up to the empty line ending the header
+    head: String,
+}
+
+impl RequestHead {
+    pub(crate) fn request_line(&self) -> &str {
+        return self.head.lines().next().unwrap_or("");
+    }
+
+    // Returns the value of the first header called `name`, which is matched case-insensitively
+    #[cfg(feature = "admin")]
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        return self
+            .head
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim());
+    }
+}
+
+// Reads an HTTP request up to the end of its header.
+// The built-in endpoints only take requests without a body.
+pub(crate) fn read_request_head<R: Read>(stream: &mut R) -> Result<RequestHead, io::Error> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = stream.read(&mut buf)?;
+        if len == 0 || head.len() + len > MAX_REQUEST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete or oversized HTTP request"));
+        }
+        head.extend_from_slice(&buf[..len]);
+    }
+    return Ok(RequestHead {
+        head: String::from_utf8_lossy(&head).to_string(),
+    });
+}
+
+pub(crate) fn write_response<W: Write>(stream: &mut W, status: &str, content_type: &str, body: &str) -> Result<(), io::Error> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body,
+    );
+    return stream.write_all(response.as_bytes());
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::address::Address;
+use crate::client::Credentials;
+use crate::reply::ReplyType;
+
+// Proxies don't send huge responses to CONNECT, so anything longer is most likely not a proxy at all
+const MAX_RESPONSE_HEADER_LEN: usize = 8192;
+
+/// Returned when an HTTP proxy fails to establish a tunnel.
+#[derive(Debug)]
+pub enum HttpConnectError {
+    /// The proxy answered with a status code other than 2xx, along with its reason phrase.
+    Status(u16, String),
+    /// The proxy's response was not valid HTTP.
+    Malformed(String),
+    /// The destination is a domain name which can't be put into a request without changing its meaning,
+    /// e.g. because it contains line breaks.
+    InvalidHost(String),
+    StreamIOError(io::Error),
+}
+
+impl HttpConnectError {
+    /// The reply code to pass on to a client whose request was relayed to the proxy.
+    pub fn reply(&self) -> ReplyType {
+        match self {
+            // Forbidden, Proxy Authentication Required
+            HttpConnectError::Status(403, _) | HttpConnectError::Status(407, _) => return ReplyType::ConnectionNotAllowed,
+            // Bad Gateway, which proxies send if they can't reach the destination
+            HttpConnectError::Status(502, _) => return ReplyType::DestinationUnreachable,
+            // Gateway Timeout
+            HttpConnectError::Status(504, _) => return ReplyType::TTLExpired,
+            HttpConnectError::InvalidHost(_) => return ReplyType::AddressTypeNotSupported,
+            HttpConnectError::StreamIOError(e) if e.kind() == io::ErrorKind::TimedOut => return ReplyType::TTLExpired,
+            HttpConnectError::StreamIOError(e) if e.kind() == io::ErrorKind::WouldBlock => return ReplyType::TTLExpired,
+            _ => return ReplyType::GeneralSocksServerFailure,
+        }
+    }
+}
+
+impl fmt::Display for HttpConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpConnectError::Status(code, reason) => write!(f, "Proxy refused CONNECT with status {} {}", code, reason),
+            HttpConnectError::Malformed(msg) => write!(f, "Proxy sent a malformed response: {}", msg),
+            HttpConnectError::InvalidHost(name) => write!(f, "Domain name {:?} can't be sent to an HTTP proxy", name),
+            HttpConnectError::StreamIOError(e) => write!(f, "I/O error while talking to proxy: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpConnectError {}
+
+impl From<io::Error> for HttpConnectError {
+    fn from(e: io::Error) -> HttpConnectError {
+        return HttpConnectError::StreamIOError(e);
+    }
+}
+
+/// Asks the HTTP proxy on the other end of `stream` to open a tunnel to `addr`:`port`.
+/// Once this returns successfully, `stream` relays data to the destination.
+pub(crate) fn connect<S: Read + Write>(stream: &mut S, addr: &Address, port: u16, credentials: Option<&Credentials>) -> Result<(), HttpConnectError> {
+    let authority = match addr {
+        Address::V6(ip) => format!("[{}]:{}", ip, port),
+        // Clients pick the name, which mustn't be able to add headers or requests of its own
+        Address::DomainName(name) if !is_valid_host(name) => return Err(HttpConnectError::InvalidHost(name.clone())),
+        _ => format!("{}:{}", addr, port),
+    };
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(creds) = credentials {
+        let token = base64(format!("{}:{}", creds.username, creds.password).as_bytes());
+        req.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    req.push_str("\r\n");
+    stream.write_all(req.as_bytes())?;
+
+    let header = read_response_header(stream)?;
+    let status_line = header.lines().next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") {
+        return Err(HttpConnectError::Malformed(format!("unexpected status line '{}'", status_line)));
+    }
+    let code: u16 = match parts.next().and_then(|code| code.parse().ok()) {
+        Some(code) => code,
+        None => return Err(HttpConnectError::Malformed(format!("unexpected status line '{}'", status_line))),
+    };
+    if !(200..300).contains(&code) {
+        return Err(HttpConnectError::Status(code, parts.next().unwrap_or("").to_string()));
+    }
+    return Ok(());
+}
+
+/// Returns whether `name` can be used as the host of a request's authority as is.
+fn is_valid_host(name: &str) -> bool {
+    return !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_control() || c.is_whitespace() || matches!(c, '/' | '@' | '[' | ']' | ':'));
+}
+
+/// Reads the response up to and including the empty line ending the header.
+/// Reads byte by byte, as anything after the header already belongs to the tunnel.
+fn read_response_header<S: Read>(stream: &mut S) -> Result<String, HttpConnectError> {
+    let mut header = Vec::new();
+    let mut byte = [0; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER_LEN {
+            return Err(HttpConnectError::Malformed("response header is too long".to_string()));
+        }
+        stream.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    return Ok(String::from_utf8_lossy(&header).to_string());
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    return encoded;
+}
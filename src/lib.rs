@@ -1,14 +1,65 @@
+pub mod access_log;
+pub mod acl;
+#[cfg(feature = "admin")]
+pub mod admin;
 mod address;
 mod auth;
+pub mod client;
 mod command;
 mod connection;
+mod dialer;
+mod handshake;
+#[cfg(any(feature = "metrics", feature = "admin"))]
+mod http;
+mod http_connect;
+mod limits;
+mod listener;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mux")]
+pub mod mux;
+mod network;
+#[cfg(feature = "obfs")]
+pub mod obfs;
+mod observer;
+mod percent;
+mod pt;
+mod quota;
+mod registry;
 mod reply;
 mod request;
+pub mod resolver;
 mod server;
+mod shutdown;
+mod socket_options;
 mod socks_error;
+mod stream;
+#[cfg(unix)]
+pub mod systemd;
+mod throttle;
+mod trace;
+mod tracker;
+mod transform;
 
 pub use auth::AuthMethod;
+pub use command::Command;
 pub use connection::SOCKSConnection as Connection;
+pub use dialer::{DestinationGuard, DialContext, DialError, Dialer, OutboundSource, UpstreamProxy};
+pub use http_connect::HttpConnectError;
 pub use server::SOCKSServer as Server;
+pub use shutdown::ShutdownHandle;
+pub use socket_options::{Keepalive, SocketOptions};
 pub use socks_error::SOCKSError as Error;
-pub use address::Address as Address;
\ No newline at end of file
+pub use address::Address as Address;
+pub use address::{ClientAddress, PeerCredentials};
+pub use limits::{ConnectionLimits, ConnectionStats, LimitAction, LimitKind};
+pub use listener::{Listener, ListenerInfo};
+pub use network::{IpNetwork, NetworkParseError};
+pub use observer::{RelayStats, ServerObserver, SessionRecord};
+pub use pt::{PtArguments, PtArgumentsError};
+pub use quota::{Quota, QuotaStore, QuotaUsage};
+pub use registry::{Registry, SessionInfo};
+pub use reply::ReplyType;
+pub use stream::ClientStream;
+pub use throttle::{BandwidthLimits, DirectionLimits, RateLimit};
+pub use transform::StreamTransform;
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net;
+use std::sync::Arc;
+
+use crate::tracker::ConnectionTracker;
+
+/// Limits on the number of concurrent connections a `Server` accepts.
+/// Every limit which is `None` is not enforced.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    /// The maximum number of active connections overall.
+    pub max_connections: Option<usize>,
+    /// The maximum number of active connections per client IP address.
+    /// Clients connecting through Unix domain sockets are not subject to this limit.
+    pub max_per_client: Option<usize>,
+    /// The maximum number of active connections per authenticated user.
+    /// Clients which didn't authenticate with a username are not subject to this limit.
+    pub max_per_user: Option<usize>,
+    pub action: LimitAction,
+}
+
+/// What the server does with a connection that exceeds a limit.
+/// Either way, the iterator yields a `ConnectionLimitError` in its place.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum LimitAction {
+    /// Close the connection as soon as the limit is detected,
+    /// which is at accept time for the overall and per-client limits and after authentication for per-user limits.
+    #[default]
+    Close,
+    /// Negotiate with the client as usual, then reply with a general SOCKS server failure.
+    ReplyFailure,
+}
+
+/// The limit a connection exceeded.
+#[derive(PartialEq, Debug, Clone)]
+pub enum LimitKind {
+    Total(usize),
+    PerClient(usize),
+    PerUser(String, usize),
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitKind::Total(max) => write!(f, "at most {} connections are allowed in total", max),
+            LimitKind::PerClient(max) => write!(f, "at most {} connections are allowed per client", max),
+            LimitKind::PerUser(user, max) => write!(f, "at most {} connections are allowed for user '{}'", max, user),
+        }
+    }
+}
+
+/// Reports how many connections a `Server` currently has active.
+/// Connections count from the moment they're accepted until every clone of their `ClientStream` has been dropped.
+#[derive(Clone)]
+pub struct ConnectionStats {
+    tracker: Arc<ConnectionTracker>,
+}
+
+impl ConnectionStats {
+    pub(crate) fn new(tracker: Arc<ConnectionTracker>) -> ConnectionStats {
+        return ConnectionStats { tracker: tracker };
+    }
+
+    pub fn total(&self) -> usize {
+        return self.tracker.count();
+    }
+
+    /// Returns the number of active connections per client IP address.
+    pub fn per_client(&self) -> HashMap<net::IpAddr, usize> {
+        return self.tracker.per_client();
+    }
+
+    /// Returns the number of active connections per authenticated user.
+    pub fn per_user(&self) -> HashMap<String, usize> {
+        return self.tracker.per_user();
+    }
+}
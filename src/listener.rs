@@ -0,0 +1,249 @@
+use std::io;
+use std::net;
+use std::thread;
+use std::time;
+
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use socket2::{Domain, Socket, Type};
+
+use crate::stream::{ClientStream, StreamSocket};
+
+// Same as the Rust standard library uses for TcpListener::bind.
+const LISTEN_BACKLOG: i32 = 128;
+
+/// A socket a `Server` accepts clients on.
+/// A server can own several listeners, e.g. to accept both IPv4 and IPv6 clients or to also listen on a Unix domain socket.
+pub struct Listener {
+    pub(crate) socket: ListenerSocket,
+    pub(crate) name: Option<String>,
+}
+
+impl Listener {
+    /// Binds a TCP listener to `addr`.
+    /// For IPv6 addresses the platform default decides whether IPv4 clients are accepted as well,
+    /// use `bind_ipv6` to control this.
+    pub fn bind_tcp(addr: net::SocketAddr) -> Result<Listener, io::Error> {
+        return Ok(Listener::from(net::TcpListener::bind(addr)?));
+    }
+
+    /// Binds a TCP listener to the IPv6 address `addr`.
+    /// If `v6_only` is `true` only IPv6 clients are accepted (`IPV6_V6ONLY`), so that another listener can bind
+    /// the same port for IPv4. Otherwise IPv4 clients are accepted too, as IPv4-mapped IPv6 addresses.
+    pub fn bind_ipv6(addr: net::SocketAddrV6, v6_only: bool) -> Result<Listener, io::Error> {
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+        socket.set_only_v6(v6_only)?;
+        // Allow quick restarts, as TcpListener::bind does on Unix
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&net::SocketAddr::V6(addr).into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        return Ok(Listener::from(net::TcpListener::from(socket)));
+    }
+
+    /// Binds a Unix domain socket listener at `path`.
+    /// See `Server::init_unix` for how stale sockets and `mode` are handled.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P, mode: Option<u32>) -> Result<Listener, io::Error> {
+        return Ok(Listener::from(bind_unix(path.as_ref(), mode)?));
+    }
+
+    /// Takes ownership of the listening socket `fd`, which may be a TCP or Unix domain stream socket.
+    /// This function fails if `fd` is any other kind of file descriptor.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor which is not owned by anything else.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Listener, io::Error> {
+        return Ok(Listener {
+            socket: socket_from_raw_fd(fd)?,
+            name: None,
+        });
+    }
+
+    /// Returns the address a TCP listener is bound to.
+    /// Fails for Unix domain socket listeners, which don't have a socket address.
+    pub fn local_addr(&self) -> Result<net::SocketAddr, io::Error> {
+        match &self.socket {
+            ListenerSocket::Tcp(l) => return l.local_addr(),
+            #[cfg(unix)]
+            ListenerSocket::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Unix domain socket listeners don't have a socket address",
+                ))
+            }
+        }
+    }
+
+    /// Attaches a name to this listener, which connections accepted on it report alongside its index.
+    pub fn named<S: Into<String>>(mut self, name: S) -> Listener {
+        self.name = Some(name.into());
+        return self;
+    }
+}
+
+impl From<net::TcpListener> for Listener {
+    fn from(listener: net::TcpListener) -> Listener {
+        return Listener {
+            socket: ListenerSocket::Tcp(listener),
+            name: None,
+        };
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Listener {
+        return Listener {
+            socket: ListenerSocket::Unix(listener),
+            name: None,
+        };
+    }
+}
+
+/// Describes the listener a connection arrived on, so handlers can apply per-listener policy.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ListenerInfo {
+    /// The position of the listener in the list the `Server` was created with.
+    pub index: usize,
+    /// The name given with `Listener::named`, or by systemd for socket-activated listeners.
+    pub name: Option<String>,
+}
+
+pub(crate) enum ListenerSocket {
+    Tcp(net::TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl ListenerSocket {
+    pub(crate) fn accept(&self) -> Result<ClientStream, io::Error> {
+        match self {
+            ListenerSocket::Tcp(l) => return Ok(ClientStream::new(StreamSocket::Tcp(l.accept()?.0))),
+            #[cfg(unix)]
+            ListenerSocket::Unix(l) => return Ok(ClientStream::new(StreamSocket::Unix(l.accept()?.0))),
+        }
+    }
+}
+
+// Slows an accept loop down while accepting keeps failing,
+// so that a persistent error such as running out of file descriptors doesn't make it spin.
+pub(crate) struct AcceptBackoff {
+    delay: time::Duration,
+}
+
+impl AcceptBackoff {
+    const MIN_DELAY: time::Duration = time::Duration::from_millis(10);
+    const MAX_DELAY: time::Duration = time::Duration::from_secs(1);
+
+    pub(crate) fn new() -> AcceptBackoff {
+        return AcceptBackoff {
+            delay: time::Duration::ZERO,
+        };
+    }
+
+    // Returns what was accepted, or sleeps for twice as long as after the previous failure in a row
+    #[cfg(any(feature = "admin", feature = "metrics", feature = "mux", feature = "obfs"))]
+    pub(crate) fn check<T>(&mut self, accepted: Result<T, io::Error>) -> Option<T> {
+        self.record(accepted.is_ok());
+        return accepted.ok();
+    }
+
+    // Sleeps like `check` after a failure, for loops which pass the error on themselves
+    pub(crate) fn record(&mut self, succeeded: bool) {
+        if succeeded {
+            self.delay = time::Duration::ZERO;
+            return;
+        }
+        self.delay = (self.delay * 2).clamp(AcceptBackoff::MIN_DELAY, AcceptBackoff::MAX_DELAY);
+        thread::sleep(self.delay);
+    }
+}
+
+/// Binds a Unix domain socket at `path`, replacing a stale socket file left behind by a previous run.
+/// If `mode` is not `None` the permission bits of the socket file are set to it after binding.
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<UnixListener, io::Error> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    return Ok(listener);
+}
+
+// A socket file nobody is listening on anymore is stale and can be removed.
+// Anything else at `path` is left alone, so we never delete regular files or steal a live server's socket.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), io::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(val) => val,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' exists and is not a socket", path.display()),
+        ));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another server is already listening on '{}'", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => return fs::remove_file(path),
+        Err(e) => return Err(e),
+    }
+}
+
+#[cfg(unix)]
+unsafe fn socket_from_raw_fd(fd: RawFd) -> Result<ListenerSocket, io::Error> {
+    let mut sock_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = libc::getsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_TYPE,
+        &mut sock_type as *mut libc::c_int as *mut libc::c_void,
+        &mut len,
+    );
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if sock_type != libc::SOCK_STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file descriptor {} is not a stream socket", fd),
+        ));
+    }
+
+    let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if libc::getsockname(fd, &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr, &mut len) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => return Ok(ListenerSocket::Tcp(net::TcpListener::from_raw_fd(fd))),
+        libc::AF_UNIX => return Ok(ListenerSocket::Unix(UnixListener::from_raw_fd(fd))),
+        family => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("file descriptor {} has unsupported address family {}", fd, family),
+            ))
+        }
+    }
+}
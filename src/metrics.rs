@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::address::ClientAddress;
+use crate::http;
+use crate::limits::ConnectionStats;
+use crate::listener::{AcceptBackoff, ListenerInfo};
+use crate::observer::{RelayStats, ServerObserver};
+use crate::reply::ReplyType;
+use crate::socks_error::SOCKSError;
+
+// Upper bounds of the handshake duration buckets, in seconds
+const HANDSHAKE_BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const SCRAPE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Collects metrics about a `Server` and renders them in the Prometheus text exposition format.
+///
+/// Metrics are collected by setting the same instance as the server's observer:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use socks5_frontend::metrics::Metrics;
+/// # let mut server = socks5_frontend::Server::init("127.0.0.1:1080".parse().unwrap(), None, vec![socks5_frontend::AuthMethod::NoAuth], None, None).unwrap();
+/// let metrics = Arc::new(Metrics::new(server.connection_stats()));
+/// server.set_observer(metrics.clone());
+/// metrics.serve(std::net::TcpListener::bind("127.0.0.1:9150").unwrap()).unwrap();
+/// ```
+pub struct Metrics {
+    connections: ConnectionStats,
+    accepted: AtomicU64,
+    auth_failures: AtomicU64,
+    handshake_errors: Mutex<BTreeMap<&'static str, u64>>,
+    replies: Mutex<BTreeMap<&'static str, u64>>,
+    handshake_duration: Histogram,
+    relayed_to_upstream: AtomicU64,
+    relayed_to_client: AtomicU64,
+}
+
+impl Metrics {
+    /// Creates a collector reporting the number of active connections from `connections`,
+    /// which should be the `connection_stats` of the observed server.
+    pub fn new(connections: ConnectionStats) -> Metrics {
+        return Metrics {
+            connections: connections,
+            accepted: AtomicU64::new(0),
+            auth_failures: AtomicU64::new(0),
+            handshake_errors: Mutex::new(BTreeMap::new()),
+            replies: Mutex::new(BTreeMap::new()),
+            handshake_duration: Histogram::new(),
+            relayed_to_upstream: AtomicU64::new(0),
+            relayed_to_client: AtomicU64::new(0),
+        };
+    }
+
+    /// Renders the current value of all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_metric(&mut out, "socks5_connections_accepted_total", "counter", "Connections accepted by the server.");
+        writeln!(out, "socks5_connections_accepted_total {}", self.accepted.load(Ordering::Relaxed)).ignore();
+        write_metric(&mut out, "socks5_connections_active", "gauge", "Connections currently active.");
+        writeln!(out, "socks5_connections_active {}", self.connections.total()).ignore();
+        write_metric(&mut out, "socks5_auth_failures_total", "counter", "Clients which supplied invalid credentials.");
+        writeln!(out, "socks5_auth_failures_total {}", self.auth_failures.load(Ordering::Relaxed)).ignore();
+
+        write_metric(&mut out, "socks5_handshake_errors_total", "counter", "Failed handshakes by error.");
+        for (error, count) in self.handshake_errors.lock().unwrap().iter() {
+            writeln!(out, "socks5_handshake_errors_total{{error=\"{}\"}} {}", error, count).ignore();
+        }
+        write_metric(&mut out, "socks5_replies_total", "counter", "Replies sent to clients by reply code.");
+        for (reply, count) in self.replies.lock().unwrap().iter() {
+            writeln!(out, "socks5_replies_total{{reply=\"{}\"}} {}", reply, count).ignore();
+        }
+
+        write_metric(&mut out, "socks5_handshake_duration_seconds", "histogram", "Time from starting to negotiate with a client until replying to its request.");
+        self.handshake_duration.render(&mut out, "socks5_handshake_duration_seconds");
+
+        write_metric(&mut out, "socks5_relayed_bytes_total", "counter", "Bytes relayed by Connection::relay by direction.");
+        writeln!(out, "socks5_relayed_bytes_total{{direction=\"client_to_upstream\"}} {}", self.relayed_to_upstream.load(Ordering::Relaxed)).ignore();
+        writeln!(out, "socks5_relayed_bytes_total{{direction=\"upstream_to_client\"}} {}", self.relayed_to_client.load(Ordering::Relaxed)).ignore();
+        return out;
+    }
+
+    /// Serves the metrics over HTTP at `/metrics` on `listener`, from a background thread.
+    /// Scrapes are answered one at a time, so the endpoint should only be reachable by the monitoring system.
+    pub fn serve(self: Arc<Self>, listener: net::TcpListener) -> Result<(), io::Error> {
+        let mut backoff = AcceptBackoff::new();
+        thread::Builder::new().name("socks5-metrics".to_string()).spawn(move || loop {
+            // A scrape failing is of no concern to the server
+            if let Some((stream, _)) = backoff.check(listener.accept()) {
+                self.answer(stream).ignore();
+            }
+        })?;
+        return Ok(());
+    }
+
+    fn answer(&self, mut stream: net::TcpStream) -> Result<(), io::Error> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+        let request = http::read_request_head(&mut stream)?;
+        let mut parts = request.request_line().split(' ');
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), Some(_)) => ("404 Not Found", "Not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+        };
+        return http::write_response(&mut stream, status, "text/plain; version=0.0.4", &body);
+    }
+}
+
+impl ServerObserver for Metrics {
+    fn on_accepted(&self, _client: &ClientAddress, _listener: &ListenerInfo) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_auth_failed(&self, _client: &ClientAddress) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_reply(&self, _client: &ClientAddress, reply: ReplyType, elapsed: time::Duration) {
+        *self.replies.lock().unwrap().entry(reply.label()).or_insert(0) += 1;
+        self.handshake_duration.observe(elapsed);
+    }
+
+    fn on_relay_finished(&self, _client: &ClientAddress, stats: &RelayStats) {
+        self.relayed_to_upstream.fetch_add(stats.client_to_upstream, Ordering::Relaxed);
+        self.relayed_to_client.fetch_add(stats.upstream_to_client, Ordering::Relaxed);
+    }
+
+    fn on_handshake_error(&self, error: &SOCKSError) {
+        *self.handshake_errors.lock().unwrap().entry(error.kind()).or_insert(0) += 1;
+    }
+}
+
+struct Histogram {
+    // Observations per bucket, not cumulative. The last one counts observations above the largest bound.
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        return Histogram {
+            buckets: (0..=HANDSHAKE_BUCKETS.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        };
+    }
+
+    fn observe(&self, duration: time::Duration) {
+        let secs = duration.as_secs_f64();
+        let index = HANDSHAKE_BUCKETS.iter().position(|bound| secs <= *bound).unwrap_or(HANDSHAKE_BUCKETS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            match HANDSHAKE_BUCKETS.get(index) {
+                Some(bound) => writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).ignore(),
+                None => writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative).ignore(),
+            }
+        }
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "{}_sum {}", name, sum).ignore();
+        writeln!(out, "{}_count {}", name, cumulative).ignore();
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).ignore();
+    writeln!(out, "# TYPE {} {}", name, kind).ignore();
+}
@@ -0,0 +1,611 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use ignore_result::Ignore;
+
+use crate::address::Address;
+use crate::client::ClientError;
+use crate::command::Command;
+use crate::connection::UnrequitedSOCKSConnection;
+use crate::dialer::{DialError, Dialer};
+use crate::listener::AcceptBackoff;
+use crate::observer::RelayStats;
+use crate::reply::ReplyType;
+use crate::socks_error::SOCKSError;
+use crate::tracker::Upstream;
+use crate::transform::StreamTransform;
+
+const VERSION: u8 = 0;
+const TYPE_DATA: u8 = 0;
+const TYPE_WINDOW_UPDATE: u8 = 1;
+const TYPE_GO_AWAY: u8 = 3;
+const FLAG_SYN: u16 = 0x1;
+const FLAG_ACK: u16 = 0x2;
+const FLAG_FIN: u16 = 0x4;
+const FLAG_RST: u16 = 0x8;
+const HEADER_LEN: usize = 12;
+// How much each side may send on a stream before the other grants more
+const INITIAL_WINDOW: u32 = 256 * 1024;
+const MAX_DATA_LEN: usize = 16 * 1024;
+// An address type, a domain name with its length, and a port
+const MAX_OPEN_LEN: usize = 1 + 1 + 255 + 2;
+const DEFAULT_MAX_STREAMS: usize = 1024;
+
+/// Carries the connections of a `Server` over a single long-lived connection to a `MuxServer`,
+/// which dials their destinations, so the number of upstream connections doesn't give away the number of clients.
+///
+/// The framing follows yamux: each connection is a stream with its own id, opened with the requested destination,
+/// and limited by a window which the receiver extends as it consumes the data. Either side may close or reset a stream.
+///
+/// ```no_run
+/// # use socks5_frontend::mux::MuxClient;
+/// # let server = socks5_frontend::Server::init("127.0.0.1:1080".parse().unwrap(), None, vec![socks5_frontend::AuthMethod::NoAuth], None, None).unwrap();
+/// let carrier = std::net::TcpStream::connect("192.0.2.1:4433").unwrap();
+/// let mux = MuxClient::new(carrier, Vec::new()).unwrap();
+/// for conn in server.flatten() {
+///     let mux = mux.clone();
+///     std::thread::spawn(move || mux.relay(conn));
+/// }
+/// ```
+///
+/// Once the connection to the `MuxServer` is lost, all of its streams are reset and no more can be opened,
+/// so a new `MuxClient` has to be created.
+#[derive(Clone)]
+pub struct MuxClient {
+    session: Arc<Session>,
+}
+
+impl MuxClient {
+    /// Starts carrying streams over `carrier`, which is passed through `transforms` like `Connection::relay_with` does.
+    /// Frames are read from a background thread.
+    pub fn new(carrier: net::TcpStream, transforms: Vec<Box<dyn StreamTransform>>) -> Result<MuxClient, io::Error> {
+        let (reader, session) = Session::new(carrier, transforms)?;
+        let reading = session.clone();
+        thread::Builder::new().name("socks5-mux".to_string()).spawn(move || {
+            reading.read_frames(reader, |_, _| ()).ignore();
+        })?;
+        return Ok(MuxClient { session: session });
+    }
+
+    /// Opens a stream to `addr`:`port`, which the `MuxServer` connects to.
+    /// Fails with `RequestFailedError` if it couldn't, with the reply code to pass on to the client.
+    pub fn open(&self, addr: &Address, port: u16) -> Result<MuxStream, ClientError> {
+        let mut request = Vec::new();
+        addr.encode(port, &mut request)?;
+        let state = self.session.register()?;
+        let stream = MuxStream::new(self.session.clone(), state.clone());
+        self.session.send_frame(TYPE_DATA, FLAG_SYN, state.id, &request)?;
+        let mut inner = state.inner.lock().unwrap();
+        loop {
+            match inner.reply {
+                Some(0) => return Ok(stream),
+                Some(rep) => match ReplyType::from_byte(rep) {
+                    Some(rep) => return Err(ClientError::RequestFailedError(rep)),
+                    None => return Err(ClientError::UnknownReplyError(rep)),
+                },
+                None if inner.reset => {
+                    drop(inner);
+                    if self.is_closed() {
+                        return Err(ClientError::StreamIOError(closed_error()));
+                    }
+                    // The server doesn't take any more streams
+                    return Err(ClientError::StreamIOError(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "the mux server reset the stream",
+                    )));
+                }
+                None => inner = state.changed.wait(inner).unwrap(),
+            }
+        }
+    }
+
+    /// Serves `conn` by opening a stream to its destination, replying to the client with the outcome,
+    /// and relaying the connection like `Connection::relay` does.
+    /// Only the CONNECT command can be served this way, clients requesting anything else are told it's not supported.
+    pub fn relay(&self, conn: UnrequitedSOCKSConnection) -> Result<RelayStats, SOCKSError> {
+        let command = conn.get_command();
+        if command != Command::Connect {
+            let err = SOCKSError::UnknownRequestCommandError(conn.get_client_address(), command.to_byte());
+            return Err(conn.refuse(ReplyType::CommandNotSupported, err));
+        }
+        let (addr, port) = conn.get_destination_address();
+        let client = conn.get_client_address();
+        match self.open(&addr, port) {
+            Ok(stream) => {
+                let conn = conn.report_success()?;
+                let upstream = Arc::new(stream.clone());
+                return Ok(conn.relay_halves(Box::new(stream.clone()), Box::new(stream), upstream)?);
+            }
+            Err(err) => {
+                let err = DialError::Upstream(err);
+                let rep = err.reply();
+                return Err(conn.refuse(rep, SOCKSError::DestinationError(client, err)));
+            }
+        }
+    }
+
+    /// Returns whether the connection to the `MuxServer` was lost.
+    pub fn is_closed(&self) -> bool {
+        return self.session.streams.lock().unwrap().closed;
+    }
+
+    /// Resets all streams and closes the connection to the `MuxServer`.
+    pub fn close(&self) {
+        self.session.close();
+    }
+}
+
+/// Serves the connections of `MuxClient`s, dialing the destination of every stream they open.
+pub struct MuxServer {
+    dialer: Arc<Dialer>,
+    max_streams: usize,
+}
+
+impl MuxServer {
+    /// Creates a server connecting to destinations with `dialer`, whose `DestinationGuard` should be set
+    /// unless every client is trusted to reach the server's internal network.
+    pub fn new(dialer: Dialer) -> MuxServer {
+        return MuxServer {
+            dialer: Arc::new(dialer),
+            max_streams: DEFAULT_MAX_STREAMS,
+        };
+    }
+
+    /// Limits how many streams may be open at once on each connection, 1024 by default.
+    /// Every stream takes a thread and a connection to its destination, so this keeps a single client from exhausting them.
+    /// Streams opened beyond the limit are reset, which their clients see as a general failure.
+    pub fn set_max_streams(&mut self, max: usize) {
+        self.max_streams = max;
+    }
+
+    /// Serves the streams opened over `carrier` until it's closed, each on its own thread.
+    /// The carrier is passed through `transforms`, which need to match those of the `MuxClient`.
+    pub fn serve(&self, carrier: net::TcpStream, transforms: Vec<Box<dyn StreamTransform>>) -> Result<(), io::Error> {
+        let (reader, session) = Session::new(carrier, transforms)?;
+        return session.read_frames(reader, |stream, (addr, port)| {
+            // The new stream counts already
+            if stream.handle.session.open_count() > self.max_streams {
+                stream.shutdown(net::Shutdown::Both).ignore();
+                return;
+            }
+            let dialer = self.dialer.clone();
+            thread::spawn(move || serve_stream(stream, &dialer, &addr, port).ignore());
+        });
+    }
+
+    /// Accepts connections from `MuxClient`s on `listener` from a background thread, and serves each on its own thread,
+    /// passing it through the transforms `make_transforms` returns.
+    pub fn serve_listener<F>(self: Arc<Self>, listener: net::TcpListener, make_transforms: F) -> Result<(), io::Error>
+    where
+        F: Fn() -> Vec<Box<dyn StreamTransform>> + Send + Sync + 'static,
+    {
+        let make_transforms = Arc::new(make_transforms);
+        let mut backoff = AcceptBackoff::new();
+        thread::Builder::new().name("socks5-mux-accept".to_string()).spawn(move || loop {
+            if let Some((carrier, _)) = backoff.check(listener.accept()) {
+                let server = self.clone();
+                let make_transforms = make_transforms.clone();
+                thread::spawn(move || server.serve(carrier, make_transforms()).ignore());
+            }
+        })?;
+        return Ok(());
+    }
+}
+
+/// A stream carried over the connection between a `MuxClient` and a `MuxServer`.
+///
+/// Clones refer to the same stream, so it can be read and written from different threads.
+/// Shutting down the writing side tells the peer no more data follows, shutting down reading resets the stream.
+/// The stream is reset once the last clone is dropped, unless both sides closed it already.
+#[derive(Clone)]
+pub struct MuxStream {
+    handle: Arc<StreamHandle>,
+}
+
+// Resets the stream once the last clone of a `MuxStream` is dropped
+struct StreamHandle {
+    session: Arc<Session>,
+    state: Arc<StreamState>,
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        let finished = {
+            let mut inner = self.state.inner.lock().unwrap();
+            let finished = inner.reset || (inner.fin_sent && inner.fin_received);
+            inner.reset = true;
+            finished
+        };
+        self.session.remove(self.state.id);
+        if !finished {
+            self.session.send_frame(TYPE_DATA, FLAG_RST, self.state.id, &[]).ignore();
+        }
+    }
+}
+
+impl MuxStream {
+    fn new(session: Arc<Session>, state: Arc<StreamState>) -> MuxStream {
+        return MuxStream {
+            handle: Arc::new(StreamHandle {
+                session: session,
+                state: state,
+            }),
+        };
+    }
+
+    pub fn shutdown(&self, how: net::Shutdown) -> Result<(), io::Error> {
+        let state = &self.handle.state;
+        let flag = {
+            let mut inner = state.inner.lock().unwrap();
+            if inner.reset {
+                return Ok(());
+            }
+            match how {
+                net::Shutdown::Write if inner.fin_sent => return Ok(()),
+                net::Shutdown::Write => {
+                    inner.fin_sent = true;
+                    FLAG_FIN
+                }
+                net::Shutdown::Read | net::Shutdown::Both => {
+                    inner.reset = true;
+                    FLAG_RST
+                }
+            }
+        };
+        state.changed.notify_all();
+        if flag == FLAG_RST {
+            self.handle.session.remove(state.id);
+        }
+        return self.handle.session.send_frame(TYPE_DATA, flag, state.id, &[]);
+    }
+}
+
+impl Read for MuxStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let state = &self.handle.state;
+        let mut inner = state.inner.lock().unwrap();
+        while inner.received.is_empty() {
+            if inner.reset {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "the mux stream was reset"));
+            }
+            if inner.fin_received {
+                return Ok(0);
+            }
+            inner = state.changed.wait(inner).unwrap();
+        }
+        let len = buf.len().min(inner.received.len());
+        for (dst, src) in buf.iter_mut().zip(inner.received.drain(..len)) {
+            *dst = src;
+        }
+        // Let the peer send more once half the window was consumed
+        inner.consumed += len as u32;
+        let update = if inner.consumed >= INITIAL_WINDOW / 2 {
+            let update = inner.consumed;
+            inner.receive_window += update;
+            inner.consumed = 0;
+            Some(update)
+        } else {
+            None
+        };
+        drop(inner);
+        if let Some(update) = update {
+            self.handle.session.send_window_update(state.id, update)?;
+        }
+        return Ok(len);
+    }
+}
+
+impl Write for MuxStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let state = &self.handle.state;
+        let mut inner = state.inner.lock().unwrap();
+        loop {
+            if inner.reset {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "the mux stream was reset"));
+            }
+            if inner.fin_sent {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the mux stream was shut down for writing"));
+            }
+            if inner.send_window > 0 {
+                break;
+            }
+            inner = state.changed.wait(inner).unwrap();
+        }
+        let len = buf.len().min(inner.send_window as usize).min(MAX_DATA_LEN);
+        inner.send_window -= len as u32;
+        drop(inner);
+        self.handle.session.send_frame(TYPE_DATA, 0, state.id, &buf[..len])?;
+        return Ok(len);
+    }
+
+    // Every frame is flushed as it's sent
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl Upstream for MuxStream {
+    fn shutdown(&self, how: net::Shutdown) -> Result<(), io::Error> {
+        return MuxStream::shutdown(self, how);
+    }
+}
+
+// The connection between a `MuxClient` and a `MuxServer`, shared by both sides
+struct Session {
+    // Frames are written whole while holding the lock, which is never taken while a stream's state is locked
+    writer: Mutex<Box<dyn Write + Send>>,
+    carrier: net::TcpStream,
+    streams: Mutex<Streams>,
+}
+
+struct Streams {
+    open: HashMap<u32, Arc<StreamState>>,
+    // Clients open streams with odd ids, as in yamux. Servers keep track of the next id to expect
+    next_id: u32,
+    closed: bool,
+}
+
+struct StreamState {
+    id: u32,
+    inner: Mutex<StreamInner>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct StreamInner {
+    received: VecDeque<u8>,
+    // How much the peer may still send
+    receive_window: u32,
+    // How much was read since the peer was last granted more
+    consumed: u32,
+    send_window: u32,
+    fin_sent: bool,
+    fin_received: bool,
+    reset: bool,
+    // The reply code the server sent once it tried to connect to the destination
+    reply: Option<u8>,
+}
+
+impl Session {
+    fn new(carrier: net::TcpStream, mut transforms: Vec<Box<dyn StreamTransform>>) -> Result<(Box<dyn Read + Send>, Arc<Session>), io::Error> {
+        let mut reader: Box<dyn Read + Send> = Box::new(carrier.try_clone()?);
+        let mut writer: Box<dyn Write + Send> = Box::new(carrier.try_clone()?);
+        for transform in transforms.iter_mut() {
+            reader = transform.wrap_reader(reader);
+            writer = transform.wrap_writer(writer);
+        }
+        let session = Arc::new(Session {
+            writer: Mutex::new(writer),
+            carrier: carrier,
+            streams: Mutex::new(Streams {
+                open: HashMap::new(),
+                next_id: 1,
+                closed: false,
+            }),
+        });
+        return Ok((reader, session));
+    }
+
+    fn new_state(id: u32) -> Arc<StreamState> {
+        return Arc::new(StreamState {
+            id: id,
+            inner: Mutex::new(StreamInner {
+                receive_window: INITIAL_WINDOW,
+                send_window: INITIAL_WINDOW,
+                ..Default::default()
+            }),
+            changed: Condvar::new(),
+        });
+    }
+
+    // Allocates a stream to open
+    fn register(&self) -> Result<Arc<StreamState>, io::Error> {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.closed {
+            return Err(closed_error());
+        }
+        let id = streams.next_id;
+        streams.next_id = id.checked_add(2).ok_or_else(|| io::Error::other("the mux connection ran out of stream ids"))?;
+        let state = Session::new_state(id);
+        streams.open.insert(id, state.clone());
+        return Ok(state);
+    }
+
+    fn open_count(&self) -> usize {
+        return self.streams.lock().unwrap().open.len();
+    }
+
+    fn remove(&self, id: u32) {
+        self.streams.lock().unwrap().open.remove(&id);
+    }
+
+    fn get(&self, id: u32) -> Option<Arc<StreamState>> {
+        return self.streams.lock().unwrap().open.get(&id).cloned();
+    }
+
+    fn send_frame(&self, kind: u8, flags: u16, id: u32, payload: &[u8]) -> Result<(), io::Error> {
+        return self.send(kind, flags, id, payload.len() as u32, payload);
+    }
+
+    fn send_window_update(&self, id: u32, update: u32) -> Result<(), io::Error> {
+        return self.send(TYPE_WINDOW_UPDATE, 0, id, update, &[]);
+    }
+
+    fn send(&self, kind: u8, flags: u16, id: u32, length: u32, payload: &[u8]) -> Result<(), io::Error> {
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.push(VERSION);
+        frame.push(kind);
+        frame.extend_from_slice(&flags.to_be_bytes());
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(payload);
+        let mut writer = self.writer.lock().unwrap();
+        let result = writer.write_all(&frame).and_then(|_| writer.flush());
+        drop(writer);
+        if result.is_err() {
+            self.close();
+        }
+        return result;
+    }
+
+    // Reads frames until the connection is closed, handing the streams the peer opens to `accept` along with their destination
+    fn read_frames<F: FnMut(MuxStream, (Address, u16))>(self: &Arc<Self>, mut reader: Box<dyn Read + Send>, mut accept: F) -> Result<(), io::Error> {
+        let result = self.read_frames_until_closed(&mut reader, &mut accept);
+        self.close();
+        return result;
+    }
+
+    fn read_frames_until_closed<F: FnMut(MuxStream, (Address, u16))>(self: &Arc<Self>, reader: &mut Box<dyn Read + Send>, accept: &mut F) -> Result<(), io::Error> {
+        loop {
+            let mut header = [0; HEADER_LEN];
+            match reader.read_exact(&mut header) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let kind = header[1];
+            let flags = u16::from_be_bytes([header[2], header[3]]);
+            let id = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+            if header[0] != VERSION {
+                return Err(protocol_error("unsupported version"));
+            }
+            match kind {
+                TYPE_DATA => {
+                    let max_len = if flags & (FLAG_SYN | FLAG_ACK) != 0 { MAX_OPEN_LEN } else { MAX_DATA_LEN };
+                    if length as usize > max_len {
+                        return Err(protocol_error("frame too long"));
+                    }
+                    let mut payload = vec![0; length as usize];
+                    reader.read_exact(&mut payload)?;
+                    self.receive_data(flags, id, payload, accept)?;
+                }
+                TYPE_WINDOW_UPDATE => {
+                    if let Some(state) = self.get(id) {
+                        let mut inner = state.inner.lock().unwrap();
+                        inner.send_window = inner.send_window.saturating_add(length);
+                        state.changed.notify_all();
+                    }
+                }
+                TYPE_GO_AWAY => return Ok(()),
+                _ => return Err(protocol_error("unknown frame type")),
+            }
+        }
+    }
+
+    fn receive_data<F: FnMut(MuxStream, (Address, u16))>(self: &Arc<Self>, flags: u16, id: u32, payload: Vec<u8>, accept: &mut F) -> Result<(), io::Error> {
+        if flags & FLAG_SYN != 0 {
+            // Only clients open streams, and only with ids they didn't use yet
+            let state = Session::new_state(id);
+            {
+                let mut streams = self.streams.lock().unwrap();
+                if id % 2 != 1 || id < streams.next_id {
+                    return Err(protocol_error("invalid stream id"));
+                }
+                // Ids must not wrap around, which would let the client reuse them
+                streams.next_id = id.checked_add(2).ok_or_else(|| protocol_error("invalid stream id"))?;
+                streams.open.insert(id, state.clone());
+            }
+            let stream = MuxStream::new(self.clone(), state);
+            match Address::decode(&mut payload.as_slice()) {
+                Ok(Ok(destination)) => accept(stream, destination),
+                _ => stream.shutdown(net::Shutdown::Both)?,
+            }
+            return Ok(());
+        }
+        let state = match self.get(id) {
+            Some(val) => val,
+            // The stream was reset in the meantime
+            None => return Ok(()),
+        };
+        let mut inner = state.inner.lock().unwrap();
+        if flags & FLAG_ACK != 0 {
+            inner.reply = Some(payload.first().copied().unwrap_or(ReplyType::GeneralSocksServerFailure.to_byte()));
+        } else if !payload.is_empty() {
+            if payload.len() as u32 > inner.receive_window {
+                return Err(protocol_error("the peer exceeded the window"));
+            }
+            inner.receive_window -= payload.len() as u32;
+            inner.received.extend(payload);
+        }
+        if flags & FLAG_FIN != 0 {
+            inner.fin_received = true;
+        }
+        let reset = flags & FLAG_RST != 0;
+        if reset {
+            inner.reset = true;
+        }
+        drop(inner);
+        state.changed.notify_all();
+        if reset {
+            self.remove(id);
+        }
+        return Ok(());
+    }
+
+    // Resets all streams and closes the carrier
+    fn close(&self) {
+        let open: Vec<Arc<StreamState>> = {
+            let mut streams = self.streams.lock().unwrap();
+            streams.closed = true;
+            streams.open.drain().map(|(_, state)| state).collect()
+        };
+        for state in open {
+            state.inner.lock().unwrap().reset = true;
+            state.changed.notify_all();
+        }
+        self.carrier.shutdown(net::Shutdown::Both).ignore();
+    }
+}
+
+// Dials the destination of a stream a client opened, tells the client the outcome and relays between the two
+fn serve_stream(stream: MuxStream, dialer: &Dialer, addr: &Address, port: u16) -> Result<(), io::Error> {
+    let id = stream.handle.state.id;
+    let upstream = match dialer.dial(addr, port) {
+        Ok(val) => val,
+        Err(err) => {
+            return stream.handle.session.send_frame(TYPE_DATA, FLAG_ACK, id, &[err.reply().to_byte()]);
+        }
+    };
+    stream.handle.session.send_frame(TYPE_DATA, FLAG_ACK, id, &[ReplyType::Succeeded.to_byte()])?;
+
+    let mut sending_stream = stream.clone();
+    let mut sending_upstream = upstream.try_clone()?;
+    let sending = thread::spawn(move || {
+        match io::copy(&mut sending_stream, &mut sending_upstream) {
+            Ok(_) => sending_upstream.shutdown(net::Shutdown::Write).ignore(),
+            Err(_) => {
+                sending_upstream.shutdown(net::Shutdown::Both).ignore();
+                sending_stream.shutdown(net::Shutdown::Both).ignore();
+            }
+        }
+    });
+    let mut receiving_stream = stream;
+    let mut receiving_upstream = upstream;
+    match io::copy(&mut receiving_upstream, &mut receiving_stream) {
+        Ok(_) => receiving_stream.shutdown(net::Shutdown::Write).ignore(),
+        Err(_) => {
+            receiving_stream.shutdown(net::Shutdown::Both).ignore();
+            receiving_upstream.shutdown(net::Shutdown::Both).ignore();
+        }
+    }
+    sending.join().ignore();
+    return Ok(());
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("the mux peer violated the protocol: {}", msg));
+}
+
+fn closed_error() -> io::Error {
+    return io::Error::new(io::ErrorKind::NotConnected, "the mux connection was closed");
+}
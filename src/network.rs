@@ -0,0 +1,118 @@
+use std::fmt;
+use std::net;
+use std::str::FromStr;
+
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fc00::/7`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct IpNetwork {
+    addr: net::IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Creates the network of all addresses sharing the first `prefix_len` bits with `addr`.
+    /// Returns `None` if `prefix_len` is longer than the address.
+    pub fn new(addr: net::IpAddr, prefix_len: u8) -> Option<IpNetwork> {
+        let max_len = match addr {
+            net::IpAddr::V4(_) => 32,
+            net::IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return None;
+        }
+        // Clear the host bits, so that equal networks compare equal
+        let addr = match addr {
+            net::IpAddr::V4(ip) => net::IpAddr::V4((u32::from(ip) & v4_mask(prefix_len)).into()),
+            net::IpAddr::V6(ip) => net::IpAddr::V6((u128::from(ip) & v6_mask(prefix_len)).into()),
+        };
+        return Some(IpNetwork {
+            addr: addr,
+            prefix_len: prefix_len,
+        });
+    }
+
+    pub fn addr(&self) -> net::IpAddr {
+        return self.addr;
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        return self.prefix_len;
+    }
+
+    /// Returns whether `ip` lies within this network.
+    /// IPv4 addresses and the IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) they map to are interchangeable:
+    /// either form lies within `10.0.0.0/8` as well as within `::ffff:0:0/96` and `::/0`.
+    pub fn contains(&self, ip: net::IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (net::IpAddr::V4(net), net::IpAddr::V4(ip)) => {
+                return u32::from(ip) & v4_mask(self.prefix_len) == u32::from(net);
+            }
+            (net::IpAddr::V6(net), net::IpAddr::V4(ip)) => {
+                return u128::from(ip.to_ipv6_mapped()) & v6_mask(self.prefix_len) == u128::from(net);
+            }
+            (net::IpAddr::V6(net), net::IpAddr::V6(ip)) => {
+                return u128::from(ip) & v6_mask(self.prefix_len) == u128::from(net);
+            }
+            (net::IpAddr::V4(_), net::IpAddr::V6(_)) => return false,
+        }
+    }
+}
+
+impl From<net::IpAddr> for IpNetwork {
+    fn from(addr: net::IpAddr) -> IpNetwork {
+        let prefix_len = match addr {
+            net::IpAddr::V4(_) => 32,
+            net::IpAddr::V6(_) => 128,
+        };
+        return IpNetwork {
+            addr: addr,
+            prefix_len: prefix_len,
+        };
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Returned when parsing an `IpNetwork` fails.
+#[derive(PartialEq, Debug, Clone)]
+pub struct NetworkParseError(String);
+
+impl fmt::Display for NetworkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a valid IP network", self.0)
+    }
+}
+
+impl std::error::Error for NetworkParseError {}
+
+impl FromStr for IpNetwork {
+    type Err = NetworkParseError;
+
+    /// Parses `addr/prefix_len`. A bare address is parsed as a network containing only that address.
+    fn from_str(s: &str) -> Result<IpNetwork, NetworkParseError> {
+        let err = || NetworkParseError(s.to_string());
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => {
+                let addr: net::IpAddr = addr.parse().map_err(|_| err())?;
+                let prefix_len: u8 = prefix_len.parse().map_err(|_| err())?;
+                return IpNetwork::new(addr, prefix_len).ok_or_else(err);
+            }
+            None => {
+                let addr: net::IpAddr = s.parse().map_err(|_| err())?;
+                return Ok(IpNetwork::from(addr));
+            }
+        }
+    }
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    return u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    return u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+}
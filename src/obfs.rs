@@ -0,0 +1,546 @@
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, Tag};
+use hkdf::Hkdf;
+use ignore_result::Ignore;
+use sha2::Sha256;
+
+use crate::listener::AcceptBackoff;
+use crate::pt::PtArguments;
+use crate::transform::StreamTransform;
+
+const SALT_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+// The encrypted length of a frame's body
+const HEADER_LEN: usize = 2 + TAG_LEN;
+// The body holds the length of the payload, the payload and the padding
+const MAX_BODY_LEN: usize = 16 * 1024;
+const MAX_PAYLOAD_LEN: usize = MAX_BODY_LEN - 2;
+const MIN_SECRET_LEN: usize = 16;
+const DEFAULT_MAX_PADDING: u16 = 256;
+
+// Distinguishes the keys of both directions, so frames can't be reflected back to their sender
+const CLIENT_INFO: &[u8] = b"socks5_frontend obfs v1 client";
+const SERVER_INFO: &[u8] = b"socks5_frontend obfs v1 server";
+
+/// The shared secret and settings of the obfuscating transport.
+///
+/// Data is sent in frames, each consisting of its length and a body holding the payload followed by random padding.
+/// Both are encrypted with ChaCha20-Poly1305, so all the bytes on the wire look random.
+/// Each direction is keyed from the secret and a random salt its sender transmits first.
+///
+/// This is a reference implementation to build transports on, it doesn't attempt to hide timing or the sizes of the first frames.
+#[derive(Clone)]
+pub struct ObfsConfig {
+    secret: Vec<u8>,
+    max_padding: u16,
+}
+
+impl ObfsConfig {
+    /// Creates a config from a shared secret of at least 16 bytes, padding each frame with up to 256 bytes.
+    pub fn new(secret: &[u8]) -> Result<ObfsConfig, io::Error> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the secret needs at least {} bytes", MIN_SECRET_LEN)));
+        }
+        return Ok(ObfsConfig {
+            secret: secret.to_vec(),
+            max_padding: DEFAULT_MAX_PADDING,
+        });
+    }
+
+    /// Reads the config from the arguments a pluggable transport client passed:
+    /// `secret` is the hex-encoded secret and `max-padding` optionally sets the padding.
+    pub fn from_pt_arguments(args: &PtArguments) -> Result<ObfsConfig, io::Error> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        let secret = args.get("secret").ok_or_else(|| invalid("the secret argument is missing"))?;
+        let secret = decode_hex(secret).ok_or_else(|| invalid("the secret argument isn't valid hex"))?;
+        let mut config = ObfsConfig::new(&secret)?;
+        if let Some(max_padding) = args.get("max-padding") {
+            config.max_padding = max_padding.parse().map_err(|_| invalid("the max-padding argument isn't a valid number"))?;
+        }
+        return Ok(config);
+    }
+
+    /// Returns the arguments `from_pt_arguments` parses, for clients to pass in their SOCKS credentials.
+    pub fn to_pt_arguments(&self) -> PtArguments {
+        let mut args = PtArguments::new();
+        args.push("secret", &encode_hex(&self.secret));
+        args.push("max-padding", &self.max_padding.to_string());
+        return args;
+    }
+
+    /// Sets how many bytes of padding each frame has at most. The padding of each frame is picked at random.
+    pub fn set_max_padding(&mut self, max_padding: u16) {
+        self.max_padding = max_padding;
+    }
+
+    fn cipher(&self, salt: &[u8], info: &[u8]) -> ChaCha20Poly1305 {
+        let mut key = [0; 32];
+        // A 32 byte key is always short enough to expand to
+        Hkdf::<Sha256>::new(Some(salt), &self.secret).expand(info, &mut key).unwrap();
+        return ChaCha20Poly1305::new(&key.into());
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    fn sending_info(self) -> &'static [u8] {
+        match self {
+            Role::Client => return CLIENT_INFO,
+            Role::Server => return SERVER_INFO,
+        }
+    }
+
+    fn receiving_info(self) -> &'static [u8] {
+        match self {
+            Role::Client => return SERVER_INFO,
+            Role::Server => return CLIENT_INFO,
+        }
+    }
+}
+
+// Encrypts with a counter as the nonce, which is never reused since each direction has its own key
+struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0; 12];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        return nonce.into();
+    }
+
+    fn seal(&mut self, data: &mut Vec<u8>) -> Result<(), io::Error> {
+        let nonce = self.next_nonce();
+        let tag = self.cipher.encrypt_in_place_detached(&nonce, b"", data).map_err(|_| io::Error::other("failed to encrypt a frame"))?;
+        data.extend_from_slice(&tag);
+        return Ok(());
+    }
+
+    // Decrypts `data`, which ends with the tag, and strips the tag
+    fn open(&mut self, data: &mut Vec<u8>) -> Result<(), io::Error> {
+        let nonce = self.next_nonce();
+        let tag = Tag::clone_from_slice(&data[data.len() - TAG_LEN..]);
+        data.truncate(data.len() - TAG_LEN);
+        self.cipher
+            .decrypt_in_place_detached(&nonce, b"", data, &tag)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "an obfuscated frame failed authentication"))?;
+        return Ok(());
+    }
+}
+
+/// Decrypts the frames read from the underlying reader.
+///
+/// Fails with `InvalidData` if a frame wasn't sent by a peer with the same secret, or was tampered with.
+pub struct ObfsReader<R: Read> {
+    inner: R,
+    config: ObfsConfig,
+    role: Role,
+    // `None` until the peer's salt was read
+    sealer: Option<Sealer>,
+    payload: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> ObfsReader<R> {
+    fn new(inner: R, config: ObfsConfig, role: Role) -> ObfsReader<R> {
+        return ObfsReader {
+            inner: inner,
+            config: config,
+            role: role,
+            sealer: None,
+            payload: Vec::new(),
+            pos: 0,
+        };
+    }
+
+    pub fn get_ref(&self) -> &R {
+        return &self.inner;
+    }
+
+    // Reads the payload of the next frame which has one, returns false if the peer closed the connection between frames
+    fn read_frame(&mut self) -> Result<bool, io::Error> {
+        loop {
+            if self.sealer.is_none() {
+                let mut salt = [0; SALT_LEN];
+                if !read_frame_part(&mut self.inner, &mut salt)? {
+                    return Ok(false);
+                }
+                self.sealer = Some(Sealer {
+                    cipher: self.config.cipher(&salt, self.role.receiving_info()),
+                    counter: 0,
+                });
+            }
+            let sealer = self.sealer.as_mut().unwrap();
+            let mut header = vec![0; HEADER_LEN];
+            if !read_frame_part(&mut self.inner, &mut header)? {
+                return Ok(false);
+            }
+            sealer.open(&mut header)?;
+            let body_len = usize::from(u16::from_be_bytes([header[0], header[1]]));
+            if !(2..=MAX_BODY_LEN).contains(&body_len) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "an obfuscated frame has an invalid length"));
+            }
+            let mut body = vec![0; body_len + TAG_LEN];
+            self.inner.read_exact(&mut body)?;
+            sealer.open(&mut body)?;
+            let payload_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+            if payload_len > body_len - 2 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "an obfuscated frame has an invalid payload length"));
+            }
+            // Frames may consist of padding only
+            if payload_len > 0 {
+                body.truncate(2 + payload_len);
+                body.drain(..2);
+                self.payload = body;
+                self.pos = 0;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for ObfsReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.payload.len() && !self.read_frame()? {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.payload.len() - self.pos);
+        buf[..len].copy_from_slice(&self.payload[self.pos..self.pos + len]);
+        self.pos += len;
+        return Ok(len);
+    }
+}
+
+/// Encrypts what's written to it into frames on the underlying writer.
+///
+/// Data is buffered until it fills a frame or the writer is flushed, so it needs to be flushed for the peer to receive it.
+pub struct ObfsWriter<W: Write> {
+    inner: W,
+    config: ObfsConfig,
+    role: Role,
+    // `None` until the salt was sent along with the first frame
+    sealer: Option<Sealer>,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> ObfsWriter<W> {
+    fn new(inner: W, config: ObfsConfig, role: Role) -> ObfsWriter<W> {
+        return ObfsWriter {
+            inner: inner,
+            config: config,
+            role: role,
+            sealer: None,
+            pending: Vec::new(),
+        };
+    }
+
+    pub fn get_ref(&self) -> &W {
+        return &self.inner;
+    }
+
+    // Sends up to a frame's worth of the pending data
+    fn send_frame(&mut self) -> Result<(), io::Error> {
+        let mut out = Vec::new();
+        if self.sealer.is_none() {
+            let mut salt = [0; SALT_LEN];
+            random(&mut salt)?;
+            out.extend_from_slice(&salt);
+            self.sealer = Some(Sealer {
+                cipher: self.config.cipher(&salt, self.role.sending_info()),
+                counter: 0,
+            });
+        }
+        let sealer = self.sealer.as_mut().unwrap();
+        let payload_len = self.pending.len().min(MAX_PAYLOAD_LEN);
+        let mut padding = [0; 2];
+        random(&mut padding)?;
+        let padding_len = (usize::from(u16::from_be_bytes(padding)) % (usize::from(self.config.max_padding) + 1)).min(MAX_PAYLOAD_LEN - payload_len);
+
+        let mut body = Vec::with_capacity(2 + payload_len + padding_len + TAG_LEN);
+        body.extend_from_slice(&(payload_len as u16).to_be_bytes());
+        body.extend(self.pending.drain(..payload_len));
+        body.resize(2 + payload_len + padding_len, 0);
+        let mut header = (body.len() as u16).to_be_bytes().to_vec();
+        sealer.seal(&mut header)?;
+        sealer.seal(&mut body)?;
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&body);
+        return self.inner.write_all(&out);
+    }
+}
+
+impl<W: Write> Write for ObfsWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while self.pending.len() >= MAX_PAYLOAD_LEN {
+            self.send_frame()?;
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            self.send_frame()?;
+        }
+        return self.inner.flush();
+    }
+}
+
+/// Obfuscates the connections `Connection::relay_with` relays, as the client side of the transport unless created with `server`.
+///
+/// The config is usually taken from the arguments the client passed, see `Server::accept_pt_arguments`:
+///
+/// ```no_run
+/// # use socks5_frontend::obfs::{ObfsConfig, ObfsTransform};
+/// # let mut server = socks5_frontend::Server::init("127.0.0.1:1080".parse().unwrap(), None, vec![socks5_frontend::AuthMethod::UsernamePassword], None, None).unwrap();
+/// server.accept_pt_arguments();
+/// for conn in server.flatten() {
+///     let config = match conn.get_pt_arguments().map(ObfsConfig::from_pt_arguments) {
+///         Some(Ok(config)) => config,
+///         _ => {
+///             let _ = conn.report_connection_not_allowed();
+///             continue;
+///         }
+///     };
+///     if let Ok((conn, upstream)) = conn.connect_keeping_early_data() {
+///         std::thread::spawn(move || conn.relay_with(upstream, vec![Box::new(ObfsTransform::new(config))]));
+///     }
+/// }
+/// ```
+pub struct ObfsTransform {
+    config: ObfsConfig,
+    role: Role,
+}
+
+impl ObfsTransform {
+    pub fn new(config: ObfsConfig) -> ObfsTransform {
+        return ObfsTransform {
+            config: config,
+            role: Role::Client,
+        };
+    }
+
+    /// Creates the server side of the transport, for servers which don't accept their connections through `ObfsListener`,
+    /// like `MuxServer`.
+    pub fn server(config: ObfsConfig) -> ObfsTransform {
+        return ObfsTransform {
+            config: config,
+            role: Role::Server,
+        };
+    }
+}
+
+impl StreamTransform for ObfsTransform {
+    fn wrap_reader(&mut self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        return Box::new(ObfsReader::new(reader, self.config.clone(), self.role));
+    }
+
+    fn wrap_writer(&mut self, writer: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        return Box::new(ObfsWriter::new(writer, self.config.clone(), self.role));
+    }
+}
+
+/// The server side of the transport, which accepts connections from `ObfsTransform`.
+pub struct ObfsListener {
+    listener: net::TcpListener,
+    config: ObfsConfig,
+}
+
+impl ObfsListener {
+    pub fn bind(addr: net::SocketAddr, config: ObfsConfig) -> Result<ObfsListener, io::Error> {
+        return Ok(ObfsListener::new(net::TcpListener::bind(addr)?, config));
+    }
+
+    pub fn new(listener: net::TcpListener, config: ObfsConfig) -> ObfsListener {
+        return ObfsListener {
+            listener: listener,
+            config: config,
+        };
+    }
+
+    pub fn local_addr(&self) -> Result<net::SocketAddr, io::Error> {
+        return self.listener.local_addr();
+    }
+
+    /// Accepts a client. Nothing is read from it yet, so a client with the wrong secret is only detected once reading fails.
+    pub fn accept(&self) -> Result<(ObfsStream, net::SocketAddr), io::Error> {
+        let (stream, addr) = self.listener.accept()?;
+        return Ok((ObfsStream::new(stream, self.config.clone())?, addr));
+    }
+
+    /// Relays every client to `target` from a background thread, each on its own thread.
+    /// Clients which fail to authenticate their frames are disconnected.
+    ///
+    /// Forwarding goes on until `stop` is called on the returned handle.
+    pub fn forward(self, target: net::SocketAddr) -> Result<ForwardHandle, io::Error> {
+        let mut wake_addr = self.local_addr()?;
+        if wake_addr.ip().is_unspecified() {
+            match wake_addr {
+                net::SocketAddr::V4(_) => wake_addr.set_ip(net::Ipv4Addr::LOCALHOST.into()),
+                net::SocketAddr::V6(_) => wake_addr.set_ip(net::Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        let handle = ForwardHandle {
+            stopped: Arc::new(AtomicBool::new(false)),
+            wake_addr: wake_addr,
+        };
+        let stopped = handle.stopped.clone();
+        let mut backoff = AcceptBackoff::new();
+        thread::Builder::new().name("socks5-obfs".to_string()).spawn(move || loop {
+            let accepted = self.accept();
+            // Returning drops and thereby closes the listener
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            // A failed client is of no concern to the others
+            if let Some((stream, _)) = backoff.check(accepted) {
+                thread::spawn(move || forward_stream(stream, target).ignore());
+            }
+        })?;
+        return Ok(handle);
+    }
+}
+
+/// Stops an `ObfsListener` from forwarding clients, see `ObfsListener::forward`.
+#[derive(Clone)]
+pub struct ForwardHandle {
+    stopped: Arc<AtomicBool>,
+    // Where to connect to in order to wake up the thread blocked on accepting
+    wake_addr: net::SocketAddr,
+}
+
+impl ForwardHandle {
+    /// Stops accepting clients and closes the listener.
+    /// Clients which have already been accepted keep being forwarded until either side closes their connection.
+    pub fn stop(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        net::TcpStream::connect_timeout(&self.wake_addr, time::Duration::from_secs(1)).ignore();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        return self.stopped.load(Ordering::SeqCst);
+    }
+}
+
+/// A connection accepted by `ObfsListener`.
+pub struct ObfsStream {
+    reader: ObfsReader<net::TcpStream>,
+    writer: ObfsWriter<net::TcpStream>,
+}
+
+impl ObfsStream {
+    fn new(stream: net::TcpStream, config: ObfsConfig) -> Result<ObfsStream, io::Error> {
+        return Ok(ObfsStream {
+            reader: ObfsReader::new(stream.try_clone()?, config.clone(), Role::Server),
+            writer: ObfsWriter::new(stream, config, Role::Server),
+        });
+    }
+
+    /// Splits the stream into its halves, so each direction can be served by its own thread.
+    pub fn split(self) -> (ObfsReader<net::TcpStream>, ObfsWriter<net::TcpStream>) {
+        return (self.reader, self.writer);
+    }
+}
+
+impl Read for ObfsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.reader.read(buf);
+    }
+}
+
+impl Write for ObfsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.writer.write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.writer.flush();
+    }
+}
+
+fn forward_stream(stream: ObfsStream, target: net::SocketAddr) -> Result<(), io::Error> {
+    let (mut client_read, mut client_write) = stream.split();
+    let mut upstream_read = net::TcpStream::connect(target)?;
+    let mut upstream_write = upstream_read.try_clone()?;
+    let sending = thread::spawn(move || {
+        match copy_flushing(&mut client_read, &mut upstream_write) {
+            Ok(()) => upstream_write.shutdown(net::Shutdown::Write).ignore(),
+            Err(_) => {
+                upstream_write.shutdown(net::Shutdown::Both).ignore();
+                client_read.get_ref().shutdown(net::Shutdown::Both).ignore();
+            }
+        }
+    });
+    let result = copy_flushing(&mut upstream_read, &mut client_write);
+    match result {
+        Ok(()) => client_write.get_ref().shutdown(net::Shutdown::Write).ignore(),
+        Err(_) => {
+            client_write.get_ref().shutdown(net::Shutdown::Both).ignore();
+            upstream_read.shutdown(net::Shutdown::Both).ignore();
+        }
+    }
+    sending.join().ignore();
+    return result;
+}
+
+// Copies until `src` is closed, flushing after every chunk so nothing lingers in an `ObfsWriter`
+fn copy_flushing<R: Read, W: Write>(src: &mut R, dst: &mut W) -> Result<(), io::Error> {
+    let mut buf = vec![0; MAX_PAYLOAD_LEN];
+    loop {
+        let len = match src.read(&mut buf) {
+            Ok(0) => return dst.flush(),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buf[..len])?;
+        dst.flush()?;
+    }
+}
+
+// Fills `buf`, returns false if the reader was closed before any of it was read
+fn read_frame_part<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection was closed within an obfuscated frame")),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(true);
+}
+
+fn random(buf: &mut [u8]) -> Result<(), io::Error> {
+    return getrandom::getrandom(buf).map_err(|e| io::Error::other(format!("failed to generate random bytes: {}", e)));
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    return (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect();
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use std::time;
+
+use crate::address::{Address, ClientAddress};
+use crate::auth::AuthMethod;
+use crate::command::Command;
+use crate::listener::ListenerInfo;
+use crate::reply::ReplyType;
+use crate::socks_error::SOCKSError;
+
+/// Receives events from a `Server` as connections progress, e.g. to collect metrics or write logs.
+///
+/// Every method does nothing by default, so implementations only need to override the events they care about.
+/// Methods are called from whichever thread drives the connection, so they should return quickly.
+/// Credentials are never passed to an observer.
+pub trait ServerObserver: Send + Sync {
+    /// A client connected to one of the server's listeners.
+    fn on_accepted(&self, _client: &ClientAddress, _listener: &ListenerInfo) {}
+
+    /// The authentication method the server selected, or `None` if it shares no method with the client.
+    fn on_method_negotiated(&self, _client: &ClientAddress, _method: Option<&AuthMethod>) {}
+
+    /// The client authenticated as `username`.
+    fn on_auth_succeeded(&self, _client: &ClientAddress, _username: &str) {}
+
+    /// The client supplied invalid credentials.
+    fn on_auth_failed(&self, _client: &ClientAddress) {}
+
+    /// The client requested `command` for `destination`:`port`.
+    fn on_request(&self, _client: &ClientAddress, _command: Command, _destination: &Address, _port: u16) {}
+
+    /// The server sent a reply to the client, `elapsed` after it started negotiating with the client.
+    fn on_reply(&self, _client: &ClientAddress, _reply: ReplyType, _elapsed: time::Duration) {}
+
+    /// A relay started with `Connection::relay` finished.
+    fn on_relay_finished(&self, _client: &ClientAddress, _stats: &RelayStats) {}
+
+    /// Negotiating with a client failed, the server yields `error` in place of the connection.
+    /// Errors of `connect` on the connections the server yields are reported here as well.
+    fn on_handshake_error(&self, _error: &SOCKSError) {}
+
+    /// A session ended, either because the handshake failed, the relay finished or the consumer dropped the connection.
+    /// This is called exactly once for every client the server started negotiating with.
+    fn on_session_finished(&self, _session: &SessionRecord) {}
+}
+
+/// Passes every event on to each of the observers in order, so that a server can have more than one.
+impl ServerObserver for Vec<Arc<dyn ServerObserver>> {
+    fn on_accepted(&self, client: &ClientAddress, listener: &ListenerInfo) {
+        self.iter().for_each(|observer| observer.on_accepted(client, listener));
+    }
+
+    fn on_method_negotiated(&self, client: &ClientAddress, method: Option<&AuthMethod>) {
+        self.iter().for_each(|observer| observer.on_method_negotiated(client, method));
+    }
+
+    fn on_auth_succeeded(&self, client: &ClientAddress, username: &str) {
+        self.iter().for_each(|observer| observer.on_auth_succeeded(client, username));
+    }
+
+    fn on_auth_failed(&self, client: &ClientAddress) {
+        self.iter().for_each(|observer| observer.on_auth_failed(client));
+    }
+
+    fn on_request(&self, client: &ClientAddress, command: Command, destination: &Address, port: u16) {
+        self.iter().for_each(|observer| observer.on_request(client, command, destination, port));
+    }
+
+    fn on_reply(&self, client: &ClientAddress, reply: ReplyType, elapsed: time::Duration) {
+        self.iter().for_each(|observer| observer.on_reply(client, reply, elapsed));
+    }
+
+    fn on_relay_finished(&self, client: &ClientAddress, stats: &RelayStats) {
+        self.iter().for_each(|observer| observer.on_relay_finished(client, stats));
+    }
+
+    fn on_handshake_error(&self, error: &SOCKSError) {
+        self.iter().for_each(|observer| observer.on_handshake_error(error));
+    }
+
+    fn on_session_finished(&self, session: &SessionRecord) {
+        self.iter().for_each(|observer| observer.on_session_finished(session));
+    }
+}
+
+/// The observer used when none was set, which ignores every event.
+pub(crate) struct NoopObserver;
+
+impl ServerObserver for NoopObserver {}
+
+/// What a relay transferred, as reported by `Connection::relay`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct RelayStats {
+    /// Bytes sent by the client and written to the destination, including data the client sent before the reply.
+    pub client_to_upstream: u64,
+    /// Bytes sent by the destination and written to the client.
+    pub upstream_to_client: u64,
+    /// How long the relay was running.
+    pub duration: time::Duration,
+}
+
+/// Summarizes a session from the moment the server started negotiating with the client, as passed to `on_session_finished`.
+///
+/// Fields the handshake didn't get to are `None`. Byte counts are only known for sessions relayed with `Connection::relay`,
+/// sessions the consumer relays on its own are finished when their connection is dropped or its stream is taken.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SessionRecord {
+    pub started: time::SystemTime,
+    pub duration: time::Duration,
+    pub client: ClientAddress,
+    pub user: Option<String>,
+    pub command: Option<Command>,
+    pub destination: Option<(Address, u16)>,
+    pub reply: Option<ReplyType>,
+    /// Bytes sent by the client, see `RelayStats::client_to_upstream`.
+    pub bytes_in: u64,
+    /// Bytes sent to the client, see `RelayStats::upstream_to_client`.
+    pub bytes_out: u64,
+}
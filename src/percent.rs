@@ -0,0 +1,34 @@
+use std::fmt::Write as _;
+
+use ignore_result::Ignore;
+
+// Percent-encodes everything but unreserved URI characters, so the result contains no whitespace or separators
+pub(crate) fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{:02X}", byte).ignore();
+        }
+    }
+    return encoded;
+}
+
+// Decodes a percent-encoded value, returns `None` if it's malformed or not UTF-8
+pub(crate) fn decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = value.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    return String::from_utf8(decoded).ok();
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::client::Credentials;
+
+// The longest username or password a client can send
+const MAX_CREDENTIAL_LEN: usize = 255;
+
+/// The per-connection arguments a pluggable transport client passes in the SOCKS credentials,
+/// see `Server::accept_pt_arguments`.
+///
+/// They're encoded as `key=value` pairs separated by `;`, where `\` escapes `=`, `;` and `\` itself.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct PtArguments {
+    args: Vec<(String, String)>,
+}
+
+impl PtArguments {
+    pub fn new() -> PtArguments {
+        return PtArguments::default();
+    }
+
+    /// Parses the arguments from the username and password a client sent.
+    ///
+    /// Clients put the arguments in the username and continue them in the password if they don't fit,
+    /// otherwise the password is a single NUL byte.
+    pub fn from_credentials(username: &str, password: &str) -> Result<PtArguments, PtArgumentsError> {
+        if password == "\0" {
+            return username.parse();
+        }
+        return format!("{}{}", username, password).parse();
+    }
+
+    /// Encodes the arguments into the credentials a client sends, which is the reverse of `from_credentials`.
+    /// Arguments longer than 510 bytes once encoded can't be sent.
+    pub fn to_credentials(&self) -> Credentials {
+        let encoded = self.to_string();
+        if encoded.len() <= MAX_CREDENTIAL_LEN {
+            return Credentials::new(&encoded, "\0");
+        }
+        let mut split = MAX_CREDENTIAL_LEN;
+        while !encoded.is_char_boundary(split) {
+            split -= 1;
+        }
+        return Credentials::new(&encoded[..split], &encoded[split..]);
+    }
+
+    /// Adds an argument, later ones with the same key take precedence over earlier ones.
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.args.push((key.to_string(), value.to_string()));
+    }
+
+    /// Returns the value of the last argument named `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        return self.args.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        return self.args.iter().map(|(key, value)| (key.as_str(), value.as_str()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.args.is_empty();
+    }
+}
+
+impl fmt::Display for PtArguments {
+    /// Encodes the arguments the way clients send them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (key, value)) in self.args.iter().enumerate() {
+            if index > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{}={}", escape(key), escape(value))?;
+        }
+        return Ok(());
+    }
+}
+
+impl FromStr for PtArguments {
+    type Err = PtArgumentsError;
+
+    fn from_str(s: &str) -> Result<PtArguments, PtArgumentsError> {
+        let err = || PtArgumentsError(s.len());
+        let mut args = PtArguments::new();
+        if s.is_empty() {
+            return Ok(args);
+        }
+        // The key of the current argument, once its `=` was seen
+        let mut key: Option<String> = None;
+        let mut current = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => current.push(chars.next().ok_or_else(err)?),
+                '=' if key.is_none() => key = Some(std::mem::take(&mut current)),
+                ';' => {
+                    let name = key.take().ok_or_else(err)?;
+                    args.args.push((name, std::mem::take(&mut current)));
+                }
+                _ => current.push(c),
+            }
+        }
+        let name = key.ok_or_else(err)?;
+        args.args.push((name, current));
+        if args.args.iter().any(|(name, _)| name.is_empty()) {
+            return Err(err());
+        }
+        return Ok(args);
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '=' | ';') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+/// Returned when pluggable transport arguments are malformed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PtArgumentsError(usize);
+
+impl fmt::Display for PtArgumentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The arguments may contain secrets, so only their length is kept
+        write!(f, "malformed pluggable transport arguments of {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for PtArgumentsError {}
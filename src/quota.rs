@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::access_log::civil_from_days;
+use crate::percent;
+
+const FILE_HEADER: &str = "# socks5_frontend quota usage v1";
+const DEFAULT_SAVE_INTERVAL: time::Duration = time::Duration::from_secs(60);
+const DEFAULT_SAVE_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// How many bytes a user may transfer, counting both directions.
+/// Every limit which is `None` is not enforced.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Quota {
+    /// The limit per day, which resets at midnight UTC.
+    pub daily: Option<u64>,
+    /// The limit per calendar month, which resets at midnight UTC on the first day of the month.
+    pub monthly: Option<u64>,
+}
+
+/// How many bytes a user has transferred in the current day and month.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct QuotaUsage {
+    pub daily: u64,
+    pub monthly: u64,
+}
+
+/// Keeps track of how much traffic each user has relayed, and enforces their quotas, see `Server::set_quotas`.
+///
+/// Clients which exhausted their quota are replied to with `ConnectionNotAllowed`,
+/// and relays are closed as soon as they would exceed it.
+/// Only traffic relayed with `Connection::relay` counts, clients which didn't authenticate with a username are not subject to quotas.
+pub struct QuotaStore {
+    // Where usage is persisted, if anywhere
+    path: Option<PathBuf>,
+    state: Mutex<QuotaState>,
+    // Serializes writing the file
+    saving: Mutex<()>,
+}
+
+struct QuotaState {
+    default: Quota,
+    quotas: HashMap<String, Quota>,
+    usage: HashMap<String, Usage>,
+    // Running relays save usage once either is reached, so a crash only loses what was relayed since
+    save_interval: time::Duration,
+    save_threshold: u64,
+    // Bytes counted since the usage was last saved
+    unsaved_bytes: u64,
+    last_saved: time::Instant,
+}
+
+#[derive(Clone, Copy)]
+struct Usage {
+    // Days since the epoch
+    day: i64,
+    day_bytes: u64,
+    // Months since the year 0, i.e. year * 12 + month - 1
+    month: i64,
+    month_bytes: u64,
+}
+
+impl Usage {
+    // Starts counting anew if the period of either counter has passed
+    fn roll(&mut self, (day, month): (i64, i64)) {
+        if self.day != day {
+            self.day = day;
+            self.day_bytes = 0;
+        }
+        if self.month != month {
+            self.month = month;
+            self.month_bytes = 0;
+        }
+    }
+}
+
+impl QuotaStore {
+    /// Creates a store which keeps usage in memory only, so it's reset when the process restarts.
+    pub fn new() -> QuotaStore {
+        return QuotaStore::with_path(None, HashMap::new());
+    }
+
+    /// Creates a store which persists usage to the file at `path`, loading the usage recorded there if the file exists.
+    ///
+    /// Usage is saved whenever a relay finishes, as well as when calling `save`.
+    /// Running relays save it as well every so often, see `set_save_interval`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<QuotaStore, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let usage = match fs::read_to_string(&path) {
+            Ok(contents) => parse_usage(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        return Ok(QuotaStore::with_path(Some(path), usage));
+    }
+
+    fn with_path(path: Option<PathBuf>, usage: HashMap<String, Usage>) -> QuotaStore {
+        return QuotaStore {
+            path: path,
+            state: Mutex::new(QuotaState {
+                default: Quota::default(),
+                quotas: HashMap::new(),
+                usage: usage,
+                save_interval: DEFAULT_SAVE_INTERVAL,
+                save_threshold: DEFAULT_SAVE_THRESHOLD,
+                unsaved_bytes: 0,
+                last_saved: time::Instant::now(),
+            }),
+            saving: Mutex::new(()),
+        };
+    }
+
+    /// Sets the quota of users which don't have one of their own.
+    pub fn set_default_quota(&self, quota: Quota) {
+        self.state.lock().unwrap().default = quota;
+    }
+
+    /// Sets the quota of `user`, replacing the default quota for them.
+    pub fn set_quota(&self, user: &str, quota: Quota) {
+        self.state.lock().unwrap().quotas.insert(user.to_string(), quota);
+    }
+
+    /// Makes running relays save the usage once `interval` has passed since it was last saved,
+    /// or once `threshold` bytes have been counted since then, whichever comes first.
+    /// Defaults to a minute and 16 MiB. Only applies to stores created with `open`.
+    pub fn set_save_interval(&self, interval: time::Duration, threshold: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.save_interval = interval;
+        state.save_threshold = threshold;
+    }
+
+    /// Returns how much `user` has transferred in the current day and month.
+    pub fn usage(&self, user: &str) -> QuotaUsage {
+        let state = self.state.lock().unwrap();
+        match state.usage.get(user) {
+            Some(usage) => {
+                let mut usage = *usage;
+                usage.roll(current_periods());
+                return QuotaUsage {
+                    daily: usage.day_bytes,
+                    monthly: usage.month_bytes,
+                };
+            }
+            None => return QuotaUsage::default(),
+        }
+    }
+
+    /// Returns whether `user` has used up their daily or monthly quota.
+    pub fn is_exhausted(&self, user: &str) -> bool {
+        let usage = self.usage(user);
+        let quota = self.quota(user);
+        return quota.daily.is_some_and(|max| usage.daily >= max) || quota.monthly.is_some_and(|max| usage.monthly >= max);
+    }
+
+    /// Writes the usage of all users to the file the store was opened with.
+    /// Does nothing for stores created with `new`.
+    pub fn save(&self) -> Result<(), io::Error> {
+        let path = match &self.path {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        let _saving = self.saving.lock().unwrap();
+        let contents = self.serialize();
+        // Replace the file atomically, so a crash while saving doesn't lose the previous usage
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, contents)?;
+        return fs::rename(&temp_path, path);
+    }
+
+    // Adds `len` bytes to the usage of `user`, returns whether they're still within their quota
+    fn consume(&self, user: &str, len: u64) -> bool {
+        let quota = self.quota(user);
+        let periods = current_periods();
+        let mut state = self.state.lock().unwrap();
+        let usage = state.usage.entry(user.to_string()).or_insert(Usage {
+            day: periods.0,
+            day_bytes: 0,
+            month: periods.1,
+            month_bytes: 0,
+        });
+        usage.roll(periods);
+        usage.day_bytes += len;
+        usage.month_bytes += len;
+        let within_quota =
+            !(quota.daily.is_some_and(|max| usage.day_bytes > max) || quota.monthly.is_some_and(|max| usage.month_bytes > max));
+        state.unsaved_bytes += len;
+        return within_quota;
+    }
+
+    // Saves the usage if enough has been counted or enough time has passed since it was last saved
+    fn save_if_due(&self) {
+        if self.path.is_none() {
+            return;
+        }
+        let due = {
+            let state = self.state.lock().unwrap();
+            state.unsaved_bytes >= state.save_threshold || state.last_saved.elapsed() >= state.save_interval
+        };
+        // A failure is retried when the next save is due, or when the relay finishes
+        if due {
+            self.save().ignore();
+        }
+    }
+
+    fn quota(&self, user: &str) -> Quota {
+        let state = self.state.lock().unwrap();
+        return *state.quotas.get(user).unwrap_or(&state.default);
+    }
+
+    fn serialize(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.unsaved_bytes = 0;
+        state.last_saved = time::Instant::now();
+        let mut users: Vec<(&String, &Usage)> = state.usage.iter().collect();
+        users.sort_by_key(|(user, _)| *user);
+        let mut out = String::new();
+        writeln!(out, "{}", FILE_HEADER).ignore();
+        for (user, usage) in users {
+            writeln!(out, "{} {} {} {} {}", percent::encode(user), usage.day, usage.day_bytes, usage.month, usage.month_bytes).ignore();
+        }
+        return out;
+    }
+}
+
+impl Default for QuotaStore {
+    fn default() -> QuotaStore {
+        return QuotaStore::new();
+    }
+}
+
+/// Charges the traffic of a relayed connection to the quota of its user.
+#[derive(Clone)]
+pub(crate) struct QuotaMeter {
+    store: Arc<QuotaStore>,
+    user: String,
+}
+
+impl QuotaMeter {
+    pub(crate) fn new(store: Arc<QuotaStore>, user: String) -> QuotaMeter {
+        return QuotaMeter { store: store, user: user };
+    }
+
+    /// Accounts for relaying `len` bytes, fails if that exceeds the user's quota.
+    pub(crate) fn consume(&self, len: usize) -> Result<(), io::Error> {
+        if self.store.consume(&self.user, len as u64) {
+            self.store.save_if_due();
+            return Ok(());
+        }
+        return Err(io::Error::other(format!("user '{}' exhausted their traffic quota", self.user)));
+    }
+
+    pub(crate) fn save(&self) -> Result<(), io::Error> {
+        return self.store.save();
+    }
+}
+
+// The current day and month in the form `Usage` stores them
+fn current_periods() -> (i64, i64) {
+    let secs = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let day = (secs / 86400) as i64;
+    let (year, month, _) = civil_from_days(day);
+    return (day, year * 12 + month - 1);
+}
+
+fn parse_usage(contents: &str) -> Result<HashMap<String, Usage>, io::Error> {
+    let mut usage = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid quota usage on line {}", index + 1));
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+        let user = percent::decode(fields[0]).ok_or_else(invalid)?;
+        let entry = Usage {
+            day: fields[1].parse().map_err(|_| invalid())?,
+            day_bytes: fields[2].parse().map_err(|_| invalid())?,
+            month: fields[3].parse().map_err(|_| invalid())?,
+            month_bytes: fields[4].parse().map_err(|_| invalid())?,
+        };
+        usage.insert(user, entry);
+    }
+    return Ok(usage);
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use std::time;
+
+use crate::address::{Address, ClientAddress};
+use crate::shutdown::ShutdownHandle;
+use crate::tracker::ConnectionTracker;
+
+/// A live connection of a `Server`, as reported by `Registry::sessions`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SessionInfo {
+    /// The id the server assigned to the connection, see `Connection::connection_id`.
+    pub id: u64,
+    pub client: ClientAddress,
+    /// The user the client authenticated as, if it used username/password authentication.
+    pub user: Option<String>,
+    /// The requested destination and port, once the client has sent its request.
+    pub destination: Option<(Address, u16)>,
+    /// When the connection was accepted.
+    pub started: time::SystemTime,
+    /// The bytes relayed from the client to the destination so far.
+    /// Only connections relayed with `Connection::relay` are counted.
+    pub bytes_in: u64,
+    /// The bytes relayed from the destination to the client so far.
+    pub bytes_out: u64,
+}
+
+/// Lists the live connections of a `Server` and closes them on demand, e.g. from an admin interface.
+/// Obtained with `Server::registry`, it can be used from any thread.
+#[derive(Clone)]
+pub struct Registry {
+    tracker: Arc<ConnectionTracker>,
+    shutdown: ShutdownHandle,
+}
+
+impl Registry {
+    pub(crate) fn new(tracker: Arc<ConnectionTracker>, shutdown: ShutdownHandle) -> Registry {
+        return Registry {
+            tracker: tracker,
+            shutdown: shutdown,
+        };
+    }
+
+    /// Returns every connection which is currently active, including those which are still negotiating, ordered by id.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        return self.tracker.sessions();
+    }
+
+    /// Closes the connection `id`, along with its connection to the destination if it's being relayed.
+    /// Returns `false` if there's no such connection.
+    ///
+    /// The connection keeps counting as active until the consumer drops its stream.
+    pub fn kill(&self, id: u64) -> bool {
+        return self.tracker.close(id);
+    }
+
+    /// Closes all connections of clients which authenticated as `user`, and returns how many there were.
+    pub fn kill_user(&self, user: &str) -> usize {
+        return self.tracker.close_user(user);
+    }
+
+    /// Returns the handle controlling the server, e.g. to pause accepting clients.
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        return &self.shutdown;
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+use std::net::ToSocketAddrs;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+/// Resolves the domain names clients request to IP addresses.
+///
+/// Set one on a `Dialer` with `set_resolver`.
+pub trait Resolver: Send + Sync {
+    /// Looks up every address `name` resolves to.
+    /// An error of kind `NotFound` means the name doesn't exist or has no addresses.
+    fn lookup(&self, name: &str) -> io::Result<Lookup>;
+}
+
+/// The result of resolving a domain name.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Lookup {
+    pub addrs: Vec<net::IpAddr>,
+    /// How long the result may be cached, if the resolver knows.
+    pub ttl: Option<time::Duration>,
+}
+
+fn not_found(name: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::NotFound, format!("'{}' did not resolve to any address", name));
+}
+
+/// Resolves names using the operating system's resolver, like `TcpStream::connect` does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn lookup(&self, name: &str) -> io::Result<Lookup> {
+        let addrs: Vec<net::IpAddr> = (name, 0).to_socket_addrs()?.map(|addr| addr.ip()).collect();
+        if addrs.is_empty() {
+            return Err(not_found(name));
+        }
+        return Ok(Lookup {
+            addrs: addrs,
+            ttl: None,
+        });
+    }
+}
+
+/// Resolves names from a fixed table, like a hosts file. Names are compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<net::IpAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> StaticResolver {
+        return StaticResolver { hosts: HashMap::new() };
+    }
+
+    /// Makes `name` resolve to `addrs`, replacing any previous entry.
+    pub fn insert(&mut self, name: &str, addrs: Vec<net::IpAddr>) {
+        self.hosts.insert(normalize_name(name), addrs);
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn lookup(&self, name: &str) -> io::Result<Lookup> {
+        match self.hosts.get(&normalize_name(name)) {
+            Some(addrs) if !addrs.is_empty() => {
+                return Ok(Lookup {
+                    addrs: addrs.clone(),
+                    ttl: None,
+                });
+            }
+            _ => return Err(not_found(name)),
+        }
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    return name.trim_end_matches('.').to_ascii_lowercase();
+}
+
+/// How `DnsResolver` talks to its server.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DnsTransport {
+    /// Queries over UDP, retrying over TCP if the answer was truncated.
+    Udp,
+    Tcp,
+}
+
+/// A minimal DNS client sending A and AAAA queries to a single recursive DNS server.
+///
+/// Names are resolved without touching the system resolver,
+/// so queries only ever go to the configured server.
+#[derive(Debug, Clone)]
+pub struct DnsResolver {
+    server: net::SocketAddr,
+    transport: DnsTransport,
+    timeout: time::Duration,
+}
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+impl DnsResolver {
+    /// Creates a resolver querying `server` over UDP, waiting up to 5 seconds for each answer.
+    pub fn new(server: net::SocketAddr) -> DnsResolver {
+        return DnsResolver {
+            server: server,
+            transport: DnsTransport::Udp,
+            timeout: time::Duration::from_secs(5),
+        };
+    }
+
+    pub fn set_transport(&mut self, transport: DnsTransport) {
+        self.transport = transport;
+    }
+
+    pub fn set_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = timeout;
+    }
+
+    fn query(&self, name: &str, qtype: u16) -> io::Result<Answer> {
+        let id = query_id();
+        let query = encode_query(id, name, qtype)?;
+        if self.transport == DnsTransport::Udp {
+            let answer = parse_response(&self.exchange_udp(id, &query)?, id)?;
+            if !answer.truncated {
+                return Ok(answer);
+            }
+        }
+        return parse_response(&self.exchange_tcp(&query)?, id);
+    }
+
+    fn exchange_udp(&self, id: u16, query: &[u8]) -> io::Result<Vec<u8>> {
+        let bind_addr: net::SocketAddr = match self.server {
+            net::SocketAddr::V4(_) => (net::Ipv4Addr::UNSPECIFIED, 0).into(),
+            net::SocketAddr::V6(_) => (net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = net::UdpSocket::bind(bind_addr)?;
+        // Connecting makes the OS discard datagrams from anyone but the server
+        socket.connect(self.server)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.send(query)?;
+        let deadline = time::Instant::now() + self.timeout;
+        let mut buf = vec![0; 65535];
+        loop {
+            let len = socket.recv(&mut buf)?;
+            // Skip stray answers to earlier queries
+            if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from DNS server"));
+            }
+            socket.set_read_timeout(Some(remaining))?;
+        }
+    }
+
+    fn exchange_tcp(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = net::TcpStream::connect_timeout(&self.server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut msg = (query.len() as u16).to_be_bytes().to_vec();
+        msg.extend_from_slice(query);
+        stream.write_all(&msg)?;
+        let mut len_buf = [0; 2];
+        stream.read_exact(&mut len_buf)?;
+        let mut buf = vec![0; u16::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut buf)?;
+        return Ok(buf);
+    }
+}
+
+impl Resolver for DnsResolver {
+    fn lookup(&self, name: &str) -> io::Result<Lookup> {
+        // Both queries are sent at once, so a slow AAAA answer doesn't hold up the A answer by more than it takes itself
+        let answers = thread::scope(|scope| {
+            let aaaa = scope.spawn(|| self.query(name, TYPE_AAAA));
+            let a = self.query(name, TYPE_A);
+            return [a, aaaa.join().unwrap()];
+        });
+        let mut addrs = Vec::new();
+        let mut ttl: Option<time::Duration> = None;
+        let mut error = None;
+        // Many servers mishandle AAAA queries, so one family failing is only an error if the other one has no addresses either
+        for answer in answers {
+            match answer {
+                Ok(answer) => {
+                    if let Some(answer_ttl) = answer.ttl {
+                        ttl = Some(ttl.map_or(answer_ttl, |ttl| ttl.min(answer_ttl)));
+                    }
+                    addrs.extend(answer.addrs);
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        if addrs.is_empty() {
+            return Err(error.unwrap_or_else(|| not_found(name)));
+        }
+        return Ok(Lookup {
+            addrs: addrs,
+            ttl: ttl,
+        });
+    }
+}
+
+/// Query IDs only need to be unpredictable enough that off-path attackers can't easily spoof answers.
+fn query_id() -> u16 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+    return hasher.finish() as u16;
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid domain name", name));
+    // ID, flags with recursion desired, one question and no other records
+    let mut msg = Vec::with_capacity(18 + name.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    // The encoded name may be at most 255 bytes long
+    if msg.len() - 12 > 255 {
+        return Err(invalid());
+    }
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    return Ok(msg);
+}
+
+struct Answer {
+    addrs: Vec<net::IpAddr>,
+    ttl: Option<time::Duration>,
+    truncated: bool,
+}
+
+fn parse_response(msg: &[u8], id: u16) -> io::Result<Answer> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        return Err(malformed());
+    }
+    let flags = u16::from_be_bytes([msg[2], msg[3]]);
+    // Must be a response
+    if flags & 0x8000 == 0 {
+        return Err(malformed());
+    }
+    let truncated = flags & 0x0200 != 0;
+    match flags & 0x000F {
+        0 => (),
+        RCODE_NXDOMAIN => return Err(io::Error::new(io::ErrorKind::NotFound, "domain name does not exist")),
+        rcode => return Err(io::Error::other(format!("DNS server failed with response code {}", rcode))),
+    }
+    let questions = u16::from_be_bytes([msg[4], msg[5]]);
+    let answers = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos).ok_or_else(malformed)? + 4;
+    }
+    let mut addrs = Vec::new();
+    let mut ttl: Option<time::Duration> = None;
+    for _ in 0..answers {
+        pos = skip_name(msg, pos).ok_or_else(malformed)?;
+        let header = msg.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rttl = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let rdlen = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10;
+        let rdata = msg.get(pos..pos + rdlen).ok_or_else(malformed)?;
+        pos += rdlen;
+        // The answer is only valid as long as every record in it is, including CNAMEs leading up to the addresses
+        let rttl = time::Duration::from_secs(rttl as u64);
+        ttl = Some(ttl.map_or(rttl, |ttl| ttl.min(rttl)));
+        let addr: net::IpAddr = match (rtype, rdlen) {
+            (TYPE_A, 4) => net::Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).into(),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(rdata);
+                net::Ipv6Addr::from(octets).into()
+            }
+            _ => continue,
+        };
+        addrs.push(addr);
+    }
+    return Ok(Answer {
+        addrs: addrs,
+        ttl: ttl,
+        truncated: truncated,
+    });
+}
+
+/// Returns the position right after the (possibly compressed) name starting at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            // A pointer to a name elsewhere ends the name
+            msg.get(pos + 1)?;
+            return Some(pos + 2);
+        }
+        pos += 1 + len as usize;
+    }
+}
+
+/// Caches the results of another resolver for as long as their TTL allows.
+pub struct CachingResolver<R: Resolver> {
+    inner: R,
+    default_ttl: time::Duration,
+    max_ttl: time::Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+struct CacheEntry {
+    addrs: Vec<net::IpAddr>,
+    expires: time::Instant,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    /// Wraps `inner`, caching results without a TTL for a minute and any result for at most a day.
+    pub fn new(inner: R) -> CachingResolver<R> {
+        return CachingResolver {
+            inner: inner,
+            default_ttl: time::Duration::from_secs(60),
+            max_ttl: time::Duration::from_secs(24 * 60 * 60),
+            max_entries: 1024,
+            entries: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Sets how long results are cached if the wrapped resolver doesn't report a TTL.
+    pub fn set_default_ttl(&mut self, ttl: time::Duration) {
+        self.default_ttl = ttl;
+    }
+
+    /// Caps how long results are cached, regardless of their TTL.
+    pub fn set_max_ttl(&mut self, ttl: time::Duration) {
+        self.max_ttl = ttl;
+    }
+
+    /// Sets how many names are cached at most.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+    }
+
+    /// Forgets every cached result.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn lookup(&self, name: &str) -> io::Result<Lookup> {
+        let key = normalize_name(name);
+        let now = time::Instant::now();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.expires > now {
+                return Ok(Lookup {
+                    addrs: entry.addrs.clone(),
+                    ttl: Some(entry.expires - now),
+                });
+            }
+        }
+
+        // Not holding the lock while resolving, so that slow lookups don't block cached ones
+        let lookup = self.inner.lookup(name)?;
+        let ttl = lookup.ttl.unwrap_or(self.default_ttl).min(self.max_ttl);
+        if ttl.is_zero() || self.max_entries == 0 {
+            return Ok(lookup);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.max_entries {
+                entries.clear();
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                addrs: lookup.addrs.clone(),
+                expires: now + ttl,
+            },
+        );
+        return Ok(lookup);
+    }
+}
@@ -1,20 +1,63 @@
 use std::io;
 use std::net;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
 use std::time;
 
+use ignore_result::Ignore;
+
 use crate::auth::AuthMethod;
-use crate::connection::UnrequitedSOCKSConnection;
+use crate::connection::{SOCKSConnection, UnrequitedSOCKSConnection};
+use crate::dialer::Dialer;
+use crate::limits::{ConnectionLimits, ConnectionStats, LimitAction, LimitKind};
+use crate::listener::{AcceptBackoff, Listener, ListenerInfo, ListenerSocket};
+use crate::observer::{NoopObserver, ServerObserver};
+use crate::quota::{QuotaMeter, QuotaStore};
+use crate::registry::Registry;
+use crate::shutdown::{ShutdownHandle, WakeAddress};
+use crate::socket_options::SocketOptions;
 use crate::socks_error::SOCKSError;
+use crate::stream::ClientStream;
+use crate::throttle::{BandwidthLimits, Throttle};
+use crate::tracker::ConnectionTracker;
+
+pub(crate) type Incoming = Result<Accepted, SOCKSError>;
+
+/// A client accepted by one of the accept threads, which the iterator still has to negotiate with.
+pub(crate) struct Accepted {
+    listener: Arc<ListenerInfo>,
+    stream: ClientStream,
+    // The overall or per-client limit exceeded by this connection, if it's to be replied to with a failure
+    exceeded: Option<LimitKind>,
+}
 
 /// A SOCKSServer's function is to accept and negotiate connections from clients.
-/// However, actually forwarding/modifying client data is out of scope for this library,
-/// and is left to the consumer.
+/// Forwarding/modifying client data is mostly left to the consumer,
+/// though `Connection::relay` covers the plain case of forwarding it unmodified.
 pub struct SOCKSServer {
-    listener: net::TcpListener,
+    // Each listener is served by its own thread, which hands accepted streams to the iterator through this channel.
+    // `None` is sent to wake the iterator up on shutdown.
+    incoming: mpsc::Receiver<Option<Incoming>>,
+    shutdown: ShutdownHandle,
+    tracker: Arc<ConnectionTracker>,
+    dialer: Arc<Dialer>,
+    socket_options: SocketOptions,
+    observer: Arc<dyn ServerObserver>,
+    throttle: Arc<Throttle>,
+    quotas: Option<Arc<QuotaStore>>,
     timeout: Option<time::Duration>,
     auth_methods: Vec<AuthMethod>,
     username: Option<String>,
     password: Option<String>,
+    // Whether username/password credentials are pluggable transport arguments rather than checked
+    pt_arguments: bool,
 }
 
 impl SOCKSServer {
@@ -40,38 +83,404 @@ impl SOCKSServer {
         username: Option<String>,
         password: Option<String>,
     ) -> Result<SOCKSServer, io::Error> {
-        let listener = net::TcpListener::bind(&bind_addr)?;
-        let server = SOCKSServer {
-            listener: listener,
+        let listener = Listener::bind_tcp(bind_addr)?;
+        return Ok(SOCKSServer::from_listeners(vec![listener], timeout, auth_methods, username, password));
+    }
+
+    /// Creates a new SOCKS5 server listening for connections on the Unix domain socket at `path`.
+    ///
+    /// If a stale socket file from a previous run exists at `path` it is removed first.
+    /// This function fails if another server is still listening on `path`, or if something other than a socket exists there.
+    ///
+    /// If `mode` is not `None` the permission bits of the socket file are set to it (e.g. `0o660`),
+    /// which is the only access control most deployments need for Unix sockets.
+    /// Note that the socket is briefly reachable with the permissions given by the process umask before that happens.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    /// Connections accepted by this server report the credentials of the peer process as their client address.
+    #[cfg(unix)]
+    pub fn init_unix<P: AsRef<Path>>(
+        path: P,
+        mode: Option<u32>,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<SOCKSServer, io::Error> {
+        let listener = Listener::bind_unix(path, mode)?;
+        return Ok(SOCKSServer::from_listeners(vec![listener], timeout, auth_methods, username, password));
+    }
+
+    /// Creates a new SOCKS5 server accepting connections on an already bound `listener`,
+    /// e.g. one inherited from a parent process which had the privileges to bind it.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    pub fn from_tcp_listener(
+        listener: net::TcpListener,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> SOCKSServer {
+        return SOCKSServer::from_listeners(vec![Listener::from(listener)], timeout, auth_methods, username, password);
+    }
+
+    /// Creates a new SOCKS5 server accepting connections on an already bound Unix domain socket `listener`.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    #[cfg(unix)]
+    pub fn from_unix_listener(
+        listener: UnixListener,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> SOCKSServer {
+        return SOCKSServer::from_listeners(vec![Listener::from(listener)], timeout, auth_methods, username, password);
+    }
+
+    /// Creates a new SOCKS5 server accepting connections on the listening socket `fd`,
+    /// which may either be a TCP or a Unix domain stream socket.
+    /// This function fails if `fd` is any other kind of file descriptor.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor which is not owned by anything else, as the server takes ownership of it.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(
+        fd: RawFd,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<SOCKSServer, io::Error> {
+        let listener = Listener::from_raw_fd(fd)?;
+        return Ok(SOCKSServer::from_listeners(vec![listener], timeout, auth_methods, username, password));
+    }
+
+    /// Creates a new SOCKS5 server accepting connections on all sockets passed to this process by systemd socket activation.
+    /// See `systemd::listen_fds` for details on how the sockets are obtained.
+    /// Each listener is named after the `FileDescriptorName=` of its socket.
+    ///
+    /// This function fails if no sockets were passed.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    #[cfg(unix)]
+    pub fn from_systemd(
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<SOCKSServer, io::Error> {
+        // Own every socket up front, so that the ones not wrapped yet are closed if one of them fails
+        // SAFETY: listen_fds() doesn't take ownership of the sockets, and clears the environment so nobody else will.
+        let sockets: Vec<(OwnedFd, String)> = crate::systemd::listen_fds()?
+            .into_iter()
+            .map(|socket| (unsafe { OwnedFd::from_raw_fd(socket.fd) }, socket.name))
+            .collect();
+        let mut listeners = Vec::new();
+        for (fd, name) in sockets {
+            // SAFETY: fd is open, and ownership is handed over to the listener right after it is created.
+            let listener = unsafe { Listener::from_raw_fd(fd.as_raw_fd())? };
+            let _ = fd.into_raw_fd();
+            listeners.push(listener.named(name));
+        }
+        return SOCKSServer::with_listeners(listeners, timeout, auth_methods, username, password);
+    }
+
+    /// Creates a new SOCKS5 server accepting connections on all of `listeners`.
+    /// Connections from every listener are yielded by the same iterator,
+    /// and report which listener they arrived on via `get_listener`.
+    ///
+    /// This function fails if `listeners` is empty.
+    ///
+    /// The remaining arguments behave as they do for `init`.
+    pub fn with_listeners(
+        listeners: Vec<Listener>,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<SOCKSServer, io::Error> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a server needs at least one listener",
+            ));
+        }
+        return Ok(SOCKSServer::from_listeners(listeners, timeout, auth_methods, username, password));
+    }
+
+    fn from_listeners(
+        listeners: Vec<Listener>,
+        timeout: Option<time::Duration>,
+        auth_methods: Vec<AuthMethod>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> SOCKSServer {
+        // Don't accept more clients than the iterator is ready to negotiate with,
+        // the rest should wait in the listen backlog.
+        // There's room for a single one, so that a shutdown can always wake up the iterator.
+        let (tx, rx) = mpsc::sync_channel(1);
+        let tracker = ConnectionTracker::new();
+        let wake_listeners = listeners.iter().filter_map(SOCKSServer::wake_address).collect();
+        let shutdown = ShutdownHandle::new(tx.clone(), wake_listeners, tracker.clone());
+        for (index, listener) in listeners.into_iter().enumerate() {
+            SOCKSServer::spawn_accept_thread(index, listener, tx.clone(), tracker.clone(), shutdown.clone());
+        }
+        return SOCKSServer {
+            incoming: rx,
+            shutdown: shutdown,
+            tracker: tracker,
+            dialer: Arc::new(Dialer::new()),
+            socket_options: SocketOptions::default(),
+            observer: Arc::new(NoopObserver),
+            throttle: Throttle::new(),
+            quotas: None,
             timeout: timeout,
             auth_methods: auth_methods,
             username: username,
             password: password,
+            pt_arguments: false,
         };
-        return Ok(server);
+    }
+
+    /// Returns a handle which can stop this server from another thread,
+    /// and tells when the connections accepted by it have drained.
+    ///
+    /// Dropping the server shuts it down as well.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        return self.shutdown.clone();
+    }
+
+    /// Limits how many connections this server keeps active at the same time.
+    /// The limits can be changed at any time, but connections which are already active are not affected.
+    pub fn set_connection_limits(&self, limits: ConnectionLimits) {
+        self.tracker.set_limits(limits);
+    }
+
+    /// Limits the bandwidth `Connection::relay` uses for the connections of this server.
+    /// The limits can be changed at any time and apply to connections which are already being relayed as well.
+    pub fn set_bandwidth_limits(&self, limits: BandwidthLimits) {
+        self.throttle.set_limits(limits);
+    }
+
+    /// Enforces the traffic quotas of `quotas` on the connections this server yields from now on.
+    /// Clients whose user exhausted their quota are replied to with `ConnectionNotAllowed`
+    /// and yielded as a `QuotaExceededError` in their place.
+    pub fn set_quotas(&mut self, quotas: Arc<QuotaStore>) {
+        self.quotas = Some(quotas);
+    }
+
+    /// Sets the dialer used by `connect` on the connections this server yields from now on.
+    /// If socket options were set with `set_socket_options`, they replace the dialer's own.
+    pub fn set_dialer(&mut self, mut dialer: Dialer) {
+        if !self.socket_options.is_empty() {
+            dialer.set_socket_options(self.socket_options.clone());
+        }
+        self.dialer = Arc::new(dialer);
+    }
+
+    /// Sets socket options on client streams accepted from now on, as well as on the streams `connect` dials.
+    /// The server's `timeout` applies on top of these.
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        Arc::make_mut(&mut self.dialer).set_socket_options(options.clone());
+        self.socket_options = options;
+    }
+
+    /// Sets the observer notified of events on the connections this server yields from now on.
+    pub fn set_observer(&mut self, observer: Arc<dyn ServerObserver>) {
+        self.observer = observer;
+    }
+
+    /// Makes clients which use the UsernamePassword method pass pluggable transport arguments in the credentials,
+    /// instead of checking them against the server's `username` and `password`, which are ignored.
+    /// Clients whose arguments are malformed are refused like clients supplying wrong credentials.
+    ///
+    /// The arguments are available through `get_pt_arguments` on the connections this server yields from now on.
+    /// Since no user is authenticated, per-user limits and quotas don't apply to these clients.
+    pub fn accept_pt_arguments(&mut self) {
+        self.pt_arguments = true;
+    }
+
+    /// Stops accepting clients until `resume` is called, see `ShutdownHandle::pause`.
+    pub fn pause(&self) {
+        self.shutdown.pause();
+    }
+
+    pub fn resume(&self) {
+        self.shutdown.resume();
+    }
+
+    /// Returns a registry of the live connections of this server, which can close them from any thread.
+    pub fn registry(&self) -> Registry {
+        return Registry::new(self.tracker.clone(), self.shutdown.clone());
+    }
+
+    /// Returns a handle reporting how many connections this server currently has active.
+    pub fn connection_stats(&self) -> ConnectionStats {
+        return ConnectionStats::new(self.tracker.clone());
+    }
+
+    fn spawn_accept_thread(
+        index: usize,
+        listener: Listener,
+        incoming: mpsc::SyncSender<Option<Incoming>>,
+        tracker: Arc<ConnectionTracker>,
+        shutdown: ShutdownHandle,
+    ) {
+        let info = Arc::new(ListenerInfo {
+            index: index,
+            name: listener.name.clone(),
+        });
+        thread::Builder::new()
+            .name(format!("socks5-accept-{}", index))
+            .spawn(move || {
+                let mut backoff = AcceptBackoff::new();
+                loop {
+                    let stream = listener.socket.accept();
+                    let accepted = stream.is_ok();
+                    // A client accepted while paused waits here, the others in the listen backlog
+                    shutdown.wait_while_paused();
+                    // Returning drops and thereby closes the listener
+                    if shutdown.is_shut_down() {
+                        return;
+                    }
+                    let tracked = SOCKSServer::track(stream, &tracker).map(|(stream, exceeded)| Accepted {
+                        listener: info.clone(),
+                        stream: stream,
+                        exceeded: exceeded,
+                    });
+                    // The server has been dropped
+                    if incoming.send(Some(tracked)).is_err() {
+                        return;
+                    }
+                    // The error was passed on, but accepting right away would likely fail the same way
+                    backoff.record(accepted);
+                }
+            })
+            .unwrap();
+    }
+
+    // Connections exceeding a limit are closed right away unless they're to be replied to with a failure
+    fn track(
+        stream: Result<ClientStream, io::Error>,
+        tracker: &Arc<ConnectionTracker>,
+    ) -> Result<(ClientStream, Option<LimitKind>), SOCKSError> {
+        let mut stream = stream?;
+        match tracker.track(&mut stream)? {
+            Some(limit) if tracker.limits().action == LimitAction::Close => {
+                return Err(SOCKSError::ConnectionLimitError(stream.client_address()?, limit));
+            }
+            exceeded => return Ok((stream, exceeded)),
+        }
+    }
+
+    fn wake_address(listener: &Listener) -> Option<WakeAddress> {
+        match &listener.socket {
+            ListenerSocket::Tcp(l) => {
+                let mut addr = l.local_addr().ok()?;
+                if addr.ip().is_unspecified() {
+                    match addr {
+                        net::SocketAddr::V4(_) => addr.set_ip(net::Ipv4Addr::LOCALHOST.into()),
+                        net::SocketAddr::V6(_) => addr.set_ip(net::Ipv6Addr::LOCALHOST.into()),
+                    }
+                }
+                return Some(WakeAddress::Tcp(addr));
+            }
+            #[cfg(unix)]
+            ListenerSocket::Unix(l) => {
+                let addr = l.local_addr().ok()?;
+                return Some(WakeAddress::Unix(addr.as_pathname()?.to_path_buf()));
+            }
+        }
+    }
+}
+
+impl Drop for SOCKSServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
     }
 }
 
 impl Iterator for SOCKSServer {
     type Item = Result<UnrequitedSOCKSConnection, SOCKSError>;
     fn next(&mut self) -> Option<Self::Item> {
-        let stream: net::TcpStream;
-        match self.listener.accept() {
-            Ok(val) => {
-                stream = val.0;
-                stream.set_read_timeout(self.timeout).unwrap();
-                stream.set_write_timeout(self.timeout).unwrap();
+        let next = self.negotiate_next();
+        if let Some(Err(err)) = &next {
+            self.observer.on_handshake_error(err);
+        }
+        return next;
+    }
+}
+
+impl SOCKSServer {
+    fn negotiate_next(&mut self) -> Option<Result<UnrequitedSOCKSConnection, SOCKSError>> {
+        let accepted: Accepted;
+        if self.shutdown.is_shut_down() {
+            return None;
+        }
+        match self.incoming.recv() {
+            Ok(Some(Ok(val))) => {
+                accepted = val;
+                accepted.stream.set_read_timeout(self.timeout).unwrap();
+                accepted.stream.set_write_timeout(self.timeout).unwrap();
+                if let Err(e) = accepted.stream.apply_socket_options(&self.socket_options) {
+                    return Some(Err(SOCKSError::StreamIOError(e)));
+                }
+                match accepted.stream.client_address() {
+                    Ok(client_addr) => self.observer.on_accepted(&client_addr, &accepted.listener),
+                    Err(e) => return Some(Err(SOCKSError::StreamIOError(e))),
+                }
             }
-            Err(e) => return Some(Err(SOCKSError::StreamIOError(e))),
+            Ok(Some(Err(e))) => return Some(Err(e)),
+            Ok(None) => return None,
+            // Only happens if all accept threads died
+            Err(_) => return None,
         }
-        match UnrequitedSOCKSConnection::init(
-            stream,
+        let socks_conn = match SOCKSConnection::init(
+            accepted.stream,
+            self.observer.clone(),
+            self.throttle.clone(),
             self.auth_methods.clone(),
             self.username.clone(),
             self.password.clone(),
+            self.pt_arguments,
         ) {
-            Ok(val) => return Some(Ok(val)),
+            Ok(val) => val,
             Err(err) => return Some(Err(err)),
+        };
+        let mut conn = UnrequitedSOCKSConnection::new(socks_conn, accepted.listener, self.dialer.clone());
+
+        // Per-user limits can only be checked once the client has authenticated
+        let mut exceeded = accepted.exceeded;
+        if let Some(id) = conn.connection_id() {
+            let (dst_addr, dst_port) = conn.get_destination_address();
+            self.tracker.set_destination(id, dst_addr, dst_port);
+            if let Some(user) = conn.get_username() {
+                let user_exceeded = self.tracker.set_user(id, &user);
+                exceeded = exceeded.or(user_exceeded);
+            }
+        }
+        if let Some(limit) = exceeded {
+            let client_addr = conn.get_client_address();
+            if self.tracker.limits().action == LimitAction::ReplyFailure {
+                conn.report_general_server_failure().ignore();
+            }
+            // Otherwise dropping the connection closes it
+            return Some(Err(SOCKSError::ConnectionLimitError(client_addr, limit)));
+        }
+
+        if let (Some(quotas), Some(user)) = (&self.quotas, conn.get_username()) {
+            if quotas.is_exhausted(&user) {
+                let client_addr = conn.get_client_address();
+                conn.report_connection_not_allowed().ignore();
+                return Some(Err(SOCKSError::QuotaExceededError(client_addr, user)));
+            }
+            conn.set_quota(QuotaMeter::new(quotas.clone(), user));
         }
+        return Some(Ok(conn));
     }
 }
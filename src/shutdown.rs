@@ -0,0 +1,217 @@
+use std::net;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::server::Incoming;
+use crate::tracker::ConnectionTracker;
+
+/// Stops a `Server` from another thread (or a signal handler, see `shutdown_on_signal`)
+/// and tells the consumer when the connections it accepted have drained.
+///
+/// A connection counts as active from the moment it's accepted until every clone of its `ClientStream` has been dropped,
+/// so consumers only need to drop their streams once they're done relaying.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownState>,
+}
+
+pub(crate) struct ShutdownState {
+    shut_down: AtomicBool,
+    paused: Mutex<bool>,
+    // Wakes up the accept threads once accepting is resumed, or the server is shut down
+    resumed: Condvar,
+    // Wakes up the iterator if it's waiting for a client
+    wake_iterator: mpsc::SyncSender<Option<Incoming>>,
+    wake_listeners: Vec<WakeAddress>,
+    tracker: Arc<ConnectionTracker>,
+}
+
+/// Where to connect to in order to wake up an accept thread blocked on its listener.
+pub(crate) enum WakeAddress {
+    Tcp(net::SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(
+        wake_iterator: mpsc::SyncSender<Option<Incoming>>,
+        wake_listeners: Vec<WakeAddress>,
+        tracker: Arc<ConnectionTracker>,
+    ) -> ShutdownHandle {
+        return ShutdownHandle {
+            inner: Arc::new(ShutdownState {
+                shut_down: AtomicBool::new(false),
+                paused: Mutex::new(false),
+                resumed: Condvar::new(),
+                wake_iterator: wake_iterator,
+                wake_listeners: wake_listeners,
+                tracker: tracker,
+            }),
+        };
+    }
+
+    /// Stops accepting clients and closes the server's listeners.
+    /// The server's iterator returns `None` once a handshake it's currently performing has completed.
+    /// Connections which have already been accepted are not affected.
+    pub fn shutdown(&self) {
+        if self.inner.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Accept threads waiting for the server to be resumed have to notice the shutdown as well
+        let paused = self.inner.paused.lock().unwrap();
+        self.inner.resumed.notify_all();
+        drop(paused);
+        // The accept threads are blocked in accept(), connecting to them is the only portable way to wake them up.
+        // Once awake they notice the shutdown and close their listener.
+        for addr in self.inner.wake_listeners.iter() {
+            match addr {
+                WakeAddress::Tcp(addr) => {
+                    net::TcpStream::connect_timeout(addr, time::Duration::from_secs(1)).ignore();
+                }
+                #[cfg(unix)]
+                WakeAddress::Unix(path) => {
+                    UnixStream::connect(path).ignore();
+                }
+            }
+        }
+        // If the channel is full, the iterator is going to receive a client and notice the shutdown afterwards anyway
+        self.inner.wake_iterator.try_send(None).ignore();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        return self.inner.shut_down.load(Ordering::SeqCst);
+    }
+
+    /// Stops handing new clients to the server's iterator until `resume` is called, e.g. to shed load.
+    /// Clients connecting in the meantime wait in the listen backlog, connections which have already been accepted are not affected.
+    pub fn pause(&self) {
+        *self.inner.paused.lock().unwrap() = true;
+    }
+
+    /// Resumes accepting clients after `pause`.
+    pub fn resume(&self) {
+        *self.inner.paused.lock().unwrap() = false;
+        self.inner.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return *self.inner.paused.lock().unwrap();
+    }
+
+    // Blocks the calling accept thread while the server is paused
+    pub(crate) fn wait_while_paused(&self) {
+        let paused = self.inner.paused.lock().unwrap();
+        let _paused = self
+            .inner
+            .resumed
+            .wait_while(paused, |paused| *paused && !self.is_shut_down())
+            .unwrap();
+    }
+
+    /// Returns how many accepted connections are still active, including those which are still negotiating.
+    pub fn active_connections(&self) -> usize {
+        return self.inner.tracker.count();
+    }
+
+    /// Blocks until all accepted connections have drained, or `timeout` expires.
+    /// Returns whether all connections have drained.
+    pub fn wait_drained(&self, timeout: Option<time::Duration>) -> bool {
+        return self.inner.tracker.wait_drained(timeout);
+    }
+
+    /// Forcefully closes all active connections, which makes reads and writes on them fail.
+    /// The connections keep counting as active until the consumer drops their streams.
+    pub fn abort_connections(&self) {
+        self.inner.tracker.close_all();
+    }
+
+    /// Shuts the server down and waits up to `grace` for active connections to drain.
+    /// Any connections still active after that are aborted.
+    ///
+    /// Returns whether all connections drained within the grace period.
+    /// Use `wait_drained` to find out when the aborted connections have been dropped by the consumer.
+    pub fn shutdown_gracefully(&self, grace: time::Duration) -> bool {
+        self.shutdown();
+        if self.wait_drained(Some(grace)) {
+            return true;
+        }
+        self.abort_connections();
+        return false;
+    }
+
+    /// Shuts the server down when the process receives `signal`, e.g. `libc::SIGTERM`.
+    /// This replaces any handler previously installed for `signal`.
+    #[cfg(unix)]
+    pub fn shutdown_on_signal(&self, signal: libc::c_int) -> Result<(), std::io::Error> {
+        return signal::register(self.clone(), signal);
+    }
+}
+
+// Signal handlers may only do very little, so ours just writes to a socket.
+// A thread reading from the other end shuts down all registered servers.
+#[cfg(unix)]
+mod signal {
+    use std::io;
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::thread;
+
+    use super::{ShutdownHandle, ShutdownState};
+
+    static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+    // Weak, so that registering doesn't keep a server's state alive after the server and its handles are gone
+    static HANDLES: Mutex<Vec<Weak<ShutdownState>>> = Mutex::new(Vec::new());
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        let fd = WAKE_FD.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let byte: u8 = 1;
+            // SAFETY: write() is async-signal-safe and `byte` is valid for reads.
+            unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+
+    pub(super) fn register(handle: ShutdownHandle, signal: libc::c_int) -> Result<(), io::Error> {
+        let mut handles = HANDLES.lock().unwrap();
+        if WAKE_FD.load(Ordering::SeqCst) < 0 {
+            let (mut reader, writer) = UnixStream::pair()?;
+            writer.set_nonblocking(true)?;
+            thread::Builder::new().name("socks5-signal".to_string()).spawn(move || {
+                let mut buf = [0; 1];
+                while let Ok(1) = reader.read(&mut buf) {
+                    for inner in HANDLES.lock().unwrap().iter().filter_map(Weak::upgrade) {
+                        ShutdownHandle { inner: inner }.shutdown();
+                    }
+                }
+            })?;
+            WAKE_FD.store(writer.into_raw_fd(), Ordering::SeqCst);
+        }
+        // Servers which have been shut down (dropping a server shuts it down) don't need to be notified anymore
+        handles.retain(|inner| inner.upgrade().is_some_and(|inner| !inner.shut_down.load(Ordering::SeqCst)));
+        handles.push(Arc::downgrade(&handle.inner));
+
+        // SAFETY: `action` is fully initialized before being passed to sigaction(), and the handler is async-signal-safe.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        return Ok(());
+    }
+}
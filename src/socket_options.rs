@@ -0,0 +1,143 @@
+use std::io;
+use std::time;
+
+use socket2::SockRef;
+
+/// Options to set on sockets, all of which are left at the operating system's default if `None`.
+///
+/// Options which don't apply to a socket, such as TCP options on Unix domain sockets, are skipped.
+/// Setting an option the platform doesn't support fails with `ErrorKind::Unsupported`.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SocketOptions {
+    /// Disables Nagle's algorithm (`TCP_NODELAY`).
+    pub nodelay: Option<bool>,
+    /// Enables TCP keepalive (`SO_KEEPALIVE`) with the given parameters.
+    pub keepalive: Option<Keepalive>,
+    /// `SO_SNDBUF`, in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF`, in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// The type of service field (`IP_TOS`, or `IPV6_TCLASS` for IPv6). The DSCP is the upper 6 bits.
+    pub tos: Option<u32>,
+    /// `SO_MARK`, for policy routing. Linux only, and requires `CAP_NET_ADMIN`.
+    pub mark: Option<u32>,
+    /// How long sent data may remain unacknowledged before the connection is dropped (`TCP_USER_TIMEOUT`). Linux only.
+    pub user_timeout: Option<time::Duration>,
+}
+
+/// TCP keepalive parameters. Parameters left at `None` keep the operating system's defaults.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Keepalive {
+    /// How long the connection has to be idle before the first probe is sent (`TCP_KEEPIDLE`).
+    pub idle: Option<time::Duration>,
+    /// How long to wait between probes (`TCP_KEEPINTVL`).
+    pub interval: Option<time::Duration>,
+    /// How many unanswered probes it takes to drop the connection (`TCP_KEEPCNT`).
+    pub retries: Option<u32>,
+}
+
+impl SocketOptions {
+    pub(crate) fn is_empty(&self) -> bool {
+        return *self == SocketOptions::default();
+    }
+
+    /// Sets the options on `socket`, which may be connected or not.
+    pub(crate) fn apply(&self, socket: SockRef) -> io::Result<()> {
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        let is_ipv6 = match socket.local_addr()?.as_socket() {
+            Some(addr) => addr.is_ipv6(),
+            // Not an IP socket, so none of the other options apply
+            None => return Ok(()),
+        };
+
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = &self.keepalive {
+            set_keepalive(&socket, keepalive)?;
+        }
+        if let Some(tos) = self.tos {
+            set_tos(&socket, tos, is_ipv6)?;
+        }
+        if let Some(mark) = self.mark {
+            set_mark(&socket, mark)?;
+        }
+        if let Some(timeout) = self.user_timeout {
+            set_user_timeout(&socket, timeout)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn unsupported(option: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::Unsupported, format!("{} is not supported on this platform", option));
+}
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn set_keepalive(socket: &SockRef, keepalive: &Keepalive) -> io::Result<()> {
+    let mut params = socket2::TcpKeepalive::new();
+    if let Some(idle) = keepalive.idle {
+        params = params.with_time(idle);
+    }
+    if let Some(interval) = keepalive.interval {
+        params = params.with_interval(interval);
+    }
+    if let Some(retries) = keepalive.retries {
+        params = params.with_retries(retries);
+    }
+    return socket.set_tcp_keepalive(&params);
+}
+
+#[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn set_keepalive(socket: &SockRef, keepalive: &Keepalive) -> io::Result<()> {
+    if keepalive.interval.is_some() || keepalive.retries.is_some() {
+        return Err(unsupported("setting the TCP keepalive interval or retries"));
+    }
+    let mut params = socket2::TcpKeepalive::new();
+    if let Some(idle) = keepalive.idle {
+        params = params.with_time(idle);
+    }
+    return socket.set_tcp_keepalive(&params);
+}
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos"))]
+fn set_tos(socket: &SockRef, tos: u32, is_ipv6: bool) -> io::Result<()> {
+    if is_ipv6 {
+        return socket.set_tclass_v6(tos);
+    }
+    return socket.set_tos_v4(tos);
+}
+
+#[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos")))]
+fn set_tos(socket: &SockRef, tos: u32, is_ipv6: bool) -> io::Result<()> {
+    if is_ipv6 {
+        return Err(unsupported("IPV6_TCLASS"));
+    }
+    return socket.set_tos_v4(tos);
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn set_mark(socket: &SockRef, mark: u32) -> io::Result<()> {
+    return socket.set_mark(mark);
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn set_mark(_socket: &SockRef, _mark: u32) -> io::Result<()> {
+    return Err(unsupported("SO_MARK"));
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn set_user_timeout(socket: &SockRef, timeout: time::Duration) -> io::Result<()> {
+    return socket.set_tcp_user_timeout(Some(timeout));
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn set_user_timeout(_socket: &SockRef, _timeout: time::Duration) -> io::Result<()> {
+    return Err(unsupported("TCP_USER_TIMEOUT"));
+}
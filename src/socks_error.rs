@@ -1,24 +1,29 @@
 use std::error;
 use std::fmt;
-use std::net::SocketAddr;
 use std::io;
 use std::convert::From;
 
+use crate::address::ClientAddress;
 use crate::auth::AuthMethod;
+use crate::dialer::DialError;
+use crate::limits::LimitKind;
 
 /// Returned in case negotiating a proxy connection with a client fails for whatever reason.
 #[derive(Debug)]
 pub enum SOCKSError {
-    NoOverlappingAuthMethodsError(SocketAddr, Vec<AuthMethod>, Vec<AuthMethod>),
-    UnknownAuthMethodSubnegotiationVersionError(SocketAddr, u8, u8),
-    WrongCredentialsError(SocketAddr), // Don't store or log the credentials for security reasons
-    ProtoolVersionError(SocketAddr, u8),
-    UnknownRequestCommandError(SocketAddr, u8),
-    UnknownAddressTypeError(SocketAddr, u8),
-    UnknownReservedByteError(SocketAddr, u8),
-    UnknownProtocolViolationError(SocketAddr, String),
-    NoAuthMethodsError(SocketAddr),
-    TimeoutError(SocketAddr),
+    NoOverlappingAuthMethodsError(ClientAddress, Vec<AuthMethod>, Vec<AuthMethod>),
+    UnknownAuthMethodSubnegotiationVersionError(ClientAddress, u8, u8),
+    WrongCredentialsError(ClientAddress), // Don't store or log the credentials for security reasons
+    ProtoolVersionError(ClientAddress, u8),
+    UnknownRequestCommandError(ClientAddress, u8),
+    UnknownAddressTypeError(ClientAddress, u8),
+    UnknownReservedByteError(ClientAddress, u8),
+    UnknownProtocolViolationError(ClientAddress, String),
+    NoAuthMethodsError(ClientAddress),
+    TimeoutError(ClientAddress),
+    ConnectionLimitError(ClientAddress, LimitKind),
+    DestinationError(ClientAddress, DialError),
+    QuotaExceededError(ClientAddress, String),
     StreamIOError(io::Error),
 }
 
@@ -57,6 +62,15 @@ impl fmt::Display for SOCKSError {
             SOCKSError::TimeoutError(client_addr) => {
                 write!(f, "Client '{}' timed out", client_addr)
             },
+            SOCKSError::ConnectionLimitError(client_addr, limit) => {
+                write!(f, "Client '{}' exceeded a connection limit: {}", client_addr, limit)
+            },
+            SOCKSError::DestinationError(client_addr, err) => {
+                write!(f, "Could not connect on behalf of client '{}': {}", client_addr, err)
+            },
+            SOCKSError::QuotaExceededError(client_addr, user) => {
+                write!(f, "Client '{}' authenticated as user '{}', who exhausted their traffic quota", client_addr, user)
+            },
             SOCKSError::StreamIOError(e) => {
                 write!(f, "Failed to send data due to an IO error: {}", e)
             },
@@ -67,6 +81,28 @@ impl fmt::Display for SOCKSError {
     }
 }
 
+impl SOCKSError {
+    /// Returns the name of the variant, which is stable and suitable for labelling errors in metrics and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SOCKSError::NoOverlappingAuthMethodsError(..) => return "NoOverlappingAuthMethodsError",
+            SOCKSError::UnknownAuthMethodSubnegotiationVersionError(..) => return "UnknownAuthMethodSubnegotiationVersionError",
+            SOCKSError::WrongCredentialsError(..) => return "WrongCredentialsError",
+            SOCKSError::ProtoolVersionError(..) => return "ProtoolVersionError",
+            SOCKSError::UnknownRequestCommandError(..) => return "UnknownRequestCommandError",
+            SOCKSError::UnknownAddressTypeError(..) => return "UnknownAddressTypeError",
+            SOCKSError::UnknownReservedByteError(..) => return "UnknownReservedByteError",
+            SOCKSError::UnknownProtocolViolationError(..) => return "UnknownProtocolViolationError",
+            SOCKSError::NoAuthMethodsError(..) => return "NoAuthMethodsError",
+            SOCKSError::TimeoutError(..) => return "TimeoutError",
+            SOCKSError::ConnectionLimitError(..) => return "ConnectionLimitError",
+            SOCKSError::DestinationError(..) => return "DestinationError",
+            SOCKSError::QuotaExceededError(..) => return "QuotaExceededError",
+            SOCKSError::StreamIOError(..) => return "StreamIOError",
+        }
+    }
+}
+
 // This is important for other errors to wrap this one.
 // TODO: Proper implementation
 impl error::Error for SOCKSError {
@@ -0,0 +1,231 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time;
+
+use socket2::SockRef;
+
+use crate::address::ClientAddress;
+use crate::socket_options::SocketOptions;
+use crate::tracker::{ByteCounters, ConnectionToken, Upstream};
+
+/// The stream a client is connected through.
+/// Whether it's a TCP or Unix domain socket stream depends on the kind of listener the client connected to.
+///
+/// While a connection is tracked by its server (see `ShutdownHandle`), it counts as active until
+/// this stream and all clones made of it with `try_clone` have been dropped.
+pub struct ClientStream {
+    socket: StreamSocket,
+    token: Option<Arc<ConnectionToken>>,
+}
+
+pub(crate) enum StreamSocket {
+    Tcp(net::TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    pub(crate) fn new(socket: StreamSocket) -> ClientStream {
+        return ClientStream {
+            socket: socket,
+            token: None,
+        };
+    }
+
+    pub(crate) fn set_token(&mut self, token: Arc<ConnectionToken>) {
+        self.token = Some(token);
+    }
+
+    /// The ID the server's connection tracker knows this stream by.
+    pub(crate) fn connection_id(&self) -> Option<u64> {
+        return self.token.as_ref().map(|token| token.id());
+    }
+
+    /// The counters the server's registry reports the relayed bytes of this stream from.
+    /// Untracked streams get counters nobody else sees.
+    pub(crate) fn byte_counters(&self) -> Arc<ByteCounters> {
+        match &self.token {
+            Some(token) => return token.counters(),
+            None => return Arc::new(ByteCounters::default()),
+        }
+    }
+
+    /// Records the stream this one is relayed to, so that closing the connection through the server closes both.
+    pub(crate) fn set_upstream(&self, upstream: Arc<dyn Upstream>) {
+        if let Some(token) = &self.token {
+            token.set_upstream(upstream);
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<ClientStream, io::Error> {
+        return Ok(ClientStream {
+            socket: self.socket.try_clone()?,
+            token: self.token.clone(),
+        });
+    }
+
+    /// Clones the stream without the tracking token, so that holding the clone doesn't keep the connection active.
+    pub(crate) fn try_clone_untracked(&self) -> Result<ClientStream, io::Error> {
+        return Ok(ClientStream::new(self.socket.try_clone()?));
+    }
+
+    pub fn shutdown(&self, how: net::Shutdown) -> Result<(), io::Error> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return s.shutdown(how),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.shutdown(how),
+        }
+    }
+
+    pub(crate) fn apply_socket_options(&self, options: &SocketOptions) -> Result<(), io::Error> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return options.apply(SockRef::from(s)),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return options.apply(SockRef::from(s)),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<time::Duration>) -> Result<(), io::Error> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<time::Duration>) -> Result<(), io::Error> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.set_write_timeout(timeout),
+        }
+    }
+
+    /// Returns the underlying `TcpStream` if the client connected over TCP.
+    pub fn as_tcp(&self) -> Option<&net::TcpStream> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return Some(s),
+            #[cfg(unix)]
+            StreamSocket::Unix(_) => return None,
+        }
+    }
+
+    /// Returns the underlying `UnixStream` if the client connected over a Unix domain socket.
+    #[cfg(unix)]
+    pub fn as_unix(&self) -> Option<&UnixStream> {
+        match &self.socket {
+            StreamSocket::Tcp(_) => return None,
+            StreamSocket::Unix(s) => return Some(s),
+        }
+    }
+
+    pub(crate) fn client_address(&self) -> Result<ClientAddress, io::Error> {
+        match &self.socket {
+            StreamSocket::Tcp(s) => return Ok(ClientAddress::Tcp(s.peer_addr()?)),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return Ok(ClientAddress::Unix(peer_cred::get(s)?)),
+        }
+    }
+
+    /// The address to report as BND.ADDR in replies sent over this stream.
+    /// Unix domain sockets don't have one, so the unspecified IPv4 address is used for them.
+    pub(crate) fn reply_address(&self) -> net::SocketAddr {
+        let unspecified = net::SocketAddr::from((net::Ipv4Addr::UNSPECIFIED, 0));
+        match &self.socket {
+            StreamSocket::Tcp(s) => return s.local_addr().unwrap_or(unspecified),
+            #[cfg(unix)]
+            StreamSocket::Unix(_) => return unspecified,
+        }
+    }
+}
+
+impl StreamSocket {
+    fn try_clone(&self) -> Result<StreamSocket, io::Error> {
+        match self {
+            StreamSocket::Tcp(s) => return Ok(StreamSocket::Tcp(s.try_clone()?)),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return Ok(StreamSocket::Unix(s.try_clone()?)),
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.socket {
+            StreamSocket::Tcp(s) => return s.read(buf),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.socket {
+            StreamSocket::Tcp(s) => return s.write(buf),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.socket {
+            StreamSocket::Tcp(s) => return s.flush(),
+            #[cfg(unix)]
+            StreamSocket::Unix(s) => return s.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod peer_cred {
+    use crate::address::PeerCredentials;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn get(stream: &UnixStream) -> Result<PeerCredentials, io::Error> {
+        let mut ucred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `ucred` and `len` are valid for writes and `len` holds the size of `ucred`.
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(PeerCredentials {
+            uid: ucred.uid,
+            gid: ucred.gid,
+            pid: Some(ucred.pid),
+        });
+    }
+
+    // Other Unices don't have SO_PEERCRED, but can at least tell us the peer's effective uid/gid.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn get(stream: &UnixStream) -> Result<PeerCredentials, io::Error> {
+        let mut uid: libc::uid_t = 0;
+        let mut gid: libc::gid_t = 0;
+        // SAFETY: `uid` and `gid` are valid for writes.
+        let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(PeerCredentials {
+            uid: uid,
+            gid: gid,
+            pid: None,
+        });
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::process;
+
+// The first file descriptor passed by systemd, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A listening socket passed to this process by systemd socket activation.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ActivatedSocket {
+    pub fd: RawFd,
+    /// The name configured with `FileDescriptorName=` in the socket unit, or `"unknown"` if none was set.
+    pub name: String,
+}
+
+/// Returns the sockets passed to this process via `LISTEN_FDS`, `LISTEN_PID` and `LISTEN_FDNAMES`.
+///
+/// If the variables are unset or meant for another process (`LISTEN_PID` doesn't match ours), no sockets are returned.
+/// The variables are removed from the environment afterwards so they aren't inherited by child processes,
+/// which means this function only returns sockets the first time it's called.
+///
+/// The returned file descriptors are marked close-on-exec, but their ownership is not taken.
+/// Pass them to `Server::from_raw_fd` or close them yourself.
+pub fn listen_fds() -> Result<Vec<ActivatedSocket>, io::Error> {
+    let pid = env::var("LISTEN_PID");
+    let fds = env::var("LISTEN_FDS");
+    let names = env::var("LISTEN_FDNAMES");
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Ok(pid), Ok(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    if parse_var("LISTEN_PID", &pid)? != process::id() {
+        return Ok(Vec::new());
+    }
+    let count = parse_var("LISTEN_FDS", &fds)?;
+    let names: Vec<String> = match names {
+        Ok(names) => names.split(':').map(|name| name.to_string()).collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut sockets = Vec::new();
+    for i in 0..count {
+        let fd = SD_LISTEN_FDS_START + i as RawFd;
+        // SAFETY: Only manipulates the descriptor flags of `fd`, which systemd handed to us.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        sockets.push(ActivatedSocket {
+            fd: fd,
+            name: names.get(i as usize).cloned().unwrap_or_else(|| "unknown".to_string()),
+        });
+    }
+    return Ok(sockets);
+}
+
+fn parse_var(name: &str, value: &str) -> Result<u32, io::Error> {
+    return value.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} contains invalid value '{}'", name, value),
+        )
+    });
+}
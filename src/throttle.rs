@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+/// A token bucket rate limit.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// The sustained rate. A rate of 0 is treated as 1 byte per second.
+    pub bytes_per_second: u64,
+    /// How many bytes may be transferred at once after being idle, on top of the sustained rate.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// Limits the rate to `bytes_per_second`, with a burst of one second's worth of data.
+    pub fn new(bytes_per_second: u64) -> RateLimit {
+        return RateLimit {
+            bytes_per_second: bytes_per_second,
+            burst: bytes_per_second,
+        };
+    }
+}
+
+/// The rate limits for one direction of the relayed traffic.
+/// Every limit which is `None` is not enforced, the strictest of the others applies.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct DirectionLimits {
+    /// The limit for each connection on its own.
+    pub per_connection: Option<RateLimit>,
+    /// The limit shared by all connections of the same authenticated user.
+    /// Clients which didn't authenticate with a username are not subject to this limit.
+    pub per_user: Option<RateLimit>,
+    /// The limit shared by all connections of the server.
+    pub global: Option<RateLimit>,
+}
+
+/// Limits the bandwidth `Connection::relay` uses, see `Server::set_bandwidth_limits`.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct BandwidthLimits {
+    /// Limits on the data relayed from clients to their destinations.
+    pub upload: DirectionLimits,
+    /// Limits on the data relayed from destinations to their clients.
+    pub download: DirectionLimits,
+}
+
+impl BandwidthLimits {
+    fn direction(&self, direction: Direction) -> &DirectionLimits {
+        match direction {
+            Direction::Upload => return &self.upload,
+            Direction::Download => return &self.download,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Upload,
+    Download,
+}
+
+/// The token buckets shared by the connections of a server.
+pub(crate) struct Throttle {
+    state: Mutex<ThrottleState>,
+}
+
+struct ThrottleState {
+    limits: BandwidthLimits,
+    // Indexed by direction
+    global: [Bucket; 2],
+    users: HashMap<String, UserBuckets>,
+}
+
+struct UserBuckets {
+    // How many limiters use these buckets, they're removed once there are none
+    limiters: usize,
+    buckets: [Bucket; 2],
+}
+
+// Allowed to go into debt, which the transfer that caused it has to wait out
+#[derive(Default)]
+struct Bucket {
+    tokens: f64,
+    // `None` until the bucket is first used, at which point it's full
+    last: Option<time::Instant>,
+}
+
+impl Bucket {
+    // Takes `len` tokens and returns how long to wait for the bucket to be out of debt
+    fn take(&mut self, limit: Option<&RateLimit>, len: u64, now: time::Instant) -> time::Duration {
+        let limit = match limit {
+            Some(val) => val,
+            None => {
+                self.last = None;
+                return time::Duration::ZERO;
+            }
+        };
+        let rate = limit.bytes_per_second.max(1) as f64;
+        let burst = limit.burst as f64;
+        let tokens = match self.last {
+            Some(last) => (self.tokens + now.duration_since(last).as_secs_f64() * rate).min(burst),
+            None => burst,
+        };
+        self.last = Some(now);
+        self.tokens = tokens - len as f64;
+        if self.tokens >= 0.0 {
+            return time::Duration::ZERO;
+        }
+        return time::Duration::from_secs_f64(-self.tokens / rate);
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new() -> Arc<Throttle> {
+        return Arc::new(Throttle {
+            state: Mutex::new(ThrottleState {
+                limits: BandwidthLimits::default(),
+                global: Default::default(),
+                users: HashMap::new(),
+            }),
+        });
+    }
+
+    pub(crate) fn set_limits(&self, limits: BandwidthLimits) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    /// Returns a limiter for one direction of a connection, which authenticated as `user`.
+    pub(crate) fn limiter(self: &Arc<Self>, direction: Direction, user: Option<&str>) -> Limiter {
+        if let Some(user) = user {
+            let mut state = self.state.lock().unwrap();
+            let entry = state.users.entry(user.to_string()).or_insert_with(|| UserBuckets {
+                limiters: 0,
+                buckets: Default::default(),
+            });
+            entry.limiters += 1;
+        }
+        return Limiter {
+            throttle: self.clone(),
+            direction: direction,
+            user: user.map(|user| user.to_string()),
+            connection: Bucket::default(),
+        };
+    }
+
+    fn take(&self, limiter: &mut Limiter, len: u64) -> time::Duration {
+        let now = time::Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let limits = state.limits.direction(limiter.direction);
+        let index = limiter.direction as usize;
+        let mut delay = limiter.connection.take(limits.per_connection.as_ref(), len, now);
+        delay = delay.max(state.global[index].take(limits.global.as_ref(), len, now));
+        if let Some(user) = limiter.user.as_ref().and_then(|user| state.users.get_mut(user)) {
+            delay = delay.max(user.buckets[index].take(limits.per_user.as_ref(), len, now));
+        }
+        return delay;
+    }
+
+    // The smallest burst which applies to `direction`, so a single transfer doesn't put a bucket deep into debt
+    fn smallest_burst(&self, direction: Direction, has_user: bool) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let limits = state.limits.direction(direction);
+        let per_user = if has_user { limits.per_user } else { None };
+        return [limits.per_connection, per_user, limits.global].iter().flatten().map(|limit| limit.burst).min();
+    }
+}
+
+/// Throttles one direction of a relayed connection.
+pub(crate) struct Limiter {
+    throttle: Arc<Throttle>,
+    direction: Direction,
+    user: Option<String>,
+    connection: Bucket,
+}
+
+impl Limiter {
+    /// Returns how many bytes to transfer at most at once, given a buffer of `buf_len` bytes.
+    pub(crate) fn chunk_len(&self, buf_len: usize) -> usize {
+        match self.throttle.smallest_burst(self.direction, self.user.is_some()) {
+            Some(burst) => return buf_len.min(burst.max(1) as usize),
+            None => return buf_len,
+        }
+    }
+
+    /// Accounts for transferring `len` bytes, and blocks for as long as that exceeds the limits.
+    pub(crate) fn wait(&mut self, len: usize) {
+        let throttle = self.throttle.clone();
+        let delay = throttle.take(self, len as u64);
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+impl Drop for Limiter {
+    fn drop(&mut self) {
+        if let Some(user) = &self.user {
+            let mut state = self.throttle.state.lock().unwrap();
+            if let Some(entry) = state.users.get_mut(user) {
+                entry.limiters -= 1;
+                if entry.limiters == 0 {
+                    state.users.remove(user);
+                }
+            }
+        }
+    }
+}
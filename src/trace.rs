@@ -0,0 +1,44 @@
+// Spans and events for the `tracing` feature. Without it, these compile to nothing,
+// so the handshake can be instrumented without sprinkling `cfg` attributes all over it.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, info, debug_span as span, Span};
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! noop_event {
+    ($($arg:tt)*) => {
+        {}
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! noop_span {
+    ($($arg:tt)*) => {
+        $crate::trace::Span
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use {noop_event as debug, noop_event as info, noop_span as span};
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Entered;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn enter(&self) -> Entered {
+        return Entered;
+    }
+
+    pub(crate) fn record<V>(&self, _field: &str, _value: V) -> &Span {
+        return self;
+    }
+
+    pub(crate) fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        return f();
+    }
+}
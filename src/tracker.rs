@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time;
+
+use ignore_result::Ignore;
+
+use crate::address::{Address, ClientAddress};
+use crate::limits::{ConnectionLimits, LimitKind};
+use crate::registry::SessionInfo;
+use crate::stream::ClientStream;
+
+/// Keeps track of the connections accepted by a server,
+/// from the moment they're accepted until the consumer has dropped every clone of the client stream.
+pub(crate) struct ConnectionTracker {
+    state: Mutex<TrackerState>,
+    drained: Condvar,
+}
+
+struct TrackerState {
+    next_id: u64,
+    connections: HashMap<u64, TrackedConnection>,
+    per_client: HashMap<net::IpAddr, usize>,
+    per_user: HashMap<String, usize>,
+    limits: ConnectionLimits,
+}
+
+struct TrackedConnection {
+    // An untracked clone of the client stream, so the connection can be closed forcefully
+    stream: ClientStream,
+    // The stream to the destination while the connection is being relayed, which is closed along with it
+    upstream: Option<Arc<dyn Upstream>>,
+    client: ClientAddress,
+    client_ip: Option<net::IpAddr>,
+    user: Option<String>,
+    destination: Option<(Address, u16)>,
+    started: time::SystemTime,
+    counters: Arc<ByteCounters>,
+}
+
+/// A stream `Connection::relay` relays to, which can be closed from any thread.
+pub(crate) trait Upstream: Send + Sync {
+    fn shutdown(&self, how: net::Shutdown) -> Result<(), io::Error>;
+}
+
+impl Upstream for net::TcpStream {
+    fn shutdown(&self, how: net::Shutdown) -> Result<(), io::Error> {
+        return net::TcpStream::shutdown(self, how);
+    }
+}
+
+/// How much `Connection::relay` has transferred so far, in each direction.
+#[derive(Default)]
+pub(crate) struct ByteCounters {
+    pub(crate) client_to_upstream: AtomicU64,
+    pub(crate) upstream_to_client: AtomicU64,
+}
+
+/// Held by every clone of a tracked `ClientStream`. The connection is untracked once the last one is dropped.
+pub(crate) struct ConnectionToken {
+    id: u64,
+    tracker: Arc<ConnectionTracker>,
+    counters: Arc<ByteCounters>,
+}
+
+impl ConnectionToken {
+    pub(crate) fn id(&self) -> u64 {
+        return self.id;
+    }
+
+    pub(crate) fn counters(&self) -> Arc<ByteCounters> {
+        return self.counters.clone();
+    }
+
+    /// Records the stream the connection is relayed to, so it's closed when the connection is.
+    pub(crate) fn set_upstream(&self, upstream: Arc<dyn Upstream>) {
+        if let Some(conn) = self.tracker.state.lock().unwrap().connections.get_mut(&self.id) {
+            conn.upstream = Some(upstream);
+        }
+    }
+}
+
+impl Drop for ConnectionToken {
+    fn drop(&mut self) {
+        self.tracker.untrack(self.id);
+    }
+}
+
+impl ConnectionTracker {
+    pub(crate) fn new() -> Arc<ConnectionTracker> {
+        return Arc::new(ConnectionTracker {
+            state: Mutex::new(TrackerState {
+                next_id: 0,
+                connections: HashMap::new(),
+                per_client: HashMap::new(),
+                per_user: HashMap::new(),
+                limits: ConnectionLimits::default(),
+            }),
+            drained: Condvar::new(),
+        });
+    }
+
+    /// Starts tracking `stream`.
+    /// Returns the overall or per-client limit the connection exceeds, if any. It's tracked either way.
+    pub(crate) fn track(self: &Arc<Self>, stream: &mut ClientStream) -> Result<Option<LimitKind>, io::Error> {
+        let untracked = stream.try_clone_untracked()?;
+        let client = stream.client_address()?;
+        let client_ip = match &client {
+            ClientAddress::Tcp(addr) => Some(addr.ip().to_canonical()),
+            ClientAddress::Unix(_) => None,
+        };
+        let counters = Arc::new(ByteCounters::default());
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.connections.insert(
+            id,
+            TrackedConnection {
+                stream: untracked,
+                upstream: None,
+                client: client,
+                client_ip: client_ip,
+                user: None,
+                destination: None,
+                started: time::SystemTime::now(),
+                counters: counters.clone(),
+            },
+        );
+        stream.set_token(Arc::new(ConnectionToken {
+            id: id,
+            tracker: self.clone(),
+            counters: counters,
+        }));
+
+        let mut exceeded = None;
+        if let Some(max) = state.limits.max_connections {
+            if state.connections.len() > max {
+                exceeded = Some(LimitKind::Total(max));
+            }
+        }
+        if let Some(ip) = client_ip {
+            let count = state.per_client.entry(ip).or_insert(0);
+            *count += 1;
+            let count = *count;
+            if let Some(max) = state.limits.max_per_client {
+                if exceeded.is_none() && count > max {
+                    exceeded = Some(LimitKind::PerClient(max));
+                }
+            }
+        }
+        return Ok(exceeded);
+    }
+
+    /// Records that the connection `id` authenticated as `user`.
+    /// Returns the per-user limit the connection exceeds, if any.
+    pub(crate) fn set_user(&self, id: u64, user: &str) -> Option<LimitKind> {
+        let mut state = self.state.lock().unwrap();
+        match state.connections.get_mut(&id) {
+            Some(conn) => conn.user = Some(user.to_string()),
+            None => return None,
+        }
+        let count = state.per_user.entry(user.to_string()).or_insert(0);
+        *count += 1;
+        let count = *count;
+        match state.limits.max_per_user {
+            Some(max) if count > max => return Some(LimitKind::PerUser(user.to_string(), max)),
+            _ => return None,
+        }
+    }
+
+    /// Records the destination the connection `id` requested.
+    pub(crate) fn set_destination(&self, id: u64, addr: Address, port: u16) {
+        if let Some(conn) = self.state.lock().unwrap().connections.get_mut(&id) {
+            conn.destination = Some((addr, port));
+        }
+    }
+
+    fn untrack(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        let conn = match state.connections.remove(&id) {
+            Some(val) => val,
+            None => return,
+        };
+        if let Some(ip) = conn.client_ip {
+            decrement(&mut state.per_client, &ip);
+        }
+        if let Some(user) = conn.user {
+            decrement(&mut state.per_user, &user);
+        }
+        if state.connections.is_empty() {
+            self.drained.notify_all();
+        }
+    }
+
+    pub(crate) fn limits(&self) -> ConnectionLimits {
+        return self.state.lock().unwrap().limits.clone();
+    }
+
+    pub(crate) fn set_limits(&self, limits: ConnectionLimits) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        return self.state.lock().unwrap().connections.len();
+    }
+
+    pub(crate) fn per_client(&self) -> HashMap<net::IpAddr, usize> {
+        return self.state.lock().unwrap().per_client.clone();
+    }
+
+    pub(crate) fn per_user(&self) -> HashMap<String, usize> {
+        return self.state.lock().unwrap().per_user.clone();
+    }
+
+    /// Blocks until no connections are tracked anymore, or `timeout` expires.
+    /// Returns whether all connections have drained.
+    pub(crate) fn wait_drained(&self, timeout: Option<time::Duration>) -> bool {
+        let state = self.state.lock().unwrap();
+        match timeout {
+            Some(timeout) => {
+                let (state, _) = self
+                    .drained
+                    .wait_timeout_while(state, timeout, |state| !state.connections.is_empty())
+                    .unwrap();
+                return state.connections.is_empty();
+            }
+            None => {
+                let state = self.drained.wait_while(state, |state| !state.connections.is_empty()).unwrap();
+                return state.connections.is_empty();
+            }
+        }
+    }
+
+    /// Returns a snapshot of every tracked connection, ordered by id.
+    pub(crate) fn sessions(&self) -> Vec<SessionInfo> {
+        let state = self.state.lock().unwrap();
+        let mut sessions: Vec<SessionInfo> = state
+            .connections
+            .iter()
+            .map(|(id, conn)| SessionInfo {
+                id: *id,
+                client: conn.client.clone(),
+                user: conn.user.clone(),
+                destination: conn.destination.clone(),
+                started: conn.started,
+                bytes_in: conn.counters.client_to_upstream.load(Ordering::Relaxed),
+                bytes_out: conn.counters.upstream_to_client.load(Ordering::Relaxed),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        return sessions;
+    }
+
+    /// Shuts down every tracked stream, which makes blocked reads and writes on them return.
+    /// The connections stay tracked until the consumer drops their streams.
+    pub(crate) fn close_all(&self) {
+        self.close_matching(|_, _| true);
+    }
+
+    /// Shuts down the connection `id`. Returns whether it's tracked.
+    pub(crate) fn close(&self, id: u64) -> bool {
+        return self.close_matching(|conn_id, _| conn_id == id) > 0;
+    }
+
+    /// Shuts down all connections which authenticated as `user`, and returns how many there were.
+    pub(crate) fn close_user(&self, user: &str) -> usize {
+        return self.close_matching(|_, conn| conn.user.as_deref() == Some(user));
+    }
+
+    fn close_matching<F: Fn(u64, &TrackedConnection) -> bool>(&self, matches: F) -> usize {
+        let state = self.state.lock().unwrap();
+        let mut closed = 0;
+        for (id, conn) in state.connections.iter() {
+            if matches(*id, conn) {
+                conn.stream.shutdown(net::Shutdown::Both).ignore();
+                if let Some(upstream) = &conn.upstream {
+                    upstream.shutdown(net::Shutdown::Both).ignore();
+                }
+                closed += 1;
+            }
+        }
+        return closed;
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = counts.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
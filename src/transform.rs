@@ -0,0 +1,18 @@
+use std::io::{Read, Write};
+
+/// Transforms the data a `Connection` exchanges with its upstream, e.g. to frame, pad or encrypt it,
+/// see `Connection::relay_with`.
+///
+/// A transform wraps both halves of the upstream connection: the reader yields the data to send to the client,
+/// and whatever the client sends is written to the writer. State both halves need, like keys, can be shared through the transform.
+/// Any handshake with the upstream has to be performed before relaying, on the stream passed to `relay_with`.
+///
+/// The relay calls `flush` on the writer after writing each chunk read from the client, and once the client is done sending,
+/// before closing the upstream's sending side. Errors on either half close both connections.
+pub trait StreamTransform: Send {
+    /// Wraps the reading half of the upstream connection.
+    fn wrap_reader(&mut self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send>;
+
+    /// Wraps the writing half of the upstream connection.
+    fn wrap_writer(&mut self, writer: Box<dyn Write + Send>) -> Box<dyn Write + Send>;
+}
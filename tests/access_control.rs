@@ -0,0 +1,163 @@
+use socks5_frontend;
+use socks5_frontend::acl::{AccessControlList, AccessRequest, Action, DestinationPattern, Rule};
+use socks5_frontend::{Address, ClientAddress, IpNetwork, ReplyType};
+
+use std::net;
+use std::thread;
+
+mod common;
+use common::*;
+
+fn client(ip: &str) -> ClientAddress {
+    return ClientAddress::Tcp(net::SocketAddr::new(ip.parse().unwrap(), 40000));
+}
+
+fn evaluate(acl: &AccessControlList, destination: Address, port: u16, user: Option<&str>, client: &ClientAddress) -> Action {
+    return acl
+        .evaluate(&AccessRequest {
+            destination: &destination,
+            port: port,
+            user: user,
+            client: client,
+        })
+        .action;
+}
+
+fn example_acl() -> AccessControlList {
+    return AccessControlList::new(
+        vec![
+            // Nobody gets to reach the internal network, except admins from the office
+            Rule {
+                users: vec!["admin".to_string()],
+                sources: vec!["192.168.1.0/24".parse().unwrap()],
+                ..Rule::allow()
+            },
+            Rule {
+                destinations: vec!["10.0.0.0/8".parse().unwrap(), "*.internal.example".parse().unwrap()],
+                ..Rule::deny()
+            },
+            Rule {
+                destinations: vec!["tracker.example".parse().unwrap()],
+                action: Action::Reject(ReplyType::DestinationUnreachable),
+                ..Rule::deny()
+            },
+            // Only web traffic otherwise
+            Rule {
+                ports: vec![80..=80, 443..=443],
+                ..Rule::allow()
+            },
+        ],
+        Action::Deny,
+    );
+}
+
+#[test]
+fn test_first_match_wins() {
+    let acl = example_acl();
+    let office = client("192.168.1.20");
+    let home = client("203.0.113.7");
+
+    let internal = Address::V4("10.1.2.3".parse().unwrap());
+    assert_eq!(evaluate(&acl, internal.clone(), 443, None, &home), Action::Deny);
+    assert_eq!(evaluate(&acl, internal.clone(), 443, Some("admin"), &home), Action::Deny);
+    assert_eq!(evaluate(&acl, internal.clone(), 443, Some("randall"), &office), Action::Deny);
+    assert_eq!(evaluate(&acl, internal, 22, Some("admin"), &office), Action::Allow);
+
+    let public = Address::V4("198.51.100.1".parse().unwrap());
+    assert_eq!(evaluate(&acl, public.clone(), 443, None, &home), Action::Allow);
+    // Falls through to the default action
+    assert_eq!(evaluate(&acl, public, 22, None, &home), Action::Deny);
+}
+
+#[test]
+fn test_domain_patterns() {
+    let acl = example_acl();
+    let home = client("203.0.113.7");
+    let domain = |name: &str| Address::DomainName(name.to_string());
+
+    assert_eq!(evaluate(&acl, domain("wiki.internal.example"), 443, None, &home), Action::Deny);
+    assert_eq!(evaluate(&acl, domain("WIKI.Internal.Example."), 443, None, &home), Action::Deny);
+    // The wildcard doesn't cover the domain itself, or domains merely ending the same way
+    assert_eq!(evaluate(&acl, domain("internal.example"), 443, None, &home), Action::Allow);
+    assert_eq!(evaluate(&acl, domain("notinternal.example"), 443, None, &home), Action::Allow);
+    assert_eq!(
+        evaluate(&acl, domain("tracker.example"), 443, None, &home),
+        Action::Reject(ReplyType::DestinationUnreachable)
+    );
+}
+
+#[test]
+fn test_ipv4_mapped_destinations_match_ipv4_networks() {
+    let acl = example_acl();
+    let mapped = Address::V6("::ffff:10.1.2.3".parse().unwrap());
+    assert_eq!(evaluate(&acl, mapped, 443, None, &client("203.0.113.7")), Action::Deny);
+}
+
+#[test]
+fn test_ip_addresses_sent_as_domain_names_match_networks() {
+    let acl = example_acl();
+    let home = client("203.0.113.7");
+    let domain = |name: &str| Address::DomainName(name.to_string());
+
+    assert_eq!(evaluate(&acl, domain("10.0.0.1"), 443, None, &home), Action::Deny);
+    assert_eq!(evaluate(&acl, domain("::ffff:10.0.0.1"), 443, None, &home), Action::Deny);
+    assert_eq!(evaluate(&acl, domain("198.51.100.1"), 443, None, &home), Action::Allow);
+}
+
+#[test]
+fn test_ipv6_networks_match_ipv4_addresses() {
+    let mapped: IpNetwork = "::ffff:0:0/96".parse().unwrap();
+    let any: IpNetwork = "::/0".parse().unwrap();
+    let ten: IpNetwork = "::ffff:10.0.0.0/104".parse().unwrap();
+    for ip in ["10.1.2.3", "::ffff:10.1.2.3"] {
+        let ip: net::IpAddr = ip.parse().unwrap();
+        assert!(mapped.contains(ip), "{}", ip);
+        assert!(any.contains(ip), "{}", ip);
+        assert!(ten.contains(ip), "{}", ip);
+    }
+    assert!(!ten.contains("11.1.2.3".parse().unwrap()));
+    assert!(!mapped.contains("2001:db8::1".parse().unwrap()));
+    assert!(!"10.0.0.0/8".parse::<IpNetwork>().unwrap().contains("2001:db8::1".parse().unwrap()));
+}
+
+#[test]
+fn test_invalid_patterns() {
+    assert!("10.0.0.0/33".parse::<DestinationPattern>().is_err());
+    assert!("example.com/8".parse::<DestinationPattern>().is_err());
+    assert!("*.*.example.com".parse::<DestinationPattern>().is_err());
+    assert!("*".parse::<DestinationPattern>().is_err());
+}
+
+#[test]
+fn test_apply_decision_replies_to_refused_requests() {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    thread::spawn(move || {
+        let acl = example_acl();
+        for connection in server {
+            let conn = connection.unwrap();
+            let decision = conn.check_access(&acl);
+            if let Some(conn) = conn.apply_decision(&decision).unwrap() {
+                conn.report_success().unwrap();
+            }
+        }
+    });
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [10, 0, 0, 1], 80);
+    // Connection not allowed
+    assert_eq!(reply[1], 0x02);
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [198, 51, 100, 1], 80);
+    // Succeeded
+    assert_eq!(reply[1], 0x00);
+}
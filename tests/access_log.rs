@@ -0,0 +1,165 @@
+use socks5_frontend;
+use socks5_frontend::access_log::{AccessLog, LogFormat};
+use socks5_frontend::{Address, ClientAddress, Command, ReplyType, ServerObserver, SessionRecord};
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("socks5_frontend-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    return path;
+}
+
+fn record() -> SessionRecord {
+    return SessionRecord {
+        // 2021-03-04T05:06:07.089Z
+        started: time::UNIX_EPOCH + time::Duration::from_millis(1614834367089),
+        duration: time::Duration::from_millis(1500),
+        client: ClientAddress::Tcp("192.0.2.1:4711".parse().unwrap()),
+        user: Some("randall".to_string()),
+        command: Some(Command::Connect),
+        destination: Some((Address::DomainName("example.com".to_string()), 443)),
+        reply: Some(ReplyType::Succeeded),
+        bytes_in: 100,
+        bytes_out: 2000,
+    };
+}
+
+#[test]
+fn test_formats() {
+    let plain = AccessLog::from_writer(std::io::sink(), LogFormat::Plain);
+    assert_eq!(
+        plain.format(&record()),
+        "2021-03-04T05:06:07.089Z 192.0.2.1:4711 randall connect example.com:443 succeeded 1.500s 100 2000\n"
+    );
+    let json = AccessLog::from_writer(std::io::sink(), LogFormat::Json);
+    assert_eq!(
+        json.format(&record()),
+        "{\"timestamp\":\"2021-03-04T05:06:07.089Z\",\"client\":\"192.0.2.1:4711\",\"user\":\"randall\",\"command\":\"connect\",\"destination\":\"example.com:443\",\"reply\":\"succeeded\",\"duration_ms\":1500,\"bytes_in\":100,\"bytes_out\":2000}\n"
+    );
+    let logfmt = AccessLog::from_writer(std::io::sink(), LogFormat::Logfmt);
+    assert_eq!(
+        logfmt.format(&record()),
+        "ts=2021-03-04T05:06:07.089Z client=192.0.2.1:4711 user=randall command=connect destination=example.com:443 reply=succeeded duration=1.500s bytes_in=100 bytes_out=2000\n"
+    );
+
+    // Missing fields, and an IPv6 destination
+    let mut session = record();
+    session.user = None;
+    session.reply = None;
+    session.destination = Some((Address::V6(net::Ipv6Addr::LOCALHOST), 80));
+    assert_eq!(
+        plain.format(&session),
+        "2021-03-04T05:06:07.089Z 192.0.2.1:4711 - connect [::1]:80 - 1.500s 100 2000\n"
+    );
+    assert!(json.format(&session).contains("\"user\":null,\"command\":\"connect\",\"destination\":\"[::1]:80\",\"reply\":null"));
+    assert!(logfmt.format(&session).contains("client=192.0.2.1:4711 command=connect destination=[::1]:80 duration="));
+}
+
+#[test]
+fn test_untrusted_values_cannot_forge_lines() {
+    let mut session = record();
+    session.destination = Some((Address::DomainName("evil.com\n2021-01-01T00:00:00.000Z".to_string()), 80));
+    session.user = Some("a \"b\"".to_string());
+    for format in [LogFormat::Plain, LogFormat::Json, LogFormat::Logfmt] {
+        let line = AccessLog::from_writer(std::io::sink(), format).format(&session);
+        assert_eq!(line.lines().count(), 1, "{}", line);
+        assert!(line.contains("\\\"b\\\""));
+    }
+}
+
+#[test]
+fn test_redacted_destinations() {
+    let mut log = AccessLog::from_writer(std::io::sink(), LogFormat::Logfmt);
+    log.set_redact_destinations(true);
+    let line = log.format(&record());
+    assert!(line.contains("destination=[redacted] "));
+    assert!(!line.contains("example.com"));
+}
+
+#[test]
+fn test_rotation() {
+    let path = temp_path("rotation.log");
+    let mut log = AccessLog::open(&path, LogFormat::Plain).unwrap();
+    let line_len = log.format(&record()).len() as u64;
+    // Two lines fit into each file
+    log.set_rotation(2 * line_len, 2);
+    for _ in 0..7 {
+        log.on_session_finished(&record());
+    }
+
+    let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+    assert_eq!(fs::read_to_string(rotated(1)).unwrap().lines().count(), 2);
+    assert_eq!(fs::read_to_string(rotated(2)).unwrap().lines().count(), 2);
+    assert!(!rotated(3).exists());
+    for index in 1..=2 {
+        fs::remove_file(rotated(index)).unwrap();
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_sessions_are_logged() {
+    let path = temp_path("sessions.log");
+    let log = Arc::new(AccessLog::open(&path, LogFormat::Json).unwrap());
+
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.set_observer(log);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            if let Ok((conn, upstream)) = connection.and_then(|conn| conn.connect()) {
+                let _ = conn.relay(upstream);
+            }
+            tx.send(()).unwrap();
+        }
+    });
+
+    let dest = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = dest.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut stream, _) = dest.accept().unwrap();
+        stream.write_all(b"Hello").unwrap();
+    });
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut req = vec![5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut response = Vec::new();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(&response[12..], b"Hello");
+    rx.recv().unwrap();
+
+    // A client which doesn't speak SOCKS5
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.write_all(&[4, 1, 0, 80, 127, 0, 0, 1, 0]).unwrap();
+    rx.recv().unwrap();
+
+    let contents = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(&format!(
+        "\"user\":null,\"command\":\"connect\",\"destination\":\"127.0.0.1:{}\",\"reply\":\"succeeded\",",
+        port
+    )));
+    assert!(lines[0].ends_with("\"bytes_in\":0,\"bytes_out\":5}"));
+    assert!(lines[1].contains("\"command\":null,\"destination\":null,\"reply\":\"general_failure\""));
+    fs::remove_file(&path).unwrap();
+}
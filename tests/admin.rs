@@ -0,0 +1,157 @@
+use socks5_frontend;
+use socks5_frontend::admin::Admin;
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// Starts a server relaying every connection to its destination, with the admin interface served on the second address.
+fn start_server() -> (net::SocketAddr, net::SocketAddr, mpsc::Receiver<()>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    let admin_listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+    Arc::new(Admin::new(server.registry())).serve(admin_listener).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            tx.send(()).unwrap();
+            thread::spawn(move || {
+                if let Ok((conn, upstream)) = connection.and_then(|conn| conn.connect()) {
+                    let _ = conn.relay(upstream);
+                }
+            });
+        }
+    });
+    return (addr, admin_addr, rx);
+}
+
+fn connect(addr: net::SocketAddr, port: u16) -> net::TcpStream {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4_as_randall(&mut stream, [127, 0, 0, 1], port);
+    assert_eq!(reply[1], 0x00);
+    return stream;
+}
+
+fn request(addr: net::SocketAddr, method: &str, path: &str) -> (String, String) {
+    return request_with_headers(addr, method, path, "X-Socks5-Admin: 1\r\n");
+}
+
+fn request_with_headers(addr: net::SocketAddr, method: &str, path: &str, headers: &str) -> (String, String) {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n", method, path, headers).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    return (head.lines().next().unwrap().to_string(), body.to_string());
+}
+
+fn assert_closed(stream: &mut net::TcpStream) {
+    stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
+    let mut buf = [0; 1];
+    match stream.read(&mut buf) {
+        Ok(len) => assert_eq!(len, 0),
+        Err(e) => assert_ne!(e.kind(), std::io::ErrorKind::WouldBlock),
+    }
+}
+
+/// Polls `path` until its response contains `expected`.
+fn wait_for(admin_addr: net::SocketAddr, path: &str, expected: &str) -> String {
+    for _ in 0..100 {
+        let (_, body) = request(admin_addr, "GET", path);
+        if body.contains(expected) {
+            return body;
+        }
+        thread::sleep(time::Duration::from_millis(20));
+    }
+    panic!("'{}' never contained '{}'", path, expected);
+}
+
+#[test]
+fn test_list_and_kill_sessions() {
+    let port = start_echo_server();
+    let (addr, admin_addr, accepted) = start_server();
+
+    let mut first = connect(addr, port);
+    accepted.recv().unwrap();
+    first.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    first.read_exact(&mut buf).unwrap();
+    let sessions = wait_for(admin_addr, "/sessions", "\"bytes_in\":4,\"bytes_out\":4}");
+    assert!(sessions.starts_with("[{\"id\":"), "{}", sessions);
+    assert!(sessions.contains(&format!(
+        "\"client\":\"{}\",\"user\":\"randall\",\"destination\":\"127.0.0.1:{}\",\"started\":",
+        first.local_addr().unwrap(),
+        port
+    )));
+    let id: String = sessions["[{\"id\":".len()..].chars().take_while(|c| c.is_ascii_digit()).collect();
+
+    assert_eq!(request(admin_addr, "DELETE", "/sessions/4711").0, "HTTP/1.1 404 Not Found");
+    assert_eq!(
+        request(admin_addr, "DELETE", &format!("/sessions/{}", id)),
+        ("HTTP/1.1 200 OK".to_string(), "{\"killed\":1}".to_string())
+    );
+    assert_closed(&mut first);
+    // The session is gone once the relay has finished
+    wait_for(admin_addr, "/status", "\"sessions\":0");
+
+    let mut second = connect(addr, port);
+    let mut third = connect(addr, port);
+    accepted.recv().unwrap();
+    accepted.recv().unwrap();
+    assert_eq!(
+        request(admin_addr, "DELETE", "/users/%72andall/sessions"),
+        ("HTTP/1.1 200 OK".to_string(), "{\"killed\":2}".to_string())
+    );
+    assert_closed(&mut second);
+    assert_closed(&mut third);
+
+    assert_eq!(request(admin_addr, "POST", "/sessions").0, "HTTP/1.1 405 Method Not Allowed");
+    assert_eq!(request(admin_addr, "GET", "/nope").0, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn test_pause_and_resume() {
+    let port = start_echo_server();
+    let (addr, admin_addr, accepted) = start_server();
+
+    assert_eq!(
+        request(admin_addr, "POST", "/pause"),
+        ("HTTP/1.1 200 OK".to_string(), "{\"paused\":true,\"sessions\":0}".to_string())
+    );
+    let client = thread::spawn(move || connect(addr, port));
+    assert!(accepted.recv_timeout(time::Duration::from_millis(300)).is_err());
+
+    assert!(request(admin_addr, "POST", "/resume").1.starts_with("{\"paused\":false,"));
+    accepted.recv_timeout(time::Duration::from_secs(5)).unwrap();
+    let _stream = client.join().unwrap();
+    assert_eq!(request(admin_addr, "GET", "/status").1, "{\"paused\":false,\"sessions\":1}");
+}
+
+#[test]
+fn test_changes_need_admin_header() {
+    let (_, admin_addr, _) = start_server();
+
+    // What a form on any web page could send
+    let (status, _) = request_with_headers(admin_addr, "POST", "/pause", "Content-Type: application/x-www-form-urlencoded\r\n");
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    assert_eq!(request_with_headers(admin_addr, "DELETE", "/users/randall/sessions", "").0, "HTTP/1.1 403 Forbidden");
+    assert_eq!(request_with_headers(admin_addr, "GET", "/status", "").1, "{\"paused\":false,\"sessions\":0}");
+
+    let (status, _) = request_with_headers(admin_addr, "POST", "/pause", "x-socks5-admin: yes\r\n");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}
@@ -0,0 +1,129 @@
+use socks5_frontend;
+use socks5_frontend::{BandwidthLimits, DirectionLimits, RateLimit};
+
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// Starts a server relaying every connection on its own thread.
+fn start_server(limits: BandwidthLimits) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_bandwidth_limits(limits);
+    thread::spawn(move || {
+        for connection in server {
+            thread::spawn(move || {
+                if let Ok((conn, upstream)) = connection.and_then(|conn| conn.connect()) {
+                    let _ = conn.relay(upstream);
+                }
+            });
+        }
+    });
+    return addr;
+}
+
+/// Starts a server which sends `len` bytes to every client, then reads until the client is done and replies with how much it read.
+fn start_dest_server(len: usize) -> u16 {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                stream.write_all(&vec![0; len]).unwrap();
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).unwrap();
+                stream.write_all(&(received.len() as u64).to_be_bytes()).unwrap();
+            });
+        }
+    });
+    return port;
+}
+
+fn connect(addr: net::SocketAddr, port: u16) -> net::TcpStream {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4_as_randall(&mut stream, [127, 0, 0, 1], port);
+    assert_eq!(reply[1], 0x00);
+    return stream;
+}
+
+/// Sends `upload` bytes through a new connection and receives everything, returning how long it took.
+fn transfer(addr: net::SocketAddr, port: u16, upload: usize, download: usize) -> time::Duration {
+    let started = time::Instant::now();
+    let mut stream = connect(addr, port);
+    let mut received = vec![0; download];
+    stream.read_exact(&mut received).unwrap();
+    stream.write_all(&vec![0; upload]).unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut count = [0; 8];
+    stream.read_exact(&mut count).unwrap();
+    assert_eq!(u64::from_be_bytes(count), upload as u64);
+    return started.elapsed();
+}
+
+#[test]
+fn test_per_connection_download_limit() {
+    let limits = BandwidthLimits {
+        download: DirectionLimits {
+            per_connection: Some(RateLimit {
+                bytes_per_second: 64 * 1024,
+                burst: 16 * 1024,
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let addr = start_server(limits);
+    let port = start_dest_server(64 * 1024);
+    // The burst is free, the remaining 48 KiB take 0.75s
+    let elapsed = transfer(addr, port, 1024, 64 * 1024);
+    assert!(elapsed >= time::Duration::from_millis(650), "{:?}", elapsed);
+    assert!(elapsed < time::Duration::from_secs(5), "{:?}", elapsed);
+}
+
+#[test]
+fn test_limits_are_shared() {
+    let shared = RateLimit {
+        bytes_per_second: 32 * 1024,
+        burst: 8 * 1024,
+    };
+    for upload in [
+        DirectionLimits { per_user: Some(shared), ..Default::default() },
+        DirectionLimits { global: Some(shared), ..Default::default() },
+    ] {
+        let addr = start_server(BandwidthLimits {
+            upload: upload,
+            ..Default::default()
+        });
+        let port = start_dest_server(0);
+        // Each connection alone would take 0.5s, together they need to wait for 40 KiB
+        let started = time::Instant::now();
+        let clients: Vec<_> = (0..2).map(|_| thread::spawn(move || transfer(addr, port, 24 * 1024, 0))).collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= time::Duration::from_millis(1100), "{:?} with {:?}", elapsed, upload);
+        assert!(elapsed < time::Duration::from_secs(5), "{:?} with {:?}", elapsed, upload);
+    }
+}
+
+#[test]
+fn test_unlimited_by_default() {
+    let addr = start_server(BandwidthLimits::default());
+    let port = start_dest_server(4 * 1024 * 1024);
+    let elapsed = transfer(addr, port, 4 * 1024 * 1024, 4 * 1024 * 1024);
+    assert!(elapsed < time::Duration::from_secs(5), "{:?}", elapsed);
+}
@@ -1,3 +1,8 @@
+// Not every test binary uses every helper.
+#![allow(dead_code)]
+
+use std::io::{self, Read, Write};
+use std::net;
 use std::thread;
 use tiny_http;
 
@@ -25,3 +30,57 @@ pub fn start_dest_server() -> (u16, u16) {
 
     return (port_v4, port_v6);
 }
+
+/// Starts a server which echoes everything it receives, and closes its end once the client has.
+pub fn start_echo_server() -> u16 {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut read = stream.try_clone().unwrap();
+                let _ = io::copy(&mut read, &mut stream);
+                let _ = stream.shutdown(net::Shutdown::Write);
+            });
+        }
+    });
+    return port;
+}
+
+/// Performs a no-auth SOCKS5 handshake on `stream` and sends a CONNECT request for `dst_ip`:`dst_port`.
+/// Returns the reply sent by the server, which is 10 bytes long for an IPv4 BND.ADDR.
+pub fn socks_connect_v4<S: Read + Write>(stream: &mut S, dst_ip: [u8; 4], dst_port: u16) -> Vec<u8> {
+    stream.write_all(&[5, 1, 0]).unwrap();
+    let mut method_buf = [0; 2];
+    stream.read_exact(&mut method_buf).unwrap();
+    assert_eq!(method_buf, [5, 0]);
+    return request_connect_v4(stream, dst_ip, dst_port);
+}
+
+/// Like `socks_connect_v4`, but authenticates as randall with username/password authentication.
+pub fn socks_connect_v4_as_randall<S: Read + Write>(stream: &mut S, dst_ip: [u8; 4], dst_port: u16) -> Vec<u8> {
+    stream.write_all(&[5, 1, 2]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [5, 2]);
+
+    let mut auth = vec![1, 7];
+    auth.extend_from_slice(b"randall");
+    auth.push(25);
+    auth.extend_from_slice(b"CorrectHorseBatteryStaple");
+    stream.write_all(&auth).unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [1, 0]);
+    return request_connect_v4(stream, dst_ip, dst_port);
+}
+
+fn request_connect_v4<S: Read + Write>(stream: &mut S, dst_ip: [u8; 4], dst_port: u16) -> Vec<u8> {
+    let mut req = vec![5, 1, 0, 1];
+    req.extend_from_slice(&dst_ip);
+    req.extend_from_slice(&dst_port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = vec![0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    return reply;
+}
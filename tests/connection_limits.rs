@@ -0,0 +1,130 @@
+use socks5_frontend;
+
+use std::io::Read;
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+mod common;
+use common::*;
+
+type Outcome = Result<socks5_frontend::ClientStream, socks5_frontend::Error>;
+
+/// Starts a server which keeps every successful connection open, and reports the outcome of each one.
+fn start_server(
+    auth_methods: Vec<socks5_frontend::AuthMethod>,
+    limits: socks5_frontend::ConnectionLimits,
+) -> (net::SocketAddr, socks5_frontend::ConnectionStats, mpsc::Receiver<Outcome>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        auth_methods,
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_connection_limits(limits);
+    let stats = server.connection_stats();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let outcome = connection.map(|conn| conn.report_success().unwrap().get_stream());
+            tx.send(outcome).unwrap();
+        }
+    });
+    return (addr, stats, rx);
+}
+
+fn connect(addr: net::SocketAddr) -> (net::TcpStream, Vec<u8>) {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    return (stream, reply);
+}
+
+fn connect_as_randall(addr: net::SocketAddr) -> (net::TcpStream, Vec<u8>) {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4_as_randall(&mut stream, [127, 0, 0, 1], 80);
+    return (stream, reply);
+}
+
+fn expect_limit_error(outcome: Outcome) -> socks5_frontend::LimitKind {
+    match outcome {
+        Err(socks5_frontend::Error::ConnectionLimitError(_, limit)) => return limit,
+        Err(e) => panic!("Expected a connection limit error, got '{}'", e),
+        Ok(_) => panic!("Expected a connection limit error, but the connection succeeded"),
+    }
+}
+
+#[test]
+fn test_total_limit_closes_at_accept() {
+    let limits = socks5_frontend::ConnectionLimits {
+        max_connections: Some(1),
+        ..Default::default()
+    };
+    let (addr, stats, outcomes) = start_server(vec![socks5_frontend::AuthMethod::NoAuth], limits);
+
+    let (_first, reply) = connect(addr);
+    // Succeeded
+    assert_eq!(reply[1], 0x00);
+    let _first_stream = outcomes.recv().unwrap().unwrap();
+    assert_eq!(stats.total(), 1);
+
+    // The second client is closed before it gets to negotiate
+    let mut second = net::TcpStream::connect(addr).unwrap();
+    assert_eq!(expect_limit_error(outcomes.recv().unwrap()), socks5_frontend::LimitKind::Total(1));
+    let mut buf = [0; 1];
+    assert_eq!(second.read(&mut buf).unwrap_or(0), 0);
+    assert_eq!(stats.total(), 1);
+}
+
+#[test]
+fn test_per_client_limit_replies_failure() {
+    let limits = socks5_frontend::ConnectionLimits {
+        max_per_client: Some(1),
+        action: socks5_frontend::LimitAction::ReplyFailure,
+        ..Default::default()
+    };
+    let (addr, stats, outcomes) = start_server(vec![socks5_frontend::AuthMethod::NoAuth], limits);
+
+    let (_first, reply) = connect(addr);
+    assert_eq!(reply[1], 0x00);
+    let first_stream = outcomes.recv().unwrap().unwrap();
+    let localhost: net::IpAddr = "127.0.0.1".parse().unwrap();
+    assert_eq!(stats.per_client().get(&localhost), Some(&1));
+
+    let (_second, reply) = connect(addr);
+    // General SOCKS server failure
+    assert_eq!(reply[1], 0x01);
+    assert_eq!(expect_limit_error(outcomes.recv().unwrap()), socks5_frontend::LimitKind::PerClient(1));
+
+    // Once the first connection is gone, there's room again
+    drop(first_stream);
+    let (_third, reply) = connect(addr);
+    assert_eq!(reply[1], 0x00);
+    outcomes.recv().unwrap().unwrap();
+}
+
+#[test]
+fn test_per_user_limit() {
+    let limits = socks5_frontend::ConnectionLimits {
+        max_per_user: Some(1),
+        action: socks5_frontend::LimitAction::ReplyFailure,
+        ..Default::default()
+    };
+    let (addr, stats, outcomes) = start_server(vec![socks5_frontend::AuthMethod::UsernamePassword], limits);
+
+    let (_first, reply) = connect_as_randall(addr);
+    assert_eq!(reply[1], 0x00);
+    let _first_stream = outcomes.recv().unwrap().unwrap();
+    assert_eq!(stats.per_user().get("randall"), Some(&1));
+
+    let (_second, reply) = connect_as_randall(addr);
+    assert_eq!(reply[1], 0x01);
+    assert_eq!(
+        expect_limit_error(outcomes.recv().unwrap()),
+        socks5_frontend::LimitKind::PerUser("randall".to_string(), 1)
+    );
+}
@@ -0,0 +1,126 @@
+use socks5_frontend;
+use socks5_frontend::{DestinationGuard, Dialer};
+
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+
+mod common;
+use common::*;
+
+/// Starts a server which connects every client through `dialer` and echoes back whatever the client sends.
+fn start_server(dialer: Dialer) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.set_dialer(dialer);
+    thread::spawn(move || {
+        for connection in server {
+            let (conn, mut upstream) = match connection.unwrap().connect() {
+                Ok(connected) => connected,
+                Err(_) => continue,
+            };
+            let mut client = conn.get_stream();
+            thread::spawn(move || {
+                let mut buf = [0; 5];
+                client.read_exact(&mut buf).unwrap();
+                upstream.write_all(&buf).unwrap();
+                upstream.read_exact(&mut buf).unwrap();
+                client.write_all(&buf).unwrap();
+            });
+        }
+    });
+    return addr;
+}
+
+fn guarded_dialer(guard: DestinationGuard) -> Dialer {
+    let mut dialer = Dialer::new();
+    dialer.set_destination_guard(Some(guard));
+    return dialer;
+}
+
+#[test]
+fn test_default_guard_blocks_loopback() {
+    let port = start_echo_server();
+    let addr = start_server(guarded_dialer(DestinationGuard::default()));
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], port);
+    // Connection not allowed by ruleset
+    assert_eq!(reply[1], 0x02);
+
+    // Names are resolved and checked too
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.write_all(&[5, 1, 0]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    let mut req = vec![5, 1, 0, 3, 9];
+    req.extend_from_slice(b"localhost");
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = vec![0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x02);
+}
+
+#[test]
+fn test_allowed_exception() {
+    let port = start_echo_server();
+    let guard = DestinationGuard {
+        allowed: vec!["127.0.0.1/32".parse().unwrap()],
+        ..Default::default()
+    };
+    let addr = start_server(guarded_dialer(guard));
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], port);
+    assert_eq!(reply[1], 0x00);
+    // BND.ADDR is the local address of the connection to the destination
+    assert_eq!(&reply[4..8], &[127, 0, 0, 1]);
+    stream.write_all(b"Hello").unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Hello");
+}
+
+#[test]
+fn test_unguarded_dialer_reports_refused_connections() {
+    // Bind and drop a listener to get a port nothing listens on
+    let port = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = start_server(Dialer::new());
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], port);
+    // Connection refused
+    assert_eq!(reply[1], 0x05);
+}
+
+#[test]
+fn test_default_guard_networks() {
+    let guard = DestinationGuard::default();
+    let blocked = |ip: &str| guard.is_blocked(ip.parse().unwrap());
+
+    assert!(blocked("169.254.169.254"));
+    assert!(blocked("192.168.1.1"));
+    assert!(blocked("0.0.0.0"));
+    assert!(blocked("::1"));
+    assert!(blocked("::ffff:10.0.0.1"));
+    assert!(blocked("fd00::1"));
+    assert!(blocked("fe80::1"));
+    assert!(blocked("192.0.2.1"));
+    assert!(blocked("198.51.100.1"));
+    assert!(blocked("203.0.113.1"));
+    assert!(blocked("2001:db8::1"));
+    // Teredo and 6to4 addresses wrapping 10.0.0.1
+    assert!(blocked("2001:0:4136:e378:8000:63bf:f5ff:fffe"));
+    assert!(blocked("2002:a00:1::1"));
+    assert!(!blocked("8.8.8.8"));
+    assert!(!blocked("2001:4860:4860::8888"));
+}
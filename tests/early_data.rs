@@ -0,0 +1,91 @@
+use socks5_frontend;
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+fn start_server() -> (net::SocketAddr, socks5_frontend::Server) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    return (addr, server);
+}
+
+/// Sends the method selection, the request and `data` in a single write, as clients sending optimistic data do.
+fn send_pipelined(stream: &mut net::TcpStream, port: u16, data: &[u8]) {
+    let mut msg = vec![5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1];
+    msg.extend_from_slice(&port.to_be_bytes());
+    msg.extend_from_slice(data);
+    stream.write_all(&msg).unwrap();
+}
+
+#[test]
+fn test_take_early_data() {
+    let (addr, server) = start_server();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let mut conn = connection.unwrap();
+            let early = conn.take_early_data();
+            let again = conn.take_early_data();
+            let conn = conn.report_success().unwrap();
+            let mut client = conn.get_stream();
+            let mut rest = [0; 5];
+            client.read_exact(&mut rest).unwrap();
+            tx.send((early, again, rest)).unwrap();
+        }
+    });
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    send_pipelined(&mut stream, 80, b"GET / HTTP/1.0\r\n\r\n");
+    let mut reply = [0; 12];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply[..2], &[5, 0]);
+    assert_eq!(reply[3], 0x00);
+    // Data sent after the reply is read from the stream as usual
+    stream.write_all(b"later").unwrap();
+
+    let (early, again, rest) = rx.recv().unwrap();
+    assert_eq!(early, b"GET / HTTP/1.0\r\n\r\n");
+    assert!(again.is_empty());
+    assert_eq!(&rest, b"later");
+}
+
+#[test]
+fn test_connect_forwards_early_data() {
+    let dest = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = dest.local_addr().unwrap().port();
+    let (dest_tx, dest_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = dest.accept().unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        dest_tx.send(buf).unwrap();
+    });
+
+    let (addr, server) = start_server();
+    thread::spawn(move || {
+        let mut connected = Vec::new();
+        for connection in server {
+            let (mut conn, upstream) = connection.unwrap().connect().unwrap();
+            // Already written to the destination
+            assert!(conn.take_early_data().is_empty());
+            connected.push((conn, upstream));
+        }
+    });
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    send_pipelined(&mut stream, port, b"Hello");
+    assert_eq!(&dest_rx.recv().unwrap(), b"Hello");
+    let mut reply = [0; 12];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[3], 0x00);
+}
@@ -0,0 +1,126 @@
+use socks5_frontend;
+
+use std::io::Read;
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+fn start_server() -> (socks5_frontend::Server, net::SocketAddr) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    return (server, addr);
+}
+
+/// Accepts every connection and hands the client stream to the test, without relaying anything.
+fn hand_out_streams(server: socks5_frontend::Server) -> (thread::JoinHandle<()>, mpsc::Receiver<socks5_frontend::ClientStream>) {
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        for connection in server {
+            let conn = connection.unwrap().report_success().unwrap();
+            tx.send(conn.get_stream()).unwrap();
+        }
+    });
+    return (handle, rx);
+}
+
+fn wait_until_refused(addr: net::SocketAddr) {
+    for _ in 0..100 {
+        if net::TcpStream::connect(addr).is_err() {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    panic!("Server is still accepting connections");
+}
+
+#[test]
+fn test_shutdown_stops_accepting() {
+    let (server, addr) = start_server();
+    let handle = server.shutdown_handle();
+    let (iterator, _streams) = hand_out_streams(server);
+
+    handle.shutdown();
+    assert!(handle.is_shut_down());
+    iterator.join().unwrap();
+    wait_until_refused(addr);
+}
+
+#[test]
+fn test_wait_drained() {
+    let (server, addr) = start_server();
+    let handle = server.shutdown_handle();
+    let (iterator, streams) = hand_out_streams(server);
+
+    let mut client = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut client, [127, 0, 0, 1], 80);
+    // Succeeded
+    assert_eq!(reply[1], 0x00);
+    let stream = streams.recv().unwrap();
+    let stream_clone = stream.try_clone().unwrap();
+
+    handle.shutdown();
+    iterator.join().unwrap();
+    assert_eq!(handle.active_connections(), 1);
+    assert!(!handle.wait_drained(Some(time::Duration::from_millis(50))));
+
+    // The connection is active until every clone of the stream is gone
+    drop(stream);
+    assert!(!handle.wait_drained(Some(time::Duration::from_millis(50))));
+    drop(stream_clone);
+    assert!(handle.wait_drained(Some(time::Duration::from_secs(1))));
+    assert_eq!(handle.active_connections(), 0);
+}
+
+#[test]
+fn test_shutdown_gracefully_aborts_after_grace_period() {
+    let (server, addr) = start_server();
+    let handle = server.shutdown_handle();
+    let (_iterator, streams) = hand_out_streams(server);
+
+    let mut client = net::TcpStream::connect(addr).unwrap();
+    socks_connect_v4(&mut client, [127, 0, 0, 1], 80);
+    let mut stream = streams.recv().unwrap();
+    // A relay waiting for data from the client
+    let relay = thread::spawn(move || {
+        let mut buf = [0; 1];
+        return stream.read(&mut buf).unwrap_or(0);
+    });
+
+    assert!(!handle.shutdown_gracefully(time::Duration::from_millis(100)));
+    // The aborted relay reads EOF and drops its stream
+    assert_eq!(relay.join().unwrap(), 0);
+    assert!(handle.wait_drained(Some(time::Duration::from_secs(1))));
+}
+
+#[test]
+fn test_dropping_server_stops_accepting() {
+    let (server, addr) = start_server();
+    drop(server);
+    wait_until_refused(addr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shutdown_on_signal() {
+    let (server, addr) = start_server();
+    let handle = server.shutdown_handle();
+    handle.shutdown_on_signal(libc::SIGUSR1).unwrap();
+    let (iterator, _streams) = hand_out_streams(server);
+
+    unsafe { libc::kill(libc::getpid(), libc::SIGUSR1) };
+    iterator.join().unwrap();
+    assert!(handle.is_shut_down());
+    wait_until_refused(addr);
+}
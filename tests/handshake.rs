@@ -0,0 +1,63 @@
+use socks5_frontend;
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+fn start_server() -> (net::SocketAddr, mpsc::Receiver<Result<(), socks5_frontend::Error>>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let result = connection.and_then(|conn| Ok(conn.report_success()?));
+            tx.send(result.map(|_| ())).unwrap();
+        }
+    });
+    return (addr, rx);
+}
+
+#[test]
+fn test_pipelined_username_password_handshake() {
+    let (addr, results) = start_server();
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut msg = vec![5, 1, 2, 1, 7];
+    msg.extend_from_slice(b"randall");
+    msg.push(25);
+    msg.extend_from_slice(b"CorrectHorseBatteryStaple");
+    msg.extend_from_slice(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]);
+    stream.write_all(&msg).unwrap();
+
+    // Method selection, authentication status and reply
+    let mut buf = [0; 14];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..4], &[5, 2, 1, 0]);
+    assert_eq!(&buf[4..7], &[5, 0, 0]);
+    assert!(results.recv().unwrap().is_ok());
+}
+
+#[test]
+fn test_truncated_credentials() {
+    let (addr, results) = start_server();
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.write_all(&[5, 1, 2]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [5, 2]);
+    stream.write_all(&[1, 7, b'r', b'a']).unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+
+    match results.recv().unwrap() {
+        Err(socks5_frontend::Error::StreamIOError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,65 @@
+use socks5_frontend;
+use socks5_frontend::resolver::StaticResolver;
+use socks5_frontend::{Address, Dialer};
+
+use std::net;
+use std::sync::Arc;
+use std::time;
+
+/// Starts a listener on `ip`:`port` which never completes a handshake,
+/// by keeping its accept queue filled with a connection it doesn't accept.
+fn start_blackhole(ip: &str, port: u16) -> (socket2::Socket, net::TcpStream) {
+    let addr: net::SocketAddr = format!("{}:{}", ip, port).parse().unwrap();
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+    socket.bind(&addr.into()).unwrap();
+    socket.listen(0).unwrap();
+    let filler = net::TcpStream::connect(addr).unwrap();
+    return (socket, filler);
+}
+
+fn dialer_for(addrs: &[&str]) -> Dialer {
+    let mut hosts = StaticResolver::new();
+    hosts.insert("dest.test", addrs.iter().map(|ip| ip.parse().unwrap()).collect());
+    let mut dialer = Dialer::new();
+    dialer.set_resolver(Arc::new(hosts));
+    dialer.set_connect_timeout(Some(time::Duration::from_secs(5)));
+    return dialer;
+}
+
+#[test]
+fn test_stalled_address_is_raced() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let _blackholes = [start_blackhole("127.0.0.2", port), start_blackhole("127.0.0.3", port)];
+
+    let mut dialer = dialer_for(&["127.0.0.2", "127.0.0.3", "127.0.0.1"]);
+    dialer.set_attempt_delay(Some(time::Duration::from_millis(100)));
+    let start = time::Instant::now();
+    let stream = dialer.dial(&Address::DomainName("dest.test".to_string()), port).unwrap();
+    assert!(start.elapsed() < time::Duration::from_secs(2));
+    assert_eq!(stream.peer_addr().unwrap(), net::SocketAddr::from(([127, 0, 0, 1], port)));
+}
+
+#[test]
+fn test_sequential_dialing_waits_for_timeout() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let _blackhole = start_blackhole("127.0.0.2", port);
+
+    let mut dialer = dialer_for(&["127.0.0.2", "127.0.0.1"]);
+    dialer.set_attempt_delay(None);
+    dialer.set_connect_timeout(Some(time::Duration::from_millis(500)));
+    let start = time::Instant::now();
+    let stream = dialer.dial(&Address::DomainName("dest.test".to_string()), port).unwrap();
+    assert!(start.elapsed() >= time::Duration::from_millis(500));
+    assert_eq!(stream.peer_addr().unwrap(), net::SocketAddr::from(([127, 0, 0, 1], port)));
+}
+
+#[test]
+fn test_all_attempts_failing() {
+    // Bind and drop a listener to get a port nothing listens on
+    let port = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let dialer = dialer_for(&["127.0.0.2", "127.0.0.1"]);
+    let err = dialer.dial(&Address::DomainName("dest.test".to_string()), port).unwrap_err();
+    assert_eq!(err.reply(), socks5_frontend::ReplyType::ConnectionRefused);
+}
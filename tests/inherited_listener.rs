@@ -0,0 +1,154 @@
+use socks5_frontend;
+
+#[cfg(unix)]
+use std::env;
+use std::net;
+use std::thread;
+
+mod common;
+use common::*;
+
+// Set in the environment of the child process spawned by `test_systemd_socket_activation`.
+#[cfg(unix)]
+const CHILD_ENV_VAR: &str = "SOCKS5_FRONTEND_SYSTEMD_CHILD";
+
+/// Refuses every connection made to `server`.
+fn refuse_all(server: socks5_frontend::Server) {
+    for connection in server {
+        connection.unwrap().report_connection_not_allowed().unwrap();
+    }
+}
+
+#[test]
+fn test_from_tcp_listener() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::from_tcp_listener(
+        listener,
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    );
+    thread::spawn(move || refuse_all(server));
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    // Connection not allowed
+    assert_eq!(reply[1], 0x02);
+}
+
+/// Runs in a child process which received a socket the way systemd passes them.
+/// Does nothing when run as part of the normal test suite.
+#[cfg(unix)]
+#[test]
+fn systemd_child() {
+    if env::var(CHILD_ENV_VAR).is_err() {
+        return;
+    }
+    let sockets = socks5_frontend::systemd::listen_fds().unwrap();
+    assert_eq!(sockets.len(), 1);
+    assert_eq!(sockets[0].fd, 3);
+    assert_eq!(sockets[0].name, "socks");
+    // The variables must not leak to our own children
+    assert!(env::var("LISTEN_FDS").is_err());
+
+    // from_systemd() consumes the environment itself, so restore it.
+    env::set_var("LISTEN_PID", std::process::id().to_string());
+    env::set_var("LISTEN_FDS", "1");
+    let mut server = socks5_frontend::Server::from_systemd(
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.next().unwrap().unwrap().report_connection_not_allowed().unwrap();
+}
+
+/// Runs `test` in a child process, passing it `fds` the way systemd passes sockets.
+#[cfg(unix)]
+fn spawn_activated_child(test: &str, fds: Vec<std::os::unix::io::RawFd>, names: &str) -> std::process::Child {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    // systemd sets LISTEN_PID to the PID of the service, which we only know once it's running.
+    // Let a shell export its own PID and then exec the test binary, which keeps the PID.
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("LISTEN_PID=$$ exec \"$0\" \"$@\"")
+        .arg(env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture"])
+        .env(CHILD_ENV_VAR, "1")
+        .env("LISTEN_FDS", fds.len().to_string())
+        .env("LISTEN_FDNAMES", names);
+    unsafe {
+        command.pre_exec(move || {
+            // Move the sockets out of the way first, so that none is overwritten by moving another one to its place.
+            // The copies don't survive exec.
+            let mut moved = Vec::new();
+            for fd in &fds {
+                let copy = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 100);
+                if copy < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                moved.push(copy);
+            }
+            // Then move them to the fds systemd passes, which survive exec.
+            for (i, fd) in moved.into_iter().enumerate() {
+                if libc::dup2(fd, 3 + i as libc::c_int) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            return Ok(());
+        });
+    }
+    return command.spawn().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_systemd_socket_activation() {
+    use std::os::unix::io::AsRawFd;
+
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut child = spawn_activated_child("systemd_child", vec![listener.as_raw_fd()], "socks");
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    assert_eq!(reply[1], 0x02);
+    assert!(child.wait().unwrap().success());
+}
+
+/// Runs in a child process which received a datagram socket followed by a listener.
+/// Does nothing when run as part of the normal test suite.
+#[cfg(unix)]
+#[test]
+fn systemd_invalid_child() {
+    if env::var(CHILD_ENV_VAR).is_err() {
+        return;
+    }
+    let result = socks5_frontend::Server::from_systemd(None, vec![socks5_frontend::AuthMethod::NoAuth], None, None);
+    assert!(result.is_err());
+    // Neither the socket which failed nor the one after it may be leaked
+    for fd in [3, 4] {
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1, "fd {} is still open", fd);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_systemd_sockets_are_closed_on_failure() {
+    use std::os::unix::io::AsRawFd;
+
+    let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut child = spawn_activated_child(
+        "systemd_invalid_child",
+        vec![socket.as_raw_fd(), listener.as_raw_fd()],
+        "dgram:socks",
+    );
+    assert!(child.wait().unwrap().success());
+}
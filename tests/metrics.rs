@@ -0,0 +1,110 @@
+use socks5_frontend;
+use socks5_frontend::metrics::Metrics;
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// Starts a server relaying every connection, whose metrics are served on the returned address.
+fn start_server() -> (net::SocketAddr, net::SocketAddr, mpsc::Receiver<()>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    let metrics = Arc::new(Metrics::new(server.connection_stats()));
+    server.set_observer(metrics.clone());
+    let metrics_listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_addr = metrics_listener.local_addr().unwrap();
+    metrics.serve(metrics_listener).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            if let Ok((conn, upstream)) = connection.and_then(|conn| conn.connect()) {
+                let _ = conn.relay(upstream);
+            }
+            tx.send(()).unwrap();
+        }
+    });
+    return (addr, metrics_addr, rx);
+}
+
+fn connect(addr: net::SocketAddr, password: &[u8], port: u16) -> Option<net::TcpStream> {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut msg = vec![5, 1, 2, 1, 7];
+    msg.extend_from_slice(b"randall");
+    msg.push(password.len() as u8);
+    msg.extend_from_slice(password);
+    stream.write_all(&msg).unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).unwrap();
+    if buf[3] != 0 {
+        return None;
+    }
+    let mut req = vec![5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x00);
+    return Some(stream);
+}
+
+fn scrape(addr: net::SocketAddr, path: &str) -> String {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    return response;
+}
+
+#[test]
+fn test_metrics_endpoint() {
+    let dest = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = dest.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut stream, _) = dest.accept().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        stream.write_all(b"pong").unwrap();
+    });
+    let (addr, metrics_addr, done) = start_server();
+
+    let mut stream = connect(addr, b"CorrectHorseBatteryStaple", port).unwrap();
+    stream.write_all(b"ping!").unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    done.recv().unwrap();
+    assert!(connect(addr, b"hunter2", port).is_none());
+    done.recv().unwrap();
+    drop(stream);
+
+    let response = scrape(metrics_addr, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    for line in [
+        "# TYPE socks5_connections_accepted_total counter",
+        "socks5_connections_accepted_total 2",
+        "socks5_connections_active 0",
+        "socks5_auth_failures_total 1",
+        "socks5_handshake_errors_total{error=\"WrongCredentialsError\"} 1",
+        "socks5_replies_total{reply=\"succeeded\"} 1",
+        "# TYPE socks5_handshake_duration_seconds histogram",
+        "socks5_handshake_duration_seconds_bucket{le=\"+Inf\"} 1",
+        "socks5_handshake_duration_seconds_count 1",
+        "socks5_relayed_bytes_total{direction=\"client_to_upstream\"} 5",
+        "socks5_relayed_bytes_total{direction=\"upstream_to_client\"} 4",
+    ] {
+        assert!(body.lines().any(|l| l == line), "missing '{}' in:\n{}", line, body);
+    }
+
+    assert!(scrape(metrics_addr, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
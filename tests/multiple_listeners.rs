@@ -0,0 +1,98 @@
+use socks5_frontend;
+
+use std::io::Read;
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+mod common;
+use common::*;
+
+/// Refuses every connection made to `server`, reporting the listener each one arrived on.
+fn start_refusing_server(server: socks5_frontend::Server) -> mpsc::Receiver<socks5_frontend::ListenerInfo> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let conn = connection.unwrap();
+            tx.send(conn.get_listener().clone()).unwrap();
+            conn.report_connection_not_allowed().unwrap();
+        }
+    });
+    return rx;
+}
+
+#[test]
+fn test_connections_report_their_listener() {
+    let v4 = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let v6 = socks5_frontend::Listener::bind_ipv6("[::1]:0".parse().unwrap(), true).unwrap();
+    // Unnamed listeners are fine too
+    let v4_unnamed = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+
+    let listener_addrs = [
+        v4.local_addr().unwrap(),
+        v6.local_addr().unwrap(),
+        v4_unnamed.local_addr().unwrap(),
+    ];
+    let server = socks5_frontend::Server::with_listeners(
+        vec![v4.named("v4"), v6.named("v6"), v4_unnamed],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    let listeners = start_refusing_server(server);
+
+    let expected_names = [Some("v4".to_string()), Some("v6".to_string()), None];
+    for (index, addr) in listener_addrs.iter().enumerate() {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+        // Connection not allowed
+        assert_eq!(reply[1], 0x02);
+        if reply[3] == 0x04 {
+            // Read the rest of the IPv6 BND.ADDR, so closing the stream doesn't reset the connection
+            let mut rest = [0; 12];
+            stream.read_exact(&mut rest).unwrap();
+        }
+        let info = listeners.recv().unwrap();
+        assert_eq!(info.index, index);
+        assert_eq!(info.name, expected_names[index]);
+    }
+}
+
+#[test]
+fn test_v6_only_listener_shares_port_with_v4() {
+    let v4 = socks5_frontend::Listener::bind_tcp("0.0.0.0:0".parse().unwrap()).unwrap();
+    let port = v4.local_addr().unwrap().port();
+    let v6 = socks5_frontend::Listener::bind_ipv6(format!("[::]:{}", port).parse().unwrap(), true).unwrap();
+
+    let server = socks5_frontend::Server::with_listeners(
+        vec![v4, v6],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    let listeners = start_refusing_server(server);
+
+    let mut stream = net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    assert_eq!(listeners.recv().unwrap().index, 0);
+
+    let mut stream = net::TcpStream::connect(("::1", port)).unwrap();
+    socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    assert_eq!(listeners.recv().unwrap().index, 1);
+}
+
+#[test]
+fn test_server_needs_a_listener() {
+    let result = socks5_frontend::Server::with_listeners(
+        Vec::new(),
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    );
+    assert!(result.is_err());
+}
@@ -0,0 +1,193 @@
+use socks5_frontend;
+use socks5_frontend::client::{Client, ClientError};
+use socks5_frontend::mux::{MuxClient, MuxServer};
+use socks5_frontend::{Address, Dialer, ReplyType};
+
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// Starts a demux server, which counts the connections it accepted.
+fn start_mux_server() -> (net::SocketAddr, Arc<AtomicUsize>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let carriers = Arc::new(AtomicUsize::new(0));
+    let accepted = carriers.clone();
+    let server = Arc::new(MuxServer::new(Dialer::new()));
+    thread::spawn(move || {
+        for carrier in listener.incoming() {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let server = server.clone();
+            thread::spawn(move || server.serve(carrier.unwrap(), Vec::new()));
+        }
+    });
+    return (addr, carriers);
+}
+
+/// Starts a server relaying every connection through `mux`.
+fn start_server(mux: MuxClient) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    thread::spawn(move || {
+        for conn in server.flatten() {
+            let mux = mux.clone();
+            thread::spawn(move || mux.relay(conn));
+        }
+    });
+    return addr;
+}
+
+fn connect(addr: net::SocketAddr, port: u16) -> Result<net::TcpStream, ClientError> {
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let client = Client::handshake(stream, None)?;
+    let (stream, _) = client.connect(&Address::V4("127.0.0.1".parse().unwrap()), port)?;
+    return Ok(stream);
+}
+
+#[test]
+fn test_connections_share_one_carrier() {
+    let (mux_addr, carriers) = start_mux_server();
+    let mux = MuxClient::new(net::TcpStream::connect(mux_addr).unwrap(), Vec::new()).unwrap();
+    let addr = start_server(mux);
+    let port = start_echo_server();
+
+    // Each connection transfers more than a window, so the receivers need to keep granting more
+    let clients: Vec<_> = (0..5u8)
+        .map(|n| {
+            thread::spawn(move || {
+                let mut stream = connect(addr, port).unwrap();
+                let data: Vec<u8> = (0..600_000u32).map(|i| (i % 249) as u8 ^ n).collect();
+                let mut read = stream.try_clone().unwrap();
+                let reader = thread::spawn(move || {
+                    let mut received = Vec::new();
+                    read.read_to_end(&mut received).unwrap();
+                    return received;
+                });
+                stream.write_all(&data).unwrap();
+                stream.shutdown(net::Shutdown::Write).unwrap();
+                assert!(reader.join().unwrap() == data);
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    assert_eq!(carriers.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_failures_are_replied() {
+    let (mux_addr, _) = start_mux_server();
+    let mux = MuxClient::new(net::TcpStream::connect(mux_addr).unwrap(), Vec::new()).unwrap();
+    let addr = start_server(mux);
+
+    // Nothing listens on the port of a listener which was closed
+    let closed_port = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    match connect(addr, closed_port) {
+        Err(ClientError::RequestFailedError(rep)) => assert_eq!(rep, ReplyType::ConnectionRefused),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+
+    // The carrier is still usable
+    let mut stream = connect(addr, start_echo_server()).unwrap();
+    stream.write_all(b"Hello").unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"Hello");
+}
+
+#[test]
+fn test_closing_resets_streams() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mux_addr = listener.local_addr().unwrap();
+    Arc::new(MuxServer::new(Dialer::new())).serve_listener(listener, Vec::new).unwrap();
+    let mux = MuxClient::new(net::TcpStream::connect(mux_addr).unwrap(), Vec::new()).unwrap();
+    let addr = start_server(mux.clone());
+    let port = start_echo_server();
+
+    let mut stream = connect(addr, port).unwrap();
+    stream.write_all(b"Hello").unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).unwrap();
+
+    mux.close();
+    assert!(mux.is_closed());
+    stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest);
+    assert!(rest.is_empty());
+    match connect(addr, port) {
+        Err(ClientError::RequestFailedError(rep)) => assert_eq!(rep, ReplyType::GeneralSocksServerFailure),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_last_stream_id_is_a_protocol_error() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (carrier, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || MuxServer::new(Dialer::new()).serve(carrier, Vec::new()));
+
+    // Opens the stream with the highest id, which leaves no id for the next one
+    // An IPv4 address and a port
+    let payload = [1, 127, 0, 0, 1, 0, 1];
+    let mut frame = vec![0, 0, 0, 1];
+    frame.extend_from_slice(&u32::MAX.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    client.write_all(&frame).unwrap();
+
+    let err = server.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_streams_beyond_the_limit_are_reset() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mux_addr = listener.local_addr().unwrap();
+    let mut server = MuxServer::new(Dialer::new());
+    server.set_max_streams(2);
+    Arc::new(server).serve_listener(listener, Vec::new).unwrap();
+    let mux = MuxClient::new(net::TcpStream::connect(mux_addr).unwrap(), Vec::new()).unwrap();
+    let localhost = Address::V4("127.0.0.1".parse().unwrap());
+    let port = start_echo_server();
+
+    let first = mux.open(&localhost, port).unwrap();
+    let _second = mux.open(&localhost, port).unwrap();
+    match mux.open(&localhost, port) {
+        Err(ClientError::StreamIOError(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    assert!(!mux.is_closed());
+
+    // Closing a stream makes room for another one, once the server has noticed
+    drop(first);
+    let mut stream = None;
+    for _ in 0..100 {
+        if let Ok(opened) = mux.open(&localhost, port) {
+            stream = Some(opened);
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    let mut stream = stream.unwrap();
+    stream.write_all(b"Hello").unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"Hello");
+}
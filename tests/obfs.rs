@@ -0,0 +1,147 @@
+use socks5_frontend;
+use socks5_frontend::client::Client;
+use socks5_frontend::obfs::{ObfsConfig, ObfsListener, ObfsTransform};
+use socks5_frontend::Address;
+
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// Starts the client side of the transport: a server relaying every connection through `ObfsTransform`,
+/// configured from the arguments its clients pass.
+fn start_pt_client() -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        None,
+        None,
+    )
+    .unwrap();
+    server.accept_pt_arguments();
+    thread::spawn(move || {
+        for conn in server.flatten() {
+            let config = match conn.get_pt_arguments().map(ObfsConfig::from_pt_arguments) {
+                Some(Ok(config)) => config,
+                _ => {
+                    let _ = conn.report_connection_not_allowed();
+                    continue;
+                }
+            };
+            thread::spawn(move || {
+                if let Ok((conn, upstream)) = conn.connect_keeping_early_data() {
+                    let _ = conn.relay_with(upstream, vec![Box::new(ObfsTransform::new(config))]);
+                }
+            });
+        }
+    });
+    return addr;
+}
+
+/// Starts the server side of the transport, forwarding to an echo server.
+fn start_pt_server(config: ObfsConfig) -> net::SocketAddr {
+    let listener = ObfsListener::bind("127.0.0.1:0".parse().unwrap(), config).unwrap();
+    let addr = listener.local_addr().unwrap();
+    listener.forward(net::SocketAddr::from(([127, 0, 0, 1], start_echo_server()))).unwrap();
+    return addr;
+}
+
+/// Connects to `bridge` through the client side of the transport, passing the config of `client_config`.
+fn connect(client: net::SocketAddr, bridge: net::SocketAddr, client_config: &ObfsConfig) -> net::TcpStream {
+    let stream = net::TcpStream::connect(client).unwrap();
+    let client = Client::handshake(stream, Some(&client_config.to_pt_arguments().to_credentials())).unwrap();
+    let (stream, _) = client.connect(&Address::from(bridge.ip()), bridge.port()).unwrap();
+    return stream;
+}
+
+#[test]
+fn test_round_trip() {
+    let config = ObfsConfig::new(b"a shared secret of some length").unwrap();
+    let client = start_pt_client();
+    let bridge = start_pt_server(config.clone());
+
+    let mut stream = connect(client, bridge, &config);
+    stream.write_all(b"Hello").unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"Hello");
+
+    // Spans many frames, the reply is read concurrently so neither side blocks on a full buffer
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut read = stream.try_clone().unwrap();
+    let len = data.len();
+    let reader = thread::spawn(move || {
+        let mut received = vec![0; len];
+        read.read_exact(&mut received).unwrap();
+        return received;
+    });
+    stream.write_all(&data).unwrap();
+    assert_eq!(reader.join().unwrap(), data);
+
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_wrong_secret_is_disconnected() {
+    let client = start_pt_client();
+    let bridge = start_pt_server(ObfsConfig::new(b"the secret of the server").unwrap());
+
+    let mut stream = connect(client, bridge, &ObfsConfig::new(b"not the secret of the server").unwrap());
+    stream.write_all(b"Hello").unwrap();
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received);
+    assert!(received.is_empty());
+}
+
+#[test]
+fn test_stop_forwarding() {
+    let config = ObfsConfig::new(b"a shared secret of some length").unwrap();
+    let client = start_pt_client();
+    let listener = ObfsListener::bind("127.0.0.1:0".parse().unwrap(), config.clone()).unwrap();
+    let bridge = listener.local_addr().unwrap();
+    let handle = listener.forward(net::SocketAddr::from(([127, 0, 0, 1], start_echo_server()))).unwrap();
+
+    let mut stream = connect(client, bridge, &config);
+    stream.write_all(b"Hello").unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).unwrap();
+    handle.stop();
+    assert!(handle.is_stopped());
+    // The listener is closed once its thread has woken up, but clients which were already accepted are still forwarded
+    let mut refused = false;
+    for _ in 0..100 {
+        if net::TcpStream::connect(bridge).is_err() {
+            refused = true;
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    assert!(refused);
+    stream.write_all(b"World").unwrap();
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"World");
+}
+
+#[test]
+fn test_config_from_arguments() {
+    let mut config = ObfsConfig::new(b"0123456789abcdef").unwrap();
+    config.set_max_padding(0);
+    let args = config.to_pt_arguments();
+    assert_eq!(args.get("secret"), Some("30313233343536373839616263646566"));
+    assert_eq!(args.get("max-padding"), Some("0"));
+    assert!(ObfsConfig::from_pt_arguments(&args).is_ok());
+
+    assert!(ObfsConfig::new(b"too short").is_err());
+    for invalid in ["max-padding=1", "secret=xyz", "secret=00112233445566778899aabbccddeeff;max-padding=lots"] {
+        assert!(ObfsConfig::from_pt_arguments(&invalid.parse().unwrap()).is_err(), "{}", invalid);
+    }
+}
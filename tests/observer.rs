@@ -0,0 +1,230 @@
+use socks5_frontend;
+use socks5_frontend::{Address, AuthMethod, ClientAddress, Command, ListenerInfo, RelayStats, ReplyType, ServerObserver};
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time;
+
+#[derive(PartialEq, Debug)]
+enum Event {
+    Accepted(Option<String>),
+    MethodNegotiated(Option<AuthMethod>),
+    AuthSucceeded(String),
+    AuthFailed,
+    Request(Command, Address, u16),
+    Reply(ReplyType),
+    RelayFinished(u64, u64),
+    HandshakeError(String),
+}
+
+/// Records every event it's notified of.
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<Event>>,
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<Event> {
+        return std::mem::take(&mut self.events.lock().unwrap());
+    }
+
+    fn record(&self, event: Event) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl ServerObserver for Recorder {
+    fn on_accepted(&self, _client: &ClientAddress, listener: &ListenerInfo) {
+        self.record(Event::Accepted(listener.name.clone()));
+    }
+
+    fn on_method_negotiated(&self, _client: &ClientAddress, method: Option<&AuthMethod>) {
+        self.record(Event::MethodNegotiated(method.cloned()));
+    }
+
+    fn on_auth_succeeded(&self, _client: &ClientAddress, username: &str) {
+        self.record(Event::AuthSucceeded(username.to_string()));
+    }
+
+    fn on_auth_failed(&self, _client: &ClientAddress) {
+        self.record(Event::AuthFailed);
+    }
+
+    fn on_request(&self, _client: &ClientAddress, command: Command, destination: &Address, port: u16) {
+        self.record(Event::Request(command, destination.clone(), port));
+    }
+
+    fn on_reply(&self, _client: &ClientAddress, reply: ReplyType, _elapsed: time::Duration) {
+        self.record(Event::Reply(reply));
+    }
+
+    fn on_relay_finished(&self, _client: &ClientAddress, stats: &RelayStats) {
+        self.record(Event::RelayFinished(stats.client_to_upstream, stats.upstream_to_client));
+    }
+
+    fn on_handshake_error(&self, error: &socks5_frontend::Error) {
+        self.record(Event::HandshakeError(error.to_string()));
+    }
+}
+
+/// Starts a server which relays every connection, and sends the outcome of each once it's done.
+fn start_server(recorder: Arc<Recorder>) -> (net::SocketAddr, mpsc::Receiver<Result<RelayStats, String>>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener.named("main")],
+        None,
+        vec![AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_observer(recorder);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let result = connection
+                .and_then(|conn| conn.connect())
+                .map_err(|err| err.to_string())
+                .and_then(|(conn, upstream)| conn.relay(upstream).map_err(|err| err.to_string()));
+            tx.send(result).unwrap();
+        }
+    });
+    return (addr, rx);
+}
+
+fn authenticate(stream: &mut net::TcpStream, password: &[u8]) -> [u8; 2] {
+    stream.write_all(&[5, 1, 2]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    let mut auth = vec![1, 7];
+    auth.extend_from_slice(b"randall");
+    auth.push(password.len() as u8);
+    auth.extend_from_slice(password);
+    stream.write_all(&auth).unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    return buf;
+}
+
+#[test]
+fn test_relayed_connection_events() {
+    let dest = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = dest.local_addr().unwrap().port();
+    thread::spawn(move || {
+        // Answer with twice as much as the client sent
+        let (mut stream, _) = dest.accept().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+    let recorder = Arc::new(Recorder::default());
+    let (addr, results) = start_server(recorder.clone());
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    assert_eq!(authenticate(&mut stream, b"CorrectHorseBatteryStaple"), [1, 0]);
+    let mut req = vec![5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    // Part of the data is sent before the reply
+    req.extend_from_slice(b"early");
+    stream.write_all(&req).unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x00);
+    stream.write_all(b" and late").unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).unwrap();
+    assert_eq!(echoed, b"early and lateearly and late");
+
+    let stats = results.recv().unwrap().unwrap();
+    assert_eq!(stats.client_to_upstream, 14);
+    assert_eq!(stats.upstream_to_client, 28);
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Accepted(Some("main".to_string())),
+            Event::MethodNegotiated(Some(AuthMethod::UsernamePassword)),
+            Event::AuthSucceeded("randall".to_string()),
+            Event::Request(Command::Connect, Address::V4(net::Ipv4Addr::LOCALHOST), port),
+            Event::Reply(ReplyType::Succeeded),
+            Event::RelayFinished(14, 28),
+        ]
+    );
+}
+
+#[test]
+fn test_failed_auth_events() {
+    let recorder = Arc::new(Recorder::default());
+    let (addr, results) = start_server(recorder.clone());
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    assert_eq!(authenticate(&mut stream, b"hunter2"), [1, 1]);
+
+    let err = results.recv().unwrap().unwrap_err();
+    let events = recorder.take();
+    assert_eq!(&events[1..3], &[Event::MethodNegotiated(Some(AuthMethod::UsernamePassword)), Event::AuthFailed]);
+    assert_eq!(events[3], Event::HandshakeError(err.clone()));
+    // The password is never passed to the observer
+    assert!(!err.contains("hunter2"));
+}
+
+#[test]
+fn test_connect_error_events() {
+    let recorder = Arc::new(Recorder::default());
+    let (addr, results) = start_server(recorder.clone());
+    // Nothing listens on the destination port
+    let port = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    assert_eq!(authenticate(&mut stream, b"CorrectHorseBatteryStaple"), [1, 0]);
+    let mut req = vec![5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x05);
+
+    let err = results.recv().unwrap().unwrap_err();
+    let events = recorder.take();
+    assert_eq!(
+        &events[4..],
+        &[Event::Reply(ReplyType::ConnectionRefused), Event::HandshakeError(err)]
+    );
+}
+
+#[test]
+fn test_unknown_command_events() {
+    let recorder = Arc::new(Recorder::default());
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server =
+        socks5_frontend::Server::with_listeners(vec![listener], None, vec![AuthMethod::NoAuth], None, None).unwrap();
+    server.set_observer(recorder.clone());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            tx.send(connection.err().unwrap().to_string()).unwrap();
+        }
+    });
+
+    // Command 0x09 instead of CONNECT
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.write_all(&[5, 1, 0, 5, 9, 0, 1, 127, 0, 0, 1, 0, 80]).unwrap();
+    let mut buf = [0; 12];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[3], 0x07);
+
+    let err = rx.recv().unwrap();
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Accepted(None),
+            Event::MethodNegotiated(Some(AuthMethod::NoAuth)),
+            Event::Reply(ReplyType::CommandNotSupported),
+            Event::HandshakeError(err),
+        ]
+    );
+}
@@ -0,0 +1,100 @@
+use socks5_frontend;
+use socks5_frontend::{Dialer, OutboundSource};
+
+use std::io::{Read, Write};
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+mod common;
+use common::*;
+
+/// Starts a destination server reporting the address each connection came from.
+fn start_peer_reporting_server() -> (u16, mpsc::Receiver<net::SocketAddr>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            tx.send(stream.unwrap().peer_addr().unwrap()).unwrap();
+        }
+    });
+    return (port, rx);
+}
+
+fn start_server(dialer: Dialer, auth_methods: Vec<socks5_frontend::AuthMethod>) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        auth_methods,
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_dialer(dialer);
+    thread::spawn(move || {
+        // Keep the connections open until the test is done
+        let mut connected = Vec::new();
+        for connection in server {
+            if let Ok(conn) = connection.unwrap().connect() {
+                connected.push(conn);
+            }
+        }
+    });
+    return addr;
+}
+
+#[test]
+fn test_source_address() {
+    let (port, peers) = start_peer_reporting_server();
+    let mut dialer = Dialer::new();
+    dialer.set_source(OutboundSource {
+        v4: Some(net::Ipv4Addr::new(127, 0, 0, 2)),
+        ..Default::default()
+    });
+    let server = start_server(dialer, vec![socks5_frontend::AuthMethod::NoAuth]);
+
+    let mut stream = net::TcpStream::connect(server).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], port);
+    assert_eq!(reply[1], 0x00);
+    let peer = peers.recv().unwrap();
+    assert_eq!(peer.ip(), net::IpAddr::from([127, 0, 0, 2]));
+    // The bound address is reported to the client
+    assert_eq!(&reply[4..8], &[127, 0, 0, 2]);
+    assert_eq!(u16::from_be_bytes([reply[8], reply[9]]), peer.port());
+}
+
+#[test]
+fn test_source_policy_by_user() {
+    let (port, peers) = start_peer_reporting_server();
+    let mut dialer = Dialer::new();
+    dialer.set_source_policy(|ctx| {
+        let last_octet = if ctx.user == Some("randall") { 3 } else { 2 };
+        return OutboundSource {
+            v4: Some(net::Ipv4Addr::new(127, 0, 0, last_octet)),
+            ..Default::default()
+        };
+    });
+    let server = start_server(dialer, vec![socks5_frontend::AuthMethod::UsernamePassword]);
+
+    let mut stream = net::TcpStream::connect(server).unwrap();
+    stream.write_all(&[5, 1, 2]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    let mut auth = vec![1, 7];
+    auth.extend_from_slice(b"randall");
+    auth.push(25);
+    auth.extend_from_slice(b"CorrectHorseBatteryStaple");
+    stream.write_all(&auth).unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [1, 0]);
+    let mut req = vec![5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x00);
+    assert_eq!(peers.recv().unwrap().ip(), net::IpAddr::from([127, 0, 0, 3]));
+}
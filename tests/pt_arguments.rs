@@ -0,0 +1,87 @@
+use socks5_frontend;
+use socks5_frontend::client::{Client, ClientError, Credentials};
+use socks5_frontend::{Address, PtArguments};
+
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+
+/// Starts a server accepting pluggable transport arguments, which sends the arguments of each client through the returned channel.
+fn start_server() -> (net::SocketAddr, mpsc::Receiver<Result<Option<PtArguments>, String>>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        None,
+        None,
+    )
+    .unwrap();
+    server.accept_pt_arguments();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let result = match connection {
+                Ok(conn) => {
+                    let args = conn.get_pt_arguments().cloned();
+                    let _ = conn.report_connection_not_allowed();
+                    Ok(args)
+                }
+                Err(e) => Err(e.kind().to_string()),
+            };
+            tx.send(result).unwrap();
+        }
+    });
+    return (addr, rx);
+}
+
+fn request(addr: net::SocketAddr, credentials: &Credentials) -> Result<(), ClientError> {
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let client = Client::handshake(stream, Some(credentials))?;
+    client.connect(&Address::V4("127.0.0.1".parse().unwrap()), 1)?;
+    return Ok(());
+}
+
+#[test]
+fn test_parse() {
+    let args: PtArguments = "secret=abc;path=a\\;b\\=c\\\\;empty=".parse().unwrap();
+    assert_eq!(args.get("secret"), Some("abc"));
+    assert_eq!(args.get("path"), Some("a;b=c\\"));
+    assert_eq!(args.get("empty"), Some(""));
+    assert_eq!(args.get("missing"), None);
+    assert_eq!(args.to_string(), "secret=abc;path=a\\;b\\=c\\\\;empty=");
+    assert!("".parse::<PtArguments>().unwrap().is_empty());
+
+    for malformed in ["secret", "secret=abc;", "=abc", "secret=abc\\"] {
+        assert!(malformed.parse::<PtArguments>().is_err(), "{}", malformed);
+    }
+}
+
+#[test]
+fn test_arguments_are_passed_in_credentials() {
+    let (addr, done) = start_server();
+    let mut args = PtArguments::new();
+    args.push("secret", "0123456789abcdef");
+    args.push("mode", "a;b");
+    let err = request(addr, &args.to_credentials()).unwrap_err();
+    assert_eq!(err.reply(), socks5_frontend::ReplyType::ConnectionNotAllowed);
+    assert_eq!(done.recv().unwrap(), Ok(Some(args)));
+
+    // Long arguments continue in the password
+    let mut args = PtArguments::new();
+    args.push("secret", &"ab".repeat(200));
+    let credentials = args.to_credentials();
+    assert_eq!(credentials.username.len(), 255);
+    let err = request(addr, &credentials).unwrap_err();
+    assert_eq!(err.reply(), socks5_frontend::ReplyType::ConnectionNotAllowed, "{:?}", err);
+    assert_eq!(done.recv().unwrap(), Ok(Some(args)));
+}
+
+#[test]
+fn test_malformed_arguments_are_refused() {
+    let (addr, done) = start_server();
+    let err = request(addr, &Credentials::new("randall", "CorrectHorseBatteryStaple")).unwrap_err();
+    assert!(matches!(err, ClientError::WrongCredentialsError), "{:?}", err);
+    assert_eq!(done.recv().unwrap(), Err("WrongCredentialsError".to_string()));
+}
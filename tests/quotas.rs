@@ -0,0 +1,161 @@
+use socks5_frontend;
+use socks5_frontend::{Quota, QuotaStore, QuotaUsage};
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("socks5_frontend-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    return path;
+}
+
+/// Starts a server enforcing `quotas`, which sends the outcome of each connection through the returned channel.
+fn start_server(quotas: Arc<QuotaStore>) -> (net::SocketAddr, mpsc::Receiver<Result<(), String>>) {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_quotas(quotas);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let result = match connection.and_then(|conn| conn.connect()) {
+                Ok((conn, upstream)) => conn.relay(upstream).map(|_| ()).map_err(|e| e.to_string()),
+                Err(e) => Err(e.kind().to_string()),
+            };
+            tx.send(result).unwrap();
+        }
+    });
+    return (addr, rx);
+}
+
+/// Starts a server which sends `len` bytes to every client and then echoes what it receives.
+fn start_dest_server(len: usize) -> u16 {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                if stream.write_all(&vec![0; len]).is_ok() {
+                    let mut read = stream.try_clone().unwrap();
+                    let _ = std::io::copy(&mut read, &mut stream);
+                }
+            });
+        }
+    });
+    return port;
+}
+
+/// Performs the handshake and returns the stream along with the reply code.
+fn connect(addr: net::SocketAddr, port: u16) -> (net::TcpStream, u8) {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4_as_randall(&mut stream, [127, 0, 0, 1], port);
+    return (stream, reply[1]);
+}
+
+#[test]
+fn test_quota_is_enforced() {
+    let quotas = Arc::new(QuotaStore::new());
+    quotas.set_default_quota(Quota {
+        daily: Some(100_000),
+        monthly: None,
+    });
+    quotas.set_quota("randall", Quota {
+        daily: None,
+        monthly: Some(10_000),
+    });
+    let (addr, done) = start_server(quotas.clone());
+    let port = start_dest_server(64 * 1024);
+
+    // The relay is closed once the quota would be exceeded
+    let (mut stream, rep) = connect(addr, port);
+    assert_eq!(rep, 0x00);
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received);
+    assert!(received.len() <= 10_000, "{}", received.len());
+    let err = done.recv().unwrap().unwrap_err();
+    assert!(err.contains("exhausted their traffic quota"), "{}", err);
+    assert!(quotas.is_exhausted("randall"));
+    assert!(quotas.usage("randall").monthly > 10_000);
+
+    // Further requests are refused
+    let (_stream, rep) = connect(addr, port);
+    assert_eq!(rep, 0x02);
+    assert_eq!(done.recv().unwrap(), Err("QuotaExceededError".to_string()));
+}
+
+#[test]
+fn test_usage_is_persisted() {
+    let path = temp_path("quota-usage");
+    let quotas = Arc::new(QuotaStore::open(&path).unwrap());
+    let (addr, done) = start_server(quotas.clone());
+    let port = start_dest_server(4);
+
+    let (mut stream, rep) = connect(addr, port);
+    assert_eq!(rep, 0x00);
+    stream.write_all(b"Hello").unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received.len(), 9);
+    done.recv().unwrap().unwrap();
+
+    // Relayed in both directions
+    let usage = QuotaUsage { daily: 14, monthly: 14 };
+    assert_eq!(quotas.usage("randall"), usage);
+    assert_eq!(QuotaStore::open(&path).unwrap().usage("randall"), usage);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_usage_is_saved_during_relays() {
+    let path = temp_path("quota-running");
+    let quotas = Arc::new(QuotaStore::open(&path).unwrap());
+    quotas.set_save_interval(time::Duration::from_secs(3600), 10_000);
+    let (addr, done) = start_server(quotas.clone());
+    let port = start_dest_server(64 * 1024);
+
+    // The relay is still running, as if the process crashed now
+    let (mut stream, rep) = connect(addr, port);
+    assert_eq!(rep, 0x00);
+    let mut received = vec![0; 64 * 1024];
+    stream.read_exact(&mut received).unwrap();
+    let saved = QuotaStore::open(&path).unwrap().usage("randall");
+    assert!(saved.daily >= 10_000, "{:?}", saved);
+
+    drop(stream);
+    done.recv().unwrap().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_usage_file() {
+    let path = temp_path("quota-file");
+    // Usage from periods which have passed doesn't count anymore
+    fs::write(&path, "# comment\nold%20user 1 500 0 700\n").unwrap();
+    let quotas = QuotaStore::open(&path).unwrap();
+    assert_eq!(quotas.usage("old user"), QuotaUsage::default());
+    quotas.save().unwrap();
+    assert!(fs::read_to_string(&path).unwrap().contains("\nold%20user 1 500 0 700\n"));
+
+    fs::write(&path, "randall 1 lots 0 0\n").unwrap();
+    let err = QuotaStore::open(&path).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    fs::remove_file(&path).unwrap();
+}
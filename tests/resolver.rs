@@ -0,0 +1,230 @@
+use socks5_frontend;
+use socks5_frontend::resolver::{CachingResolver, DnsResolver, DnsTransport, Resolver, StaticResolver};
+
+use ignore_result::Ignore;
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// A stub DNS server answering A queries for `stub.test` and AAAA queries for `v6.stub.test`,
+/// over both UDP and TCP on the same port.
+/// A queries for `broken-v6.stub.test` are answered too, but AAAA queries for it never are.
+struct StubDns {
+    addr: net::SocketAddr,
+    queries: Arc<AtomicUsize>,
+}
+
+impl StubDns {
+    /// If `truncate_udp` is set, every answer over UDP is empty and marked as truncated.
+    fn start(truncate_udp: bool) -> StubDns {
+        // The UDP port may already be taken for TCP, so try until both are free
+        let (udp, tcp) = loop {
+            let udp = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            if let Ok(tcp) = net::TcpListener::bind(udp.local_addr().unwrap()) {
+                break (udp, tcp);
+            }
+        };
+        let addr = udp.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+
+        let udp_queries = queries.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (len, peer) = udp.recv_from(&mut buf).unwrap();
+                udp_queries.fetch_add(1, Ordering::SeqCst);
+                if let Some(answer) = answer(&buf[..len], truncate_udp) {
+                    udp.send_to(&answer, peer).unwrap();
+                }
+            }
+        });
+        let tcp_queries = queries.clone();
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                let mut stream = stream.unwrap();
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut query).unwrap();
+                tcp_queries.fetch_add(1, Ordering::SeqCst);
+                let Some(answer) = answer(&query, false) else {
+                    continue;
+                };
+                stream.write_all(&(answer.len() as u16).to_be_bytes()).unwrap();
+                stream.write_all(&answer).unwrap();
+            }
+        });
+        return StubDns {
+            addr: addr,
+            queries: queries,
+        };
+    }
+
+    fn queries(&self) -> usize {
+        return self.queries.load(Ordering::SeqCst);
+    }
+}
+
+fn answer(query: &[u8], truncate: bool) -> Option<Vec<u8>> {
+    // Decode the question name
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    while query[pos] != 0 {
+        let len = query[pos] as usize;
+        labels.push(String::from_utf8_lossy(&query[pos + 1..pos + 1 + len]).to_string());
+        pos += 1 + len;
+    }
+    let question_end = pos + 5;
+    let name = labels.join(".").to_ascii_lowercase();
+    let qtype = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+
+    let rdata: Vec<u8> = match (name.as_str(), qtype) {
+        ("stub.test", 1) | ("broken-v6.stub.test", 1) => vec![127, 0, 0, 1],
+        ("broken-v6.stub.test", _) => return None,
+        ("v6.stub.test", 28) => net::Ipv6Addr::LOCALHOST.octets().to_vec(),
+        ("stub.test", _) | ("v6.stub.test", _) => Vec::new(),
+        // NXDOMAIN
+        _ => {
+            let mut msg = query[..question_end].to_vec();
+            msg[2] = 0x81;
+            msg[3] = 0x83;
+            return Some(msg);
+        }
+    };
+
+    let mut msg = query[..question_end].to_vec();
+    msg[2] = if truncate { 0x83 } else { 0x81 };
+    msg[3] = 0x80;
+    if rdata.is_empty() || truncate {
+        return Some(msg);
+    }
+    // A CNAME in front of the address, which the resolver should skip
+    msg.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 30, 0, 2, 0xC0, 12]);
+    msg.extend_from_slice(&[0xC0, 12]);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&[0, 1]);
+    // TTL of 300 seconds
+    msg.extend_from_slice(&300u32.to_be_bytes());
+    msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&rdata);
+    msg[7] = 2;
+    return Some(msg);
+}
+
+#[test]
+fn test_dns_resolver() {
+    let stub = StubDns::start(false);
+    for transport in [DnsTransport::Udp, DnsTransport::Tcp] {
+        let mut resolver = DnsResolver::new(stub.addr);
+        resolver.set_transport(transport);
+
+        let lookup = resolver.lookup("stub.test").unwrap();
+        assert_eq!(lookup.addrs, vec![net::IpAddr::from([127, 0, 0, 1])]);
+        assert_eq!(lookup.ttl, Some(time::Duration::from_secs(30)));
+        let lookup = resolver.lookup("V6.Stub.Test.").unwrap();
+        assert_eq!(lookup.addrs, vec![net::IpAddr::from(net::Ipv6Addr::LOCALHOST)]);
+
+        let err = resolver.lookup("nonexistent.test").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
+
+#[test]
+fn test_dns_resolver_survives_unanswered_aaaa_queries() {
+    let stub = StubDns::start(false);
+    let mut resolver = DnsResolver::new(stub.addr);
+    resolver.set_timeout(time::Duration::from_millis(200));
+    let lookup = resolver.lookup("broken-v6.stub.test").unwrap();
+    assert_eq!(lookup.addrs, vec![net::IpAddr::from([127, 0, 0, 1])]);
+}
+
+#[test]
+fn test_dns_resolver_retries_truncated_answers_over_tcp() {
+    let stub = StubDns::start(true);
+    let resolver = DnsResolver::new(stub.addr);
+    let lookup = resolver.lookup("stub.test").unwrap();
+    assert_eq!(lookup.addrs, vec![net::IpAddr::from([127, 0, 0, 1])]);
+    // A and AAAA, each over UDP and then TCP
+    assert_eq!(stub.queries(), 4);
+}
+
+#[test]
+fn test_caching_resolver() {
+    let stub = StubDns::start(false);
+    let resolver = CachingResolver::new(DnsResolver::new(stub.addr));
+
+    resolver.lookup("stub.test").unwrap();
+    assert_eq!(stub.queries(), 2);
+    let lookup = resolver.lookup("STUB.test").unwrap();
+    assert_eq!(lookup.addrs, vec![net::IpAddr::from([127, 0, 0, 1])]);
+    assert!(lookup.ttl.unwrap() <= time::Duration::from_secs(30));
+    assert_eq!(stub.queries(), 2);
+
+    // Failures aren't cached
+    resolver.lookup("nonexistent.test").unwrap_err();
+    resolver.lookup("nonexistent.test").unwrap_err();
+    assert_eq!(stub.queries(), 6);
+
+    resolver.clear();
+    resolver.lookup("stub.test").unwrap();
+    assert_eq!(stub.queries(), 8);
+
+    // Results expire with their TTL
+    let mut resolver = CachingResolver::new(DnsResolver::new(stub.addr));
+    resolver.set_max_ttl(time::Duration::from_millis(50));
+    resolver.lookup("stub.test").unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+    resolver.lookup("stub.test").unwrap();
+    assert_eq!(stub.queries(), 12);
+}
+
+#[test]
+fn test_dialer_uses_resolver() {
+    let mut hosts = StaticResolver::new();
+    hosts.insert("dest.test", vec![net::IpAddr::from([127, 0, 0, 1])]);
+    let mut dialer = socks5_frontend::Dialer::new();
+    dialer.set_resolver(Arc::new(hosts));
+
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.set_dialer(dialer);
+    thread::spawn(move || {
+        for connection in server {
+            connection.unwrap().connect().ignore();
+        }
+    });
+
+    let (port, _) = start_dest_server();
+    let connect = |name: &str| {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        stream.write_all(&[5, 1, 0]).unwrap();
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).unwrap();
+        let mut req = vec![5, 1, 0, 3, name.len() as u8];
+        req.extend_from_slice(name.as_bytes());
+        req.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&req).unwrap();
+        let mut reply = vec![0; 10];
+        stream.read_exact(&mut reply).unwrap();
+        return reply[1];
+    };
+    // Succeeded
+    assert_eq!(connect("dest.test"), 0x00);
+    // Host unreachable, names outside the table don't fall back to the system resolver
+    assert_eq!(connect("localhost"), 0x04);
+}
@@ -0,0 +1,132 @@
+use socks5_frontend;
+use socks5_frontend::{Keepalive, SocketOptions};
+
+use socket2::SockRef;
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+mod common;
+use common::*;
+
+/// The options as read back from a socket.
+#[derive(PartialEq, Debug)]
+struct Observed {
+    nodelay: bool,
+    keepalive: bool,
+    keepalive_idle: time::Duration,
+    tos: u32,
+    #[cfg(target_os = "linux")]
+    keepalive_interval: time::Duration,
+    #[cfg(target_os = "linux")]
+    keepalive_retries: u32,
+    #[cfg(target_os = "linux")]
+    user_timeout: Option<time::Duration>,
+}
+
+fn observe(stream: &net::TcpStream) -> Observed {
+    let socket = SockRef::from(stream);
+    return Observed {
+        nodelay: socket.tcp_nodelay().unwrap(),
+        keepalive: socket.keepalive().unwrap(),
+        keepalive_idle: socket.tcp_keepalive_time().unwrap(),
+        tos: socket.tos_v4().unwrap(),
+        #[cfg(target_os = "linux")]
+        keepalive_interval: socket.tcp_keepalive_interval().unwrap(),
+        #[cfg(target_os = "linux")]
+        keepalive_retries: socket.tcp_keepalive_retries().unwrap(),
+        #[cfg(target_os = "linux")]
+        user_timeout: socket.tcp_user_timeout().unwrap(),
+    };
+}
+
+#[test]
+fn test_options_apply_to_client_and_upstream() {
+    let options = SocketOptions {
+        nodelay: Some(true),
+        keepalive: Some(Keepalive {
+            idle: Some(time::Duration::from_secs(30)),
+            interval: Some(time::Duration::from_secs(5)),
+            retries: Some(3),
+        }),
+        recv_buffer_size: Some(64 * 1024),
+        // DSCP AF21
+        tos: Some(0x48),
+        #[cfg(target_os = "linux")]
+        user_timeout: Some(time::Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let expected = Observed {
+        nodelay: true,
+        keepalive: true,
+        keepalive_idle: time::Duration::from_secs(30),
+        tos: 0x48,
+        #[cfg(target_os = "linux")]
+        keepalive_interval: time::Duration::from_secs(5),
+        #[cfg(target_os = "linux")]
+        keepalive_retries: 3,
+        #[cfg(target_os = "linux")]
+        user_timeout: Some(time::Duration::from_secs(10)),
+    };
+
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.set_socket_options(options);
+    // Dialers set later get the server's options too
+    server.set_dialer(socks5_frontend::Dialer::new());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let (conn, upstream) = connection.unwrap().connect().unwrap();
+            let client = conn.get_stream();
+            tx.send((observe(client.as_tcp().unwrap()), observe(&upstream))).unwrap();
+        }
+    });
+
+    let dest = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], dest.local_addr().unwrap().port());
+    assert_eq!(reply[1], 0x00);
+    let (client, upstream) = rx.recv().unwrap();
+    assert_eq!(client, expected);
+    assert_eq!(upstream, expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_options_are_skipped_on_unix_sockets() {
+    let path = std::env::temp_dir().join(format!("socks5_frontend-{}-socket-options.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut server = socks5_frontend::Server::init_unix(
+        &path,
+        None,
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    server.set_socket_options(SocketOptions {
+        nodelay: Some(true),
+        tos: Some(0x48),
+        ..Default::default()
+    });
+    thread::spawn(move || {
+        for connection in server {
+            connection.unwrap().report_connection_not_allowed().unwrap();
+        }
+    });
+
+    let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    assert_eq!(reply[1], 0x02);
+}
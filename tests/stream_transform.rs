@@ -0,0 +1,262 @@
+use socks5_frontend;
+use socks5_frontend::StreamTransform;
+
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// XORs every byte with a key.
+struct Xor(u8);
+
+struct XorHalf<T> {
+    inner: T,
+    key: u8,
+}
+
+impl<R: Read> Read for XorHalf<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        buf[..len].iter_mut().for_each(|b| *b ^= self.key);
+        return Ok(len);
+    }
+}
+
+impl<W: Write> Write for XorHalf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data: Vec<u8> = buf.iter().map(|b| b ^ self.key).collect();
+        self.inner.write_all(&data)?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+impl StreamTransform for Xor {
+    fn wrap_reader(&mut self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        return Box::new(XorHalf { inner: reader, key: self.0 });
+    }
+
+    fn wrap_writer(&mut self, writer: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        return Box::new(XorHalf { inner: writer, key: self.0 });
+    }
+}
+
+/// Adds one to every byte sent, and subtracts one from every byte received.
+struct AddOne;
+
+struct AddOneHalf<T>(T);
+
+impl<R: Read> Read for AddOneHalf<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.0.read(buf)?;
+        buf[..len].iter_mut().for_each(|b| *b = b.wrapping_sub(1));
+        return Ok(len);
+    }
+}
+
+impl<W: Write> Write for AddOneHalf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data: Vec<u8> = buf.iter().map(|b| b.wrapping_add(1)).collect();
+        self.0.write_all(&data)?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.0.flush();
+    }
+}
+
+impl StreamTransform for AddOne {
+    fn wrap_reader(&mut self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        return Box::new(AddOneHalf(reader));
+    }
+
+    fn wrap_writer(&mut self, writer: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        return Box::new(AddOneHalf(writer));
+    }
+}
+
+/// Sends everything written between two flushes as a frame, prefixed with its length.
+struct Framing;
+
+struct FrameWriter {
+    inner: Box<dyn Write + Send>,
+    buf: Vec<u8>,
+}
+
+impl Write for FrameWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.inner.write_all(&frame(&self.buf))?;
+            self.buf.clear();
+        }
+        return self.inner.flush();
+    }
+}
+
+struct FrameReader {
+    inner: Box<dyn Read + Send>,
+    frame: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for FrameReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.frame.len() {
+            match read_frame(&mut self.inner)? {
+                Some(frame) => {
+                    self.frame = frame;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.frame.len() - self.pos);
+        buf[..len].copy_from_slice(&self.frame[self.pos..self.pos + len]);
+        self.pos += len;
+        return Ok(len);
+    }
+}
+
+impl StreamTransform for Framing {
+    fn wrap_reader(&mut self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        return Box::new(FrameReader {
+            inner: reader,
+            frame: Vec::new(),
+            pos: 0,
+        });
+    }
+
+    fn wrap_writer(&mut self, writer: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        return Box::new(FrameWriter { inner: writer, buf: Vec::new() });
+    }
+}
+
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = (data.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(data);
+    return frame;
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut frame = vec![0; u16::from_be_bytes(len).into()];
+    reader.read_exact(&mut frame)?;
+    return Ok(Some(frame));
+}
+
+/// Starts a server relaying every connection through the transforms `make_transforms` returns.
+fn start_server(make_transforms: fn() -> Vec<Box<dyn StreamTransform>>) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    thread::spawn(move || {
+        for connection in server {
+            thread::spawn(move || {
+                if let Ok((conn, upstream)) = connection.and_then(|conn| conn.connect()) {
+                    let _ = conn.relay_with(upstream, make_transforms());
+                }
+            });
+        }
+    });
+    return addr;
+}
+
+/// Starts a destination server which hands each connection's stream to `serve`.
+fn start_dest_server<F: Fn(net::TcpStream) + Send + Sync + 'static>(serve: F) -> u16 {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let serve = Arc::new(serve);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let serve = serve.clone();
+            thread::spawn(move || serve(stream));
+        }
+    });
+    return port;
+}
+
+fn connect(addr: net::SocketAddr, port: u16) -> net::TcpStream {
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    stream.write_all(&[5, 1, 0]).unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).unwrap();
+    let mut req = vec![5, 1, 0, 1, 127, 0, 0, 1];
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[1], 0x00);
+    return stream;
+}
+
+#[test]
+fn test_transform_stack() {
+    let addr = start_server(|| vec![Box::new(Framing), Box::new(Xor(0x55))]);
+    // Replies to every frame with a frame containing "pong: " and the frame's contents, both XORed
+    let port = start_dest_server(|mut stream| {
+        while let Some(received) = read_frame(&mut stream).unwrap() {
+            let mut reply = b"pong: ".to_vec();
+            reply.extend_from_slice(&received.iter().map(|b| b ^ 0x55).collect::<Vec<u8>>());
+            let reply: Vec<u8> = reply.iter().map(|b| b ^ 0x55).collect();
+            stream.write_all(&frame(&reply)).unwrap();
+        }
+    });
+
+    let mut stream = connect(addr, port);
+    for message in [&b"ping"[..], &b"hello there"[..]] {
+        stream.write_all(message).unwrap();
+        let mut reply = vec![0; 6 + message.len()];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply[..6], b"pong: ");
+        assert_eq!(&reply[6..], message);
+    }
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_transform_order() {
+    let addr = start_server(|| vec![Box::new(AddOne), Box::new(Xor(0x0f))]);
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    // Reports what arrived, then replies with a single byte
+    let port = start_dest_server(move |mut stream| {
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        stream.write_all(&[0x20]).unwrap();
+        tx.lock().unwrap().send(received).unwrap();
+    });
+
+    let mut stream = connect(addr, port);
+    stream.write_all(&[0x10, 0xff]).unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).unwrap();
+    // Data from the client passes through the last transform first
+    assert_eq!(rx.recv().unwrap(), vec![(0x10 ^ 0x0f) + 1, (0xff ^ 0x0f) + 1]);
+    assert_eq!(reply, vec![(0x20 - 1) ^ 0x0f]);
+}
@@ -0,0 +1,107 @@
+use socks5_frontend;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Collects everything the subscriber writes.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl Capture {
+    fn lines(&self) -> Vec<String> {
+        return String::from_utf8_lossy(&self.0.lock().unwrap()).lines().map(|line| line.to_string()).collect();
+    }
+}
+
+#[test]
+fn test_handshake_spans() {
+    let capture = Capture::default();
+    let writer = capture.clone();
+    // The handshake is performed on this thread, so the subscriber only has to be installed here
+    let _guard = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::DEBUG)
+        .with_ansi(false)
+        .without_time()
+        .with_writer(move || writer.clone())
+        .finish()
+        .set_default();
+
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        vec![socks5_frontend::AuthMethod::UsernamePassword],
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+
+    let client = thread::spawn(move || {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let mut msg = vec![5, 1, 2, 1, 7];
+        msg.extend_from_slice(b"randall");
+        msg.push(25);
+        msg.extend_from_slice(b"CorrectHorseBatteryStaple");
+        msg.extend_from_slice(&[5, 1, 0, 3, 11]);
+        msg.extend_from_slice(b"example.com");
+        msg.extend_from_slice(&443_u16.to_be_bytes());
+        stream.write_all(&msg).unwrap();
+        let mut reply = [0; 14];
+        stream.read_exact(&mut reply).unwrap();
+        let client_addr = stream.local_addr().unwrap();
+
+        // A client which typed its password into the username field
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let mut msg = vec![5, 1, 2, 1, 7];
+        msg.extend_from_slice(b"hunter2");
+        msg.push(7);
+        msg.extend_from_slice(b"hunter2");
+        stream.write_all(&msg).unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).unwrap();
+        return client_addr;
+    });
+
+    let conn = server.next().unwrap().unwrap();
+    let id = conn.connection_id().unwrap();
+    conn.report_general_server_failure().unwrap();
+    assert!(server.next().unwrap().is_err());
+    let client_addr = client.join().unwrap();
+
+    let lines = capture.lines();
+    let span = format!("connection{{client={} id={}}}", client_addr, id);
+    let expected = [
+        format!("{}: socks5_frontend::connection: client offered authentication methods methods=[UsernamePassword]", span),
+        format!("{}: socks5_frontend::connection: selected authentication method method=UsernamePassword", span),
+        format!("{}:auth: socks5_frontend::auth::user_pass_auth: client authenticated user=randall", span),
+        format!("{}:request: socks5_frontend::request: received request command=\"connect\" destination=example.com port=443", span),
+        format!("{}: socks5_frontend::connection: handshake complete", span),
+        format!("{}: socks5_frontend::connection: sent reply reply=\"general_failure\"", span),
+    ];
+    for line in &expected {
+        assert!(lines.iter().any(|l| l.ends_with(line.as_str())), "missing '{}' in:\n{:#?}", line, lines);
+    }
+    assert!(!lines.iter().any(|l| l.contains("CorrectHorseBatteryStaple")));
+
+    let failed = lines.iter().find(|l| l.contains("handshake failed")).unwrap();
+    assert!(failed.starts_with(" INFO connection{client="), "{}", failed);
+    assert!(failed.contains("supplied invalid credentials"));
+    assert!(!lines.iter().any(|l| l.contains("hunter2")));
+    assert!(lines.iter().any(|l| l.ends_with("client supplied invalid credentials username_len=7")), "{:#?}", lines);
+}
@@ -0,0 +1,99 @@
+#![cfg(unix)]
+
+use socks5_frontend;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+
+mod common;
+use common::*;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("socks5_frontend-{}-{}.sock", process::id(), name));
+    let _ = fs::remove_file(&path);
+    return path;
+}
+
+fn start_unix_proxy_server(path: &PathBuf) -> mpsc::Receiver<socks5_frontend::ClientAddress> {
+    let server = socks5_frontend::Server::init_unix(
+        path,
+        Some(0o600),
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    )
+    .unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for connection in server {
+            let conn = connection.unwrap();
+            tx.send(conn.get_client_address()).unwrap();
+            conn.report_connection_not_allowed().unwrap();
+        }
+    });
+    return rx;
+}
+
+#[test]
+fn test_unix_listener_peer_credentials() {
+    let path = socket_path("peer-credentials");
+    let client_addrs = start_unix_proxy_server(&path);
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    // Connection not allowed
+    assert_eq!(reply[1], 0x02);
+
+    match client_addrs.recv().unwrap() {
+        socks5_frontend::ClientAddress::Unix(creds) => {
+            assert_eq!(creds.uid, unsafe { libc::getuid() });
+            assert_eq!(creds.gid, unsafe { libc::getgid() });
+            if cfg!(target_os = "linux") {
+                assert_eq!(creds.pid, Some(process::id() as i32));
+            }
+        }
+        other => panic!("Expected peer credentials, got {}", other),
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_unix_listener_replaces_stale_socket() {
+    let path = socket_path("stale");
+    // Dropping a listener leaves its socket file behind
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let _client_addrs = start_unix_proxy_server(&path);
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], 80);
+    assert_eq!(reply[1], 0x02);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_unix_listener_refuses_live_socket() {
+    let path = socket_path("live");
+    let _listener = UnixListener::bind(&path).unwrap();
+
+    let result = socks5_frontend::Server::init_unix(
+        &path,
+        None,
+        None,
+        vec![socks5_frontend::AuthMethod::NoAuth],
+        None,
+        None,
+    );
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::AddrInUse);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,174 @@
+use socks5_frontend;
+use socks5_frontend::client::{decode_udp_datagram, encode_udp_datagram, Client, ClientError, Credentials};
+use socks5_frontend::{Address, Dialer, ReplyType, UpstreamProxy};
+
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+
+mod common;
+use common::*;
+
+/// Starts a proxy server which relays connections through `dialer`.
+fn start_proxy(dialer: Dialer, auth_methods: Vec<socks5_frontend::AuthMethod>) -> net::SocketAddr {
+    let listener = socks5_frontend::Listener::bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = socks5_frontend::Server::with_listeners(
+        vec![listener],
+        None,
+        auth_methods,
+        Some("randall".to_string()),
+        Some("CorrectHorseBatteryStaple".to_string()),
+    )
+    .unwrap();
+    server.set_dialer(dialer);
+    thread::spawn(move || {
+        for connection in server {
+            let (conn, upstream) = match connection.and_then(|conn| conn.connect()) {
+                Ok(connected) => connected,
+                Err(_) => continue,
+            };
+            let mut client_read = conn.get_stream();
+            let mut client_write = client_read.try_clone().unwrap();
+            let mut upstream_read = upstream;
+            let mut upstream_write = upstream_read.try_clone().unwrap();
+            thread::spawn(move || io::copy(&mut client_read, &mut upstream_write));
+            thread::spawn(move || io::copy(&mut upstream_read, &mut client_write));
+        }
+    });
+    return addr;
+}
+
+fn upstream(addr: net::SocketAddr, credentials: Option<Credentials>) -> UpstreamProxy {
+    return UpstreamProxy::Socks5 {
+        addr: Address::from(addr.ip()),
+        port: addr.port(),
+        credentials: credentials,
+    };
+}
+
+/// Starts a front proxy relaying through a chain of two proxies, the second of which requires authentication.
+fn start_chain(credentials: Credentials) -> net::SocketAddr {
+    let second = start_proxy(Dialer::new(), vec![socks5_frontend::AuthMethod::UsernamePassword]);
+    let first = start_proxy(Dialer::new(), vec![socks5_frontend::AuthMethod::NoAuth]);
+    let mut dialer = Dialer::new();
+    dialer.set_upstream_proxies(vec![upstream(first, None), upstream(second, Some(credentials))]);
+    return start_proxy(dialer, vec![socks5_frontend::AuthMethod::NoAuth]);
+}
+
+#[test]
+fn test_chain() {
+    let port = start_echo_server();
+    let front = start_chain(Credentials::new("randall", "CorrectHorseBatteryStaple"));
+
+    // The domain name is resolved by the last proxy in the chain
+    let stream = net::TcpStream::connect(front).unwrap();
+    let (mut stream, _) = Client::handshake(stream, None)
+        .unwrap()
+        .connect(&Address::DomainName("localhost".to_string()), port)
+        .unwrap();
+    stream.write_all(b"Hello").unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Hello");
+}
+
+#[test]
+fn test_chain_failures_are_passed_on() {
+    let front = start_chain(Credentials::new("randall", "Tr0ub4dor&3"));
+    let mut stream = net::TcpStream::connect(front).unwrap();
+    let reply = socks_connect_v4(&mut stream, [127, 0, 0, 1], start_echo_server());
+    // General SOCKS server failure, the client can't do anything about our credentials being wrong
+    assert_eq!(reply[1], 0x01);
+
+    // Bind and drop a listener to get a port nothing listens on
+    let closed_port = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let front = start_chain(Credentials::new("randall", "CorrectHorseBatteryStaple"));
+    let stream = net::TcpStream::connect(front).unwrap();
+    let err = Client::handshake(stream, None)
+        .unwrap()
+        .connect(&Address::V4(net::Ipv4Addr::LOCALHOST), closed_port)
+        .err()
+        .unwrap();
+    match err {
+        ClientError::RequestFailedError(rep) => assert_eq!(rep, ReplyType::ConnectionRefused),
+        e => panic!("Expected the request to be refused, got '{}'", e),
+    }
+}
+
+#[test]
+fn test_client_authentication() {
+    let proxy = start_proxy(Dialer::new(), vec![socks5_frontend::AuthMethod::UsernamePassword]);
+
+    let stream = net::TcpStream::connect(proxy).unwrap();
+    let err = Client::handshake(stream, None).err().unwrap();
+    assert!(matches!(err, ClientError::NoAcceptableAuthMethodsError));
+
+    let stream = net::TcpStream::connect(proxy).unwrap();
+    let err = Client::handshake(stream, Some(&Credentials::new("randall", "hunter2"))).err().unwrap();
+    assert!(matches!(err, ClientError::WrongCredentialsError));
+}
+
+/// Accepts one client with no authentication, checks its request is `cmd`,
+/// and answers it with each of `replies` (as address, port).
+fn start_stub_server(cmd: u8, replies: Vec<(Address, u16)>) -> net::SocketAddr {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&[5, 0]).unwrap();
+        // IPv4 requests only
+        let mut req = [0; 10];
+        stream.read_exact(&mut req).unwrap();
+        assert_eq!(req[1], cmd);
+        for (addr, port) in replies {
+            let mut reply = vec![5, 0, 0];
+            reply.extend_from_slice(&encode_udp_datagram(&addr, port, &[]).unwrap()[3..]);
+            stream.write_all(&reply).unwrap();
+        }
+        // Keep the connection open until the client is done
+        let _ = stream.read(&mut buf);
+    });
+    return addr;
+}
+
+#[test]
+fn test_client_bind() {
+    let bound = (Address::V4(net::Ipv4Addr::new(192, 0, 2, 1)), 4000);
+    let peer = (Address::DomainName("peer.example".to_string()), 5000);
+    let server = start_stub_server(2, vec![bound.clone(), peer.clone()]);
+
+    let stream = net::TcpStream::connect(server).unwrap();
+    let pending = Client::handshake(stream, None)
+        .unwrap()
+        .bind(&Address::V4(net::Ipv4Addr::new(192, 0, 2, 2)), 5000)
+        .unwrap();
+    assert_eq!(pending.bound_address(), &bound);
+    let (_, accepted) = pending.accept().unwrap();
+    assert_eq!(accepted, peer);
+}
+
+#[test]
+fn test_client_udp_associate() {
+    let relay = (Address::V6(net::Ipv6Addr::LOCALHOST), 6000);
+    let server = start_stub_server(3, vec![relay.clone()]);
+
+    let stream = net::TcpStream::connect(server).unwrap();
+    let association = Client::handshake(stream, None)
+        .unwrap()
+        .udp_associate(&Address::V4(net::Ipv4Addr::UNSPECIFIED), 0)
+        .unwrap();
+    assert_eq!(association.relay_address(), &relay);
+
+    let dst = Address::DomainName("example.com".to_string());
+    let datagram = encode_udp_datagram(&dst, 53, b"query").unwrap();
+    let (addr, port, payload) = decode_udp_datagram(&datagram).unwrap();
+    assert_eq!((addr, port, payload), (dst, 53, &b"query"[..]));
+    // Fragments are rejected
+    let mut fragment = datagram.clone();
+    fragment[2] = 1;
+    assert!(decode_udp_datagram(&fragment).is_err());
+}